@@ -1,4 +1,22 @@
-#[derive(Eq, Hash, PartialEq)]
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{Local, NaiveDate};
+use rusqlite::{params, Connection};
+use crate::{database, log_error};
+
+/// How often the Fine actors are run - the scheduler should be ticked at (at least) this rate
+pub const FINE_INTERVAL: Duration = Duration::from_millis(250);
+const COARSE_INTERVAL: Duration = Duration::from_secs(1);
+
+type ActorList = Vec<Box<dyn Actor + Send>>;
+
+static ACTORS: LazyLock<Mutex<HashMap<ActionResolution, ActorList>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// The date on which the Daily actors were last run, as loaded from the database (None if they never have been)
+static LAST_DAILY_RUN: LazyLock<Mutex<Option<NaiveDate>>> = LazyLock::new(|| Mutex::new(None));
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum ActionResolution {
     Fine,   // sub-second
     Coarse, // approximately 1Hz
@@ -9,3 +27,104 @@ pub trait Actor {
     fn act(&self);
     fn is_finished(&self) -> bool;
 }
+
+/// Keeps track of when each resolution of actor is next due to be run.
+/// The actors themselves are registered via register_actor(), and may be registered at any time.
+pub struct Scheduler {
+    last_coarse_run: Instant,
+    last_daily_run: NaiveDate,
+}
+
+/// Registers an actor to be run at the given resolution, until such time as it reports that it is finished.
+/// This may safely be invoked from within an actor.
+pub fn register_actor(resolution: ActionResolution, actor: Box<dyn Actor + Send>) {
+    ACTORS.lock().unwrap().entry(resolution).or_default().push(actor);
+}
+
+// Runs all the actors registered at the given resolution, then drops those which have finished.
+// The actors are taken out of the map while they run, so that they can register further actors.
+fn run_actors(resolution: ActionResolution) {
+    let mut actors = match ACTORS.lock().unwrap().get_mut(&resolution) {
+        Some(actors) => std::mem::take(actors),
+        None => { return; },
+    };
+
+    for actor in actors.iter() {
+        if !actor.is_finished() {
+            actor.act();
+        }
+    }
+    actors.retain(|actor| !actor.is_finished());
+
+    let mut lock = ACTORS.lock().unwrap();
+    let registered = lock.entry(resolution).or_default();
+    actors.append(registered);
+    *registered = actors;
+}
+
+/// Loads the date on which the Daily actors were last run, so that a scheduler created afterwards
+/// can catch up on a run which was missed while the game was down - Used when a game starts up.
+pub fn load_last_daily_run(database: &Connection) -> Result<(), String> {
+    let statement = "SELECT MAX(runDate) FROM daily_runs;";
+    let run_date: Option<String> = database.query_row(statement, [], |row| row.get(0))
+        .map_err(|e| format!("Cannot load the last daily run:{}", e))?;
+    let run_date = match run_date {
+        Some(run_date) => Some(run_date.parse::<NaiveDate>()
+            .map_err(|e| format!("Invalid daily run date {}:{}", run_date, e))?),
+        None => None,
+    };
+    *LAST_DAILY_RUN.lock().unwrap() = run_date;
+    Ok(())
+}
+
+// Records that the Daily actors have been run on the given date
+fn record_daily_run(run_date: NaiveDate) {
+    let result = database::with_database(|database| {
+        database.execute("INSERT OR IGNORE INTO daily_runs (runDate) VALUES (?1);", params![run_date.to_string()])
+            .map_err(|e| format!("Cannot record daily run:{}", e))
+    });
+    if let Err(e) = result {
+        log_error!("{}", e);
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+impl Scheduler {
+    /// Creates a scheduler. If the Daily actors were last run before today (see load_last_daily_run()),
+    /// they are run on the first tick, to make up for the midnight which was missed; otherwise they
+    /// are first run at the next midnight. A game which has never run them starts counting from today.
+    pub fn new() -> Scheduler {
+        let today = Local::now().date_naive();
+        let last_daily_run = match *LAST_DAILY_RUN.lock().unwrap() {
+            Some(run_date) => run_date,
+            None => {
+                record_daily_run(today);
+                today
+            },
+        };
+        Scheduler { last_coarse_run: Instant::now(), last_daily_run }
+    }
+
+    /// Runs the Fine actors, along with the Coarse and Daily actors if they are due.
+    /// To be invoked every FINE_INTERVAL.
+    pub fn tick(&mut self) {
+        run_actors(ActionResolution::Fine);
+
+        if self.last_coarse_run.elapsed() >= COARSE_INTERVAL {
+            self.last_coarse_run = Instant::now();
+            run_actors(ActionResolution::Coarse);
+        }
+
+        let today = Local::now().date_naive();
+        if today != self.last_daily_run {
+            self.last_daily_run = today;
+            run_actors(ActionResolution::Daily);
+            record_daily_run(today);
+        }
+    }
+}
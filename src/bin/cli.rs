@@ -1,7 +1,12 @@
 use std::{env, thread};
 use std::string::ToString;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
+use reqwest::blocking::RequestBuilder;
 use reqwest::header::{HeaderMap, HeaderValue};
+use rustyline::error::ReadlineError;
+use rustyline::{DefaultEditor, ExternalPrinter};
 use space_trader::ansi::*;
 use space_trader::session::SessionId;
 
@@ -18,7 +23,55 @@ impl Context {
         format!("{protocol}://{}{}", self.address, path)
     }
 }
+
+// Prints straight to stdout, for use when the line editor cannot provide an external printer
+struct StdoutPrinter;
+
+impl ExternalPrinter for StdoutPrinter {
+    fn print(&mut self, msg: String) -> rustyline::Result<()> {
+        println!("{}", msg);
+        Ok(())
+    }
+}
+
+// Commands which the user may enter, along with argument synopses and help text
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("attack", "", "Attack another player's planet in this sector"),
+    ("buy", "{commodity} {quantity}", "Buy goods from the port in this sector"),
+    ("citadel", "", "Build the next citadel level on your planet in this sector"),
+    ("claim", "", "Claim the unowned planet in this sector"),
+    ("deploy", "{fighters} {mines}", "Leave fighters and lay mines to guard this sector"),
+    ("dist", "{sector}", "Find how many hops away a sector is"),
+    ("fight", "{ship} [attack|retreat]", "Attack another ship in this sector, optionally retreating if it goes badly"),
+    ("fortify", "{fighters} {shields}", "Move fighters and shields from your ship to your planet in this sector"),
+    ("help", "", "Display this text"),
+    ("inbox", "[{page}]", "List messages which have been sent to you"),
+    ("land", "{quantity}", "Land colonists on your planet in this sector"),
+    ("look", "", "Describe the sector your ship is in"),
+    ("move", "{sector} [toll|fight|retreat]", "Move your ship to an adjacent sector, saying what to do if it is guarded"),
+    ("msg", "{user} {text}", "Send a message to another user (quote user names containing spaces)"),
+    ("nearby", "[{hops}]", "List the sectors within a number of hops"),
+    ("nearest", "[buying|selling {commodity}]", "Find the nearest port, optionally one trading a commodity"),
+    ("orders", "{attack|retreat|flee}", "Set what your ship does when it is attacked"),
+    ("outfit", "{fighters} {shields}", "Buy fighters and shields (only in a galaxy's root sector)"),
+    ("path", "{sector} [safe]", "Find the shortest path to a sector, optionally avoiding guarded sectors"),
+    ("planet", "", "Scan the planet in this sector"),
+    ("port", "", "Describe the port in this sector"),
+    ("ports", "[{hops}]", "List the ports within a number of hops"),
+    ("quit", "", "Log out and leave the game"),
+    ("recruit", "{quantity}", "Take colonists aboard (only in a galaxy's root sector)"),
+    ("recall", "{quantity}", "Take fighters deployed in this sector back aboard"),
+    ("refuel", "", "Fill your fuel tank (bought in a galaxy's root sector, or refined from Fuel Ore elsewhere)"),
+    ("repair", "", "Repair your ship's hull (only in a galaxy's root sector)"),
+    ("sell", "{commodity} {quantity}", "Sell goods to the port in this sector"),
+    ("ship", "", "Describe your ship"),
+    ("who", "", "List the users who are currently playing"),
+    ("withdraw", "{commodity} {quantity}", "Load goods produced by your planet in this sector"),
+];
+
 const DEFAULT_ADDRESS: &str = "127.0.0.1:2000";
+const POLL_TIMEOUT_SECONDS: u64 = 60; // must exceed the server's poll timeout
+const POLL_RETRY_SECONDS: u64 = 5;
 
 /// cli client for space trader
 fn main() {
@@ -59,42 +112,58 @@ fn log_in(ctx: &Context) -> Option<SessionId> {
 
 fn log_out(ctx: &Context, session_id: &SessionId) {
     let mut headers = HeaderMap::new();
-    headers.insert("X-Session-Id", HeaderValue::from_str(&*session_id).unwrap());
+    headers.insert("X-Session-Id", HeaderValue::from_str(session_id).unwrap());
 
     let client = reqwest::blocking::Client::new();
-    match client.get(ctx.compose_url("/session/logout"))
+    match client.post(ctx.compose_url("/session/logout"))
         .headers(headers)
         .send() {
         Ok(_) => {
             println!("Logging out...");
         }
         Err(error) => {
-            print_error(format!("Error logging out:{}", error.to_string()).as_str());
+            print_error(format!("Error logging out:{}", error).as_str());
         }
     }
 }
 
-fn polling_thread(url: String, session_id: SessionId) {
+// Repeatedly polls the server for messages, until told to stop.
+// Output goes through the line editor's external printer, so that it does not trample
+// whatever the user is in the middle of typing.
+fn polling_thread(url: String, session_id: SessionId, stop_flag: Arc<AtomicBool>, mut printer: impl ExternalPrinter) {
     let mut headers = HeaderMap::new();
-    headers.insert("X-Session-Id", HeaderValue::from_str(&*session_id).unwrap());
+    headers.insert("X-Session-Id", HeaderValue::from_str(&session_id).unwrap());
 
-    loop {
-        let client = reqwest::blocking::Client::new();
+    let client = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(POLL_TIMEOUT_SECONDS))
+        .build()
+        .unwrap();
+    while !stop_flag.load(Ordering::SeqCst) {
         match client.get(&url).headers(headers.clone()).send() {
             Ok(response) => {
-                let body = response.text().unwrap();
-                let lines = body.lines();
-                for line in lines {
-                    // TODO change placeholders into color codes
-                    if !line.is_empty() {
-                        println!(">{}", line);
-                    }
+                let is_success = response.status().is_success();
+                let is_unauthorized = response.status() == reqwest::StatusCode::UNAUTHORIZED;
+                let body = response.text().unwrap_or_default();
+                for line in body.lines().filter(|line| !line.is_empty()) {
+                    let text = if is_success {
+                        format!("{}>{}{}", ANSI_BOLD_GREEN, line, ANSI_RESET)
+                    } else {
+                        format!("{}>{}{}", ANSI_BOLD_RED, line, ANSI_RESET)
+                    };
+                    _ = printer.print(text);
+                }
+                if is_unauthorized {
+                    // Our session has expired (or been closed) - polling again will not help
+                    break;
+                } else if !is_success {
+                    thread::sleep(Duration::from_secs(POLL_RETRY_SECONDS));
                 }
             }
             Err(error) => {
-                // TODO what should we do here?
-                print_error(&error.to_string());
-                thread::sleep(Duration::from_secs(5));
+                if !stop_flag.load(Ordering::SeqCst) {
+                    _ = printer.print(format!("{}ERROR:{}{}", ANSI_BOLD_RED, error, ANSI_RESET));
+                    thread::sleep(Duration::from_secs(POLL_RETRY_SECONDS));
+                }
             }
         }
     }
@@ -112,45 +181,254 @@ fn process(ctx: Context) {
     }
     let session_id = session_id.unwrap();
 
-    /*
-    //TODO temp code - do a quit command
-    println!("Session id = {session_id}"); // TODO remove
-    let client = reqwest::blocking::Client::new();
-    let mut headers = HeaderMap::new();
-    headers.insert("X-Session-Id", HeaderValue::from_str(&*session_id).unwrap());
-    match client.post(ctx.compose_url("/admin/quit"))
-        .headers(headers)
-        .body("")
-        .send() {
-        Ok(response) => {
-            println!("Response: {}", response.status());
-            println!("Body: {}", response.text().unwrap_or_default());
-        },
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
         Err(error) => {
-            print_error(&error.to_string());
+            print_error(format!("Cannot set up line editor:{}", error).as_str());
+            log_out(&ctx, &session_id);
             return;
         }
-    }
-    //TODO end temp code
-     */
+    };
 
     // Start polling thread
     let poll_url = ctx.compose_url("/message/poll");
     let thread_sid = session_id.clone();
-    thread::spawn(|| {
-        polling_thread(poll_url, thread_sid);        
-    });
-    // TODO
-    
+    let stop_flag = Arc::new(AtomicBool::new(false));
+    let thread_stop_flag = stop_flag.clone();
+    let poll_handle = match editor.create_external_printer() {
+        Ok(printer) => thread::spawn(move || {
+            polling_thread(poll_url, thread_sid, thread_stop_flag, printer);
+        }),
+        Err(_) => thread::spawn(move || {
+            // Input is not a terminal, so there is no line being edited to protect
+            polling_thread(poll_url, thread_sid, thread_stop_flag, StdoutPrinter);
+        }),
+    };
+
     // Loop on accepting and handling input from the user
-    thread::sleep(Duration::from_secs(30));
-    // TODO
+    println!("Enter 'help' for a list of commands");
+    loop {
+        match editor.readline("> ") {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    _ = editor.add_history_entry(line.as_str());
+                }
+                if !execute_command(&ctx, &session_id, &line) {
+                    break;
+                }
+            }
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(error) => {
+                print_error(&error.to_string());
+                break;
+            }
+        }
+    }
 
-    // Log out
+    // Log out - this causes any outstanding poll to return promptly, so the polling thread can then stop
+    stop_flag.store(true, Ordering::SeqCst);
     log_out(&ctx, &session_id);
+    _ = poll_handle.join();
+}
+
+// Interprets and executes one line of user input.
+// Returns false if the user wants to quit.
+fn execute_command(ctx: &Context, session_id: &SessionId, line: &str) -> bool {
+    let tokens = tokenize(line);
+    if tokens.is_empty() {
+        return true;
+    }
 
-    // Stop polling thread
-    // TODO
+    let args: Vec<&str> = tokens.iter().skip(1).map(|token| token.as_str()).collect();
+    let client = reqwest::blocking::Client::new();
+    let request = match tokens[0].to_lowercase().as_str() {
+        "attack" => client.post(ctx.compose_url("/planet/attack")),
+        "buy" | "sell" | "withdraw" => {
+            if args.len() < 2 {
+                print_error(format!("usage: {} {{commodity}} {{quantity}}", tokens[0]).as_str());
+                return true;
+            }
+            let commodity = args[..args.len() - 1].join(" ");
+            let quantity = args[args.len() - 1];
+            let path = match tokens[0].to_lowercase().as_str() {
+                "buy" => "/port/buy",
+                "sell" => "/port/sell",
+                _ => "/planet/withdraw",
+            };
+            client.post(ctx.compose_url(path)).form(&[("commodity", commodity.as_str()), ("quantity", quantity)])
+        },
+        "citadel" => client.post(ctx.compose_url("/planet/citadel")),
+        "claim" => client.post(ctx.compose_url("/planet/claim")),
+        "fight" => {
+            if args.is_empty() || args.len() > 2 {
+                print_error("usage: fight {ship} [attack|retreat]");
+                return true;
+            }
+            let order = args.get(1).copied().unwrap_or("attack");
+            client.post(ctx.compose_url("/ship/attack")).form(&[("ship", args[0]), ("order", order)])
+        },
+        "fortify" | "outfit" => {
+            if args.len() != 2 {
+                print_error(format!("usage: {} {{fighters}} {{shields}}", tokens[0]).as_str());
+                return true;
+            }
+            let path = if tokens[0].eq_ignore_ascii_case("fortify") { "/planet/fortify" } else { "/ship/outfit" };
+            client.post(ctx.compose_url(path)).form(&[("fighters", args[0]), ("shields", args[1])])
+        },
+        "help" | "?" => {
+            show_help();
+            return true;
+        },
+        "inbox" => {
+            let page = args.first().copied().unwrap_or("1");
+            client.get(ctx.compose_url("/message/inbox")).query(&[("page", page)])
+        },
+        "land" | "recruit" => {
+            if args.len() != 1 {
+                print_error(format!("usage: {} {{quantity}}", tokens[0]).as_str());
+                return true;
+            }
+            let path = if tokens[0].eq_ignore_ascii_case("land") { "/planet/land" } else { "/ship/recruit" };
+            client.post(ctx.compose_url(path)).form(&[("quantity", args[0])])
+        },
+        "look" => client.get(ctx.compose_url("/sector")),
+        "deploy" => {
+            if args.len() != 2 {
+                print_error("usage: deploy {fighters} {mines}");
+                return true;
+            }
+            client.post(ctx.compose_url("/sector/deploy")).form(&[("fighters", args[0]), ("mines", args[1])])
+        },
+        "dist" => {
+            if args.len() != 1 {
+                print_error("usage: dist {sector}");
+                return true;
+            }
+            client.get(ctx.compose_url("/sector/distance")).query(&[("sector", args[0])])
+        },
+        "move" | "path" => {
+            if args.is_empty() || args.len() > 2 {
+                let option = if tokens[0].eq_ignore_ascii_case("move") { "[toll|fight|retreat]" } else { "[safe]" };
+                print_error(format!("usage: {} {{sector}} {}", tokens[0], option).as_str());
+                return true;
+            }
+            if tokens[0].eq_ignore_ascii_case("move") {
+                match args.get(1) {
+                    Some(encounter) => client.post(ctx.compose_url("/ship/move")).form(&[("sector", args[0]), ("encounter", encounter)]),
+                    None => client.post(ctx.compose_url("/ship/move")).form(&[("sector", args[0])]),
+                }
+            } else {
+                let is_safe = args.get(1).is_some_and(|option| option.eq_ignore_ascii_case("safe"));
+                client.get(ctx.compose_url("/sector/path")).query(&[("sector", args[0]), ("safe", if is_safe { "true" } else { "false" })])
+            }
+        },
+        "msg" => {
+            if args.len() < 2 {
+                print_error("usage: msg {user} {text}");
+                return true;
+            }
+            let text = args[1..].join(" ");
+            client.post(ctx.compose_url("/message/send")).form(&[("to", args[0]), ("text", text.as_str())])
+        },
+        "nearby" | "ports" => {
+            let path = if tokens[0].eq_ignore_ascii_case("nearby") { "/sector/nearby" } else { "/port/nearby" };
+            match args.first() {
+                Some(hops) => client.get(ctx.compose_url(path)).query(&[("hops", hops)]),
+                None => client.get(ctx.compose_url(path)),
+            }
+        },
+        "nearest" => {
+            match args.first().map(|role| role.to_lowercase()) {
+                None => client.get(ctx.compose_url("/port/nearest")),
+                Some(role) if (role == "buying" || role == "selling") && args.len() >= 2 => {
+                    let commodity = args[1..].join(" ");
+                    client.get(ctx.compose_url("/port/nearest")).query(&[(role.as_str(), commodity.as_str())])
+                },
+                Some(_) => {
+                    print_error("usage: nearest [buying|selling {commodity}]");
+                    return true;
+                },
+            }
+        },
+        "orders" => {
+            if args.len() != 1 {
+                print_error("usage: orders {attack|retreat|flee}");
+                return true;
+            }
+            client.post(ctx.compose_url("/ship/orders")).form(&[("order", args[0])])
+        },
+        "planet" => client.get(ctx.compose_url("/planet")),
+        "port" => client.get(ctx.compose_url("/port")),
+        "quit" | "exit" => return false,
+        "recall" => {
+            if args.len() != 1 {
+                print_error("usage: recall {quantity}");
+                return true;
+            }
+            client.post(ctx.compose_url("/sector/recall")).form(&[("quantity", args[0])])
+        },
+        "refuel" => client.post(ctx.compose_url("/ship/refuel")),
+        "repair" => client.post(ctx.compose_url("/ship/repair")),
+        "ship" => client.get(ctx.compose_url("/ship")),
+        "who" => client.get(ctx.compose_url("/user/who")),
+        _ => {
+            print_error(format!("Unknown command '{}' - enter 'help' for a list of commands", tokens[0]).as_str());
+            return true;
+        },
+    };
+
+    send_request(session_id, request);
+    true
+}
+
+// Sends a request to the server and displays the result
+fn send_request(session_id: &SessionId, request: RequestBuilder) {
+    match request.header("X-Session-Id", session_id.as_str()).send() {
+        Ok(response) => {
+            let is_success = response.status().is_success();
+            let body = response.text().unwrap_or_default();
+            if is_success {
+                println!("{}", body.trim_end());
+            } else {
+                print_error(body.trim_end());
+            }
+        }
+        Err(error) => print_error(&error.to_string()),
+    }
+}
+
+fn show_help() {
+    println!("{}Commands:{}", ANSI_BOLD_YELLOW, ANSI_RESET);
+    for (name, arguments, help) in COMMANDS {
+        println!("  {:<30} {}", format!("{} {}", name, arguments), help);
+    }
+}
+
+// Splits a line of input into whitespace-delimited tokens.
+// Double quotes may be used to include whitespace in a token.
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_token = false;
+    for ch in line.chars() {
+        if ch == '"' {
+            in_quotes = !in_quotes;
+            has_token = true;
+        } else if ch.is_whitespace() && !in_quotes {
+            if has_token {
+                tokens.push(std::mem::take(&mut current));
+                has_token = false;
+            }
+        } else {
+            current.push(ch);
+            has_token = true;
+        }
+    }
+    if has_token {
+        tokens.push(current);
+    }
+    tokens
 }
 
 fn setup() -> Option<Context> {
@@ -1,86 +1,169 @@
 use rusqlite::{Connection, OpenFlags};
-use space_trader::galaxy;
+use std::env;
+use space_trader::{config, database, galaxy, logging, planet, port, sector};
+use space_trader::config::Config;
+use space_trader::galaxy::{Galaxy, GalaxyKind};
 use space_trader::user;
 
-pub const DB_BUILD_STATEMENTS: &'static [&'static str] = &[
-    "DROP TABLE IF EXISTS galaxies_to_sectors;",
-    "DROP TABLE IF EXISTS sector_links;",
-    "DROP TABLE IF EXISTS sectors_to_planets;",
-    "DROP TABLE IF EXISTS sectors_to_ports;",
-    "DROP TABLE IF EXISTS sectors;",
-    "DROP TABLE IF EXISTS planets;",
-    "DROP TABLE IF EXISTS ports;",
-    "DROP TABLE IF EXISTS galaxies;",
-    "DROP TABLE IF EXISTS messages;",
-    "DROP TABLE IF EXISTS users;",
-
-    "CREATE TABLE users ( \
-                userId INTEGER PRIMARY KEY NOT NULL, \
-                userName TEXT NOT NULL UNIQUE, \
-                password TEXT, \
-                gameName TEXT, \
-                lastLoginTimeStamp INTEGER, \
-                isDisabled INTEGER NOT NULL,\
-                requestsPerDay INTEGER, \
-                requestsRemaining INTEGER);",
-
-    "CREATE TABLE messages ( \
-                messageId INTEGER PRIMARY KEY NOT NULL, \
-                fromUserId INTEGER REFERENCES users(userId), \
-                toUserId INTEGER NOT NULL REFERENCES users(userId), \
-                timeStamp INTEGER NOT NULL, \
-                text STRING);",
-
-    "CREATE TABLE galaxies ( \
-                galaxyId INTEGER PRIMARY KEY NOT NULL, \
-                galaxyName TEXT NOT NULL);",
-
-    "CREATE TABLE sectors ( \
-                sectorId INTEGER PRIMARY KEY NOT NULL);",
-
-    "CREATE TABLE planets ( \
-                planetId INTEGER NOT NULL,\
-                planetName STRING);",
-
-    "CREATE TABLE ports ( \
-                portId INTEGER NOT NULL,\
-                portName STRING);",
-
-    "CREATE TABLE sector_links ( \
-                fromSectorId INTEGER REFERENCES sectors(sectorId), \
-                toSectorId INTEGER REFERENCES sectors(sectorId), \
-                PRIMARY KEY (fromSectorId, toSectorId));",
-
-    "CREATE TABLE sectors_to_planets ( \
-                sectorId INTEGER REFERENCES sectors(sectorId), \
-                planetId INTEGER REFERENCES planets(planetId), \
-                PRIMARY KEY (sectorId, planetId));",
-
-    "CREATE TABLE sectors_to_ports ( \
-                sectorId INTEGER REFERENCES sectors(sectorId), \
-                portId INTEGER REFERENCES ports(portId), \
-                PRIMARY KEY (sectorId, PortId));",
-
-    "CREATE TABLE galaxies_to_sectors ( \
-                galaxyId INTEGER REFERENCES galaxy(galaxyId), \
-                sectorId INTEGER REFERENCES sectors(sectorId), \
-                PRIMARY KEY (galaxyId, SectorId));",
-];
+const DEFAULT_GALAXY_NAME: &str = "Kronos";
+const DEFAULT_SECTOR_COUNT: usize = 500;
+const DEFAULT_BRANCH_COUNT: usize = 3;
+
+#[derive(PartialEq)]
+enum Command {
+    Create, // wipe the database, then build it from scratch
+    Add,    // add galaxies to an existing database
+    Report, // validate galaxies in an existing database, and report statistics
+}
+
+// Describes a galaxy to be generated
+struct GalaxySpec {
+    kind: GalaxyKind,
+    name: String,
+    is_named: bool, // false if the name is the default
+    sector_count: usize,
+    branch_count: usize,
+    sectors_per_port: usize,
+    sectors_per_planet: usize,
+    seed: Option<u64>,
+}
 
 fn main() {
     println!("Space Trader - initializer");
 
-    match build_database() {
+    let args: Vec<String> = env::args().collect();
+    let (command, config_path, specs) = match parse_arguments(&args) {
+        Ok(result) => result,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            usage(&args[0]);
+            return;
+        }
+    };
+
+    let result = match command {
+        Command::Create => build_database(config_path.as_deref(), &specs),
+        Command::Add => add_galaxies(config_path.as_deref(), &specs),
+        Command::Report => { return report_galaxies(config_path.as_deref(), &specs); },
+    };
+
+    match result {
         Ok(_) => println!("Successfully initialized database"),
         Err(msg) => panic!("Failed to initialize database:{msg}"),
     }
 }
 
-fn build_database() -> Result<(), String> {
-    let database = match || -> rusqlite::Result<Connection> {
-        let database = Connection::open_with_flags("space-trader.db",
+// Parses the command line. Each -n switch begins a new galaxy, and the galaxy options which
+// follow it apply to that galaxy; galaxy options preceding the first -n apply to the first galaxy.
+fn parse_arguments(args: &[String]) -> Result<(Command, Option<String>, Vec<GalaxySpec>), String> {
+    let mut ax: usize = 1;
+    let mut config_path: Option<String> = None;
+    if args.get(ax).map(|arg| arg.to_lowercase()) == Some("-c".to_string()) {
+        config_path = Some(args.get(ax + 1).ok_or("-c switch has no value")?.clone());
+        ax += 2;
+    }
+
+    let command = match args.get(ax).map(|arg| arg.to_lowercase()) {
+        Some(command) if command == "create" => Command::Create,
+        Some(command) if command == "add" => Command::Add,
+        Some(command) if command == "report" => Command::Report,
+        Some(command) => { return Err(format!("Unknown command {}", command)); },
+        None => { return Err("A command is required".to_string()); },
+    };
+    ax += 1;
+
+    let mut specs: Vec<GalaxySpec> = Vec::new();
+    let new_spec = || GalaxySpec {
+        kind: GalaxyKind::Conventional,
+        name: DEFAULT_GALAXY_NAME.to_string(),
+        is_named: false,
+        sector_count: DEFAULT_SECTOR_COUNT,
+        branch_count: DEFAULT_BRANCH_COUNT,
+        sectors_per_port: 0, // filled in from the configuration later
+        sectors_per_planet: 0, // likewise
+        seed: None,
+    };
+    specs.push(new_spec());
+
+    while ax < args.len() {
+        let switch = args[ax].to_lowercase();
+        let value = args.get(ax + 1).ok_or(format!("{} switch has no value", switch))?;
+        ax += 2;
+
+        if switch == "-n" && specs.last().unwrap().is_named {
+            specs.push(new_spec());
+        }
+        let spec = specs.last_mut().unwrap();
+        match switch.as_str() {
+            "-b" => spec.branch_count = parse_number(&switch, value)?,
+            "-n" => {
+                spec.name = value.clone();
+                spec.is_named = true;
+            },
+            "-p" => spec.sectors_per_port = parse_number(&switch, value)?,
+            "-r" => spec.seed = Some(parse_number(&switch, value)?),
+            "-s" => spec.sector_count = parse_number(&switch, value)?,
+            "-t" => {
+                spec.kind = match value.to_lowercase().as_str() {
+                    "conventional" => GalaxyKind::Conventional,
+                    "tree" => GalaxyKind::Tree,
+                    _ => { return Err(format!("Unknown galaxy type {}", value)); },
+                };
+            },
+            "-w" => spec.sectors_per_planet = parse_number(&switch, value)?,
+            _ => { return Err(format!("Invalid switch {}", switch)); },
+        }
+    }
+
+    if command == Command::Add && !specs.iter().all(|spec| spec.is_named) {
+        return Err("A name (-n) is required for each galaxy to be added".to_string());
+    }
+
+    for spec in specs.iter() {
+        if spec.sector_count < 2 {
+            return Err(format!("Galaxy {} must have at least 2 sectors", spec.name));
+        }
+    }
+
+    Ok((command, config_path, specs))
+}
+
+fn parse_number<T: std::str::FromStr>(switch: &str, value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("Invalid value '{}' for {} switch", value, switch))
+}
+
+fn usage(init_name: &str) {
+    eprintln!("Usage: {init_name} [-c {{config_path}}] {{command}} {{galaxy options}}");
+    eprintln!("commands:");
+    eprintln!("  create - deletes everything in the database, then creates the tables, galaxies, and initial users");
+    eprintln!("  add    - adds galaxies to an existing database");
+    eprintln!("  report - validates galaxies in an existing database (all, or those named by -n), and reports statistics");
+    eprintln!("galaxy options (each -n begins a new galaxy):");
+    eprintln!("  [-n {{name}}] - galaxy name (defaults to {} for create)", DEFAULT_GALAXY_NAME);
+    eprintln!("  [-t conventional|tree] - galaxy type (defaults to conventional)");
+    eprintln!("  [-s {{count}}] - number of sectors (defaults to {})", DEFAULT_SECTOR_COUNT);
+    eprintln!("  [-b {{count}}] - branches per sector, for tree galaxies (defaults to {})", DEFAULT_BRANCH_COUNT);
+    eprintln!("  [-p {{count}}] - sectors per port (defaults to the configured value)");
+    eprintln!("  [-w {{count}}] - sectors per planet (world) (defaults to the configured value)");
+    eprintln!("  [-r {{seed}}] - random number seed, for a reproducible galaxy");
+}
+
+// Loads and validates the configuration, and makes it available to the library
+fn configure(config_path: Option<&str>) -> Result<Config, String> {
+    let config = config::load_config(config_path)?;
+    config.validate()?;
+    logging::set_log_level(config.get_log_level());
+    config::set_config(config.clone());
+    Ok(config)
+}
+
+fn build_database(config_path: Option<&str>, specs: &[GalaxySpec]) -> Result<(), String> {
+    // The configuration determines the database path, the admin password, and how galaxies are generated
+    let config = configure(config_path)?;
+    let mut database = match || -> rusqlite::Result<Connection> {
+        let database = Connection::open_with_flags(&config.server.database_path,
                                                    OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_READ_WRITE)?;
-        for statement in DB_BUILD_STATEMENTS {
+        for statement in database::BUILD_STATEMENTS {
             database.execute(statement, ())?;
         }
 
@@ -90,9 +173,89 @@ fn build_database() -> Result<(), String> {
         Err(msg) => return Err(format!("Failed to build database:{msg}")),
     };
 
-    _ = user::create_admin_user(&database)?;
-    _ = user::create_normal_user(&database, "Neo".to_string(), "anderson".to_string(), "The One".to_string());
-    _ = galaxy::create_conventional_galaxy(&database, "Kronos".to_string(), 500)?;
+    // The galaxies must exist before normal users are created, as their ships are placed in the first one.
+    let transaction = database.transaction().map_err(|e| e.to_string())?;
+    create_galaxies(&transaction, &config, specs)?;
+    _ = user::create_admin_user(&transaction)?;
+    _ = user::create_normal_user(&transaction, "Neo".to_string(), "anderson".to_string(), "The One".to_string());
+    transaction.commit().map_err(|e| e.to_string())
+}
+
+// Loads an existing database and reports on its galaxies - all of them, or only those named
+fn report_galaxies(config_path: Option<&str>, specs: &[GalaxySpec]) {
+    if let Err(msg) = load_galaxies(config_path) {
+        eprintln!("Failed to load database:{msg}");
+        return;
+    }
+
+    let galaxies: Vec<Galaxy> = if specs.iter().any(|spec| spec.is_named) {
+        specs.iter().filter_map(|spec| {
+            let found = galaxy::find_galaxy_by_name(&spec.name);
+            if found.is_none() {
+                eprintln!("No such galaxy {}", spec.name);
+            }
+            found
+        }).collect()
+    } else {
+        galaxy::get_galaxy_ids().into_iter().filter_map(galaxy::get_galaxy).collect()
+    };
+
+    for galaxy in galaxies {
+        for line in galaxy.get_report().get_description() {
+            println!("{}", line);
+        }
+    }
+}
+
+// Opens the configured database, and loads everything needed to work with its galaxies
+fn load_galaxies(config_path: Option<&str>) -> Result<Connection, String> {
+    let config = configure(config_path)?;
+    let database = Connection::open_with_flags(&config.server.database_path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .map_err(|e| format!("Cannot open database {}:{}", config.server.database_path, e))?;
 
+    database::upgrade_schema(&database)?;
+    planet::load_planets(&database)?;
+    port::load_ports(&database)?;
+    sector::load_sectors(&database)?;
+    galaxy::load_galaxies(&database)?;
+    Ok(database)
+}
+
+// Adds galaxies to an existing database. We load the existing ports, planets, sectors, and galaxies
+// so that new ids and port names do not collide with those already in use.
+fn add_galaxies(config_path: Option<&str>, specs: &[GalaxySpec]) -> Result<(), String> {
+    let mut database = load_galaxies(config_path)?;
+    let config = config::get_config();
+    for spec in specs {
+        if galaxy::find_galaxy_by_name(&spec.name).is_some() {
+            return Err(format!("Galaxy {} already exists", spec.name));
+        }
+    }
+
+    let transaction = database.transaction().map_err(|e| e.to_string())?;
+    create_galaxies(&transaction, &config, specs)?;
+    transaction.commit().map_err(|e| e.to_string())
+}
+
+fn create_galaxies(database: &Connection, config: &Config, specs: &[GalaxySpec]) -> Result<(), String> {
+    for spec in specs {
+        let sectors_per_port =
+            if spec.sectors_per_port == 0 { config.galaxy.sectors_per_port } else { spec.sectors_per_port };
+        let sectors_per_planet =
+            if spec.sectors_per_planet == 0 { config.galaxy.sectors_per_planet } else { spec.sectors_per_planet };
+        let galaxy_id = match spec.kind {
+            GalaxyKind::Conventional =>
+                galaxy::create_conventional_galaxy(database, spec.name.clone(), spec.sector_count,
+                                                   sectors_per_port, sectors_per_planet, spec.seed)?,
+            GalaxyKind::Tree =>
+                galaxy::create_tree_galaxy(database, spec.name.clone(), spec.branch_count, spec.sector_count,
+                                           sectors_per_port, sectors_per_planet, spec.seed)?,
+        };
+        let galaxy = galaxy::get_galaxy(galaxy_id).unwrap();
+        println!("Created galaxy {}:{} (seed {})", galaxy_id, spec.name, galaxy.get_seed().unwrap_or_default());
+        for violation in galaxy.validate() {
+            println!("  Warning: {}", violation.get_description());
+        }
+    }
     Ok(())
 }
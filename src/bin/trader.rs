@@ -1,19 +1,87 @@
-use std::{time::Duration};
+use std::env;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 use crossbeam_channel::{select, tick, Receiver};
 use rusqlite::{Connection, OpenFlags};
 
-use space_trader::{galaxy, message, planet, port, sector, server, user};
+use space_trader::{action, config, database, deployment, galaxy, logging, message, planet, port, sector, server, session, ship, user};
+use space_trader::action::{ActionResolution, Scheduler};
+use space_trader::config::Config;
 
 fn main() {
     println!("Space Trader");
 
-    match setup() {
+    let config = match configure() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            usage(&env::args().next().unwrap_or_default());
+            return;
+        }
+    };
+
+    match setup(&config) {
         Ok(_) => process(),
-        Err(err) => panic!("Failed to open or load database: {}", err),
+        Err(err) => panic!("Failed to start: {}", err),
     }
 }
 
+// Loads the configuration file, applies any overrides from the command line, then validates the result.
+fn configure() -> Result<Config, String> {
+    let args: Vec<String> = env::args().collect();
+
+    // find the configuration file first, so that the other switches can override it regardless of order
+    let mut config_path: Option<&str> = None;
+    let mut ax: usize = 1;
+    while ax < args.len() {
+        if args[ax].to_lowercase() == "-c" {
+            config_path = args.get(ax + 1).map(|path| path.as_str());
+        }
+        ax += 1;
+    }
+
+    let mut config = config::load_config(config_path)?;
+    let mut ax: usize = 1;
+    while ax < args.len() {
+        let switch = args[ax].to_lowercase();
+        let value = match args.get(ax + 1) {
+            Some(value) => value.clone(),
+            None => { return Err(format!("{} switch has no value", switch)); },
+        };
+        ax += 2;
+
+        match switch.as_str() {
+            "-a" => config.server.bind_address = value,
+            "-c" => (),
+            "-d" => config.server.database_path = value,
+            "-l" => config.server.log_level = value,
+            "-q" => {
+                config.users.requests_per_day = value.parse::<i32>()
+                    .map_err(|_| format!("Invalid requests per day '{}'", value))?;
+            },
+            "-t" => {
+                config.sessions.idle_timeout_seconds = value.parse::<u64>()
+                    .map_err(|_| format!("Invalid session timeout '{}'", value))?;
+            },
+            _ => { return Err(format!("Invalid switch {}", switch)); },
+        }
+    }
+
+    config.validate()?;
+    Ok(config)
+}
+
+fn usage(trader_name: &str) {
+    eprintln!("Usage: {trader_name} {{options}}");
+    eprintln!("options:");
+    eprintln!("  [-c {{path}}] - configuration file (defaults to {} if it exists)", config::DEFAULT_CONFIG_PATH);
+    eprintln!("  [-a {{ip_address}}:{{port}}] - address on which to listen");
+    eprintln!("  [-d {{path}}] - database file");
+    eprintln!("  [-l {{level}}] - log level: error, warn, info, or debug");
+    eprintln!("  [-q {{count}}] - requests per day for new users");
+    eprintln!("  [-t {{seconds}}] - session idle timeout");
+}
+
 fn ctrl_channel() -> Result<Receiver<()>, ctrlc::Error> {
     let (sender, receiver) = crossbeam_channel::bounded(100);
     ctrlc::set_handler(move || {
@@ -29,13 +97,16 @@ fn process() {
         Err(_) => { panic!("Could not set up channel for process loop.") },
     };
 
-    let ticks = tick(Duration::from_secs(1));
+    let mut scheduler = Scheduler::new();
+    let ticks = tick(action::FINE_INTERVAL);
     loop {
         select! {
-        recv(ticks) -> _ =>
+        recv(ticks) -> _ => {
             if !server::IS_ACTIVE.load(Ordering::SeqCst) {
                 break;
-            },
+            }
+            scheduler.tick();
+        },
         recv(chan) -> _ => break,
         }
     }
@@ -46,12 +117,21 @@ fn process() {
     println!("\nDone");
 }
 
-fn setup() -> Result<(), String> {
-    let database = match Connection::open_with_flags("space-trader.db", OpenFlags::SQLITE_OPEN_READ_WRITE) {
+fn setup(config: &Config) -> Result<(), String> {
+    logging::set_log_level(config.get_log_level());
+    session::set_session_limits(Duration::from_secs(config.sessions.idle_timeout_seconds),
+                                config.sessions.max_lifetime_seconds.map(Duration::from_secs));
+    config::set_config(config.clone());
+
+    let database_path = &config.server.database_path;
+    let database = match Connection::open_with_flags(database_path, OpenFlags::SQLITE_OPEN_READ_WRITE) {
         Ok(database) => database,
-        Err(err) => return Err(err.to_string()),
+        Err(err) => return Err(format!("Cannot open database {}:{}", database_path, err)),
     };
 
+    // Databases built by older versions of init may be missing columns which the loaders expect
+    database::upgrade_schema(&database)?;
+
     // Order might matter here, so don't change it.
     user::load_users(&database)?;
     message::load_messages(&database)?;
@@ -59,7 +139,14 @@ fn setup() -> Result<(), String> {
     port::load_ports(&database)?;
     sector::load_sectors(&database)?;
     galaxy::load_galaxies(&database)?;
+    ship::load_ships(&database)?;
+    deployment::load_deployments(&database)?;
+    action::load_last_daily_run(&database)?;
+
+    database::set_database(database);
+    action::register_actor(ActionResolution::Daily, Box::new(user::RequestResetActor));
+    action::register_actor(ActionResolution::Daily, Box::new(planet::PlanetProductionActor));
+    action::register_actor(ActionResolution::Coarse, Box::new(port::ProductionActor::default()));
+    server::start(&config.server.bind_address)
 
-    server::start();
-    Ok(())
 }
@@ -0,0 +1,341 @@
+use rand::Rng;
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use crate::combat::{BattleResult, BattleSide, Combatant};
+use crate::commodity::Commodity;
+use crate::planet::Planet;
+use crate::ship::Ship;
+use crate::user::UserId;
+use crate::{combat, log_warn, message, planet, sector, ship, user};
+
+const COLONY_REQUEST_COST: i32 = 1;
+const COLONIST_PRICE: i64 = 2; // credits per colonist recruited
+
+/// The outcome of an attack on a planet
+pub struct AssaultReport {
+    pub planet: Planet,
+    pub ship: Ship,
+    pub battle: BattleResult,
+    pub is_captured: bool,
+}
+
+/// Finds the planet in the sector where the user's ship is located, so that it can be examined
+pub fn scan_planet(user_id: UserId) -> Result<Planet, String> {
+    let ship = get_ship(user_id)?;
+    get_planet_in_sector(&ship)
+}
+
+/// Claims the unowned planet in the sector where the user's ship is located.
+/// Once claimed, the user may land colonists on the planet, and withdraw the goods they produce.
+///
+/// # Returns
+/// The planet, as claimed
+pub fn claim_planet(database: &mut Connection, user_id: UserId) -> Result<Planet, String> {
+    let ship = get_ship(user_id)?;
+    let mut planet = get_planet_in_sector(&ship)?;
+    match planet.owner_user_id {
+        Some(owner_user_id) if owner_user_id == user_id => { return Err(format!("You already own {}", planet.planet_name)); },
+        Some(_) => { return Err(format!("{} belongs to {}", planet.planet_name, planet.get_owner_name().unwrap_or_default())); },
+        None => (),
+    }
+
+    planet.owner_user_id = Some(user_id);
+    let transaction = database.transaction().map_err(|e| format!("Cannot claim planet:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, COLONY_REQUEST_COST)?;
+    planet::update_planet(&transaction, &planet)?;
+    transaction.commit().map_err(|e| format!("Cannot claim planet:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    planet::publish_planet(planet.clone());
+    Ok(planet)
+}
+
+/// Takes colonists aboard the user's ship, for COLONIST_PRICE credits each.
+/// Colonists are only to be found in the root sector of each galaxy, and take up ship::COLONISTS_PER_HOLD per hold.
+///
+/// # Returns
+/// The ship, with the colonists aboard
+pub fn recruit_colonists(database: &mut Connection, user_id: UserId, quantity: i32) -> Result<Ship, String> {
+    if quantity <= 0 {
+        return Err("Quantity must be greater than zero".to_string());
+    }
+
+    let mut ship = get_ship(user_id)?;
+    let root_sector_id = ship.get_root_sector_id()?;
+    if ship.sector_id != root_sector_id {
+        return Err(format!("Colonists may only be recruited in sector {}", root_sector_id));
+    }
+
+    if quantity > ship.get_colonist_room() {
+        return Err(format!("You only have room for {} more colonists", ship.get_colonist_room()));
+    }
+
+    let total_price = quantity as i64 * COLONIST_PRICE;
+    if total_price > ship.credits {
+        return Err(format!("{} colonists cost {} credits, but you only have {}", quantity, total_price, ship.credits));
+    }
+
+    ship.credits -= total_price;
+    ship.colonists += quantity;
+    let transaction = database.transaction().map_err(|e| format!("Cannot recruit colonists:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, COLONY_REQUEST_COST)?;
+    ship::update_ship(&transaction, &ship)?;
+    transaction.commit().map_err(|e| format!("Cannot recruit colonists:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    ship::publish_ship(ship.clone());
+    Ok(ship)
+}
+
+/// Lands colonists from the user's ship on the planet in the same sector, which the user must own.
+/// The planet cannot take more colonists than it can support.
+///
+/// # Returns
+/// The planet, with its increased population
+pub fn land_colonists(database: &mut Connection, user_id: UserId, quantity: i32) -> Result<Planet, String> {
+    if quantity <= 0 {
+        return Err("Quantity must be greater than zero".to_string());
+    }
+
+    let mut ship = get_ship(user_id)?;
+    let mut planet = get_owned_planet(&ship, user_id)?;
+    if quantity > ship.colonists {
+        return Err(format!("You only have {} colonists aboard", ship.colonists));
+    }
+    let room = planet.max_population - planet.population;
+    if quantity > room {
+        return Err(format!("{} can only support {} more colonists", planet.planet_name, room));
+    }
+
+    ship.colonists -= quantity;
+    planet.population += quantity;
+    let transaction = database.transaction().map_err(|e| format!("Cannot land colonists:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, COLONY_REQUEST_COST)?;
+    ship::update_ship(&transaction, &ship)?;
+    planet::update_planet(&transaction, &planet)?;
+    transaction.commit().map_err(|e| format!("Cannot land colonists:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    ship::publish_ship(ship.clone());
+    planet::publish_planet(planet.clone());
+    Ok(planet)
+}
+
+/// Moves goods from the stockpile of the planet in the user's sector, which the user must own,
+/// into the holds of the user's ship.
+///
+/// # Returns
+/// The ship, with the goods aboard
+pub fn withdraw_goods(database: &mut Connection, user_id: UserId, commodity: Commodity, quantity: i32) -> Result<Ship, String> {
+    if quantity <= 0 {
+        return Err("Quantity must be greater than zero".to_string());
+    }
+
+    let mut ship = get_ship(user_id)?;
+    let mut planet = get_owned_planet(&ship, user_id)?;
+    let resource = match planet.get_resource_mut(commodity) {
+        Some(resource) => resource,
+        None => { return Err(format!("{} has no {}", planet.planet_name, commodity.name())); },
+    };
+    if quantity > resource.stockpile {
+        return Err(format!("There are only {} units of {} in the stockpile", resource.stockpile, commodity.name()));
+    }
+    if quantity > ship.get_free_holds() {
+        return Err(format!("You only have {} free holds", ship.get_free_holds()));
+    }
+
+    resource.stockpile -= quantity;
+    ship.holds.insert(commodity, ship.get_cargo(commodity) + quantity);
+    let transaction = database.transaction().map_err(|e| format!("Cannot withdraw goods:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, COLONY_REQUEST_COST)?;
+    ship::update_ship(&transaction, &ship)?;
+    planet::update_planet(&transaction, &planet)?;
+    transaction.commit().map_err(|e| format!("Cannot withdraw goods:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    ship::publish_ship(ship.clone());
+    planet::publish_planet(planet);
+    Ok(ship)
+}
+
+/// Moves fighters and shields from the user's ship to the planet in the same sector, which the user must own,
+/// to defend it against attack.
+///
+/// # Returns
+/// The planet, with its strengthened defenses
+pub fn fortify_planet(database: &mut Connection, user_id: UserId, fighters: i32, shields: i32) -> Result<Planet, String> {
+    if fighters < 0 || shields < 0 || fighters + shields == 0 {
+        return Err("Nothing to transfer".to_string());
+    }
+
+    let mut ship = get_ship(user_id)?;
+    let mut planet = get_owned_planet(&ship, user_id)?;
+    if fighters > ship.fighters {
+        return Err(format!("You only have {} fighters aboard", ship.fighters));
+    }
+    if shields > ship.shields {
+        return Err(format!("You only have {} shields aboard", ship.shields));
+    }
+
+    ship.fighters -= fighters;
+    ship.shields -= shields;
+    planet.fighters += fighters;
+    planet.shields += shields;
+    let transaction = database.transaction().map_err(|e| format!("Cannot fortify planet:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, COLONY_REQUEST_COST)?;
+    ship::update_ship(&transaction, &ship)?;
+    planet::update_planet(&transaction, &planet)?;
+    transaction.commit().map_err(|e| format!("Cannot fortify planet:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    ship::publish_ship(ship.clone());
+    planet::publish_planet(planet.clone());
+    Ok(planet)
+}
+
+/// Builds the next level of the citadel on the planet in the user's sector, which the user must own,
+/// paying for it from the ship's credits. Each level strengthens the planet's garrison.
+///
+/// # Returns
+/// The planet, with its upgraded citadel
+pub fn upgrade_citadel(database: &mut Connection, user_id: UserId) -> Result<Planet, String> {
+    let mut ship = get_ship(user_id)?;
+    let mut planet = get_owned_planet(&ship, user_id)?;
+    let price = match planet.get_citadel_upgrade_price() {
+        Some(price) => price,
+        None => { return Err(format!("The citadel on {} is already complete", planet.planet_name)); },
+    };
+    if price > ship.credits {
+        return Err(format!("Citadel level {} costs {} credits, but you only have {}", planet.citadel_level + 1, price, ship.credits));
+    }
+
+    ship.credits -= price;
+    planet.citadel_level += 1;
+    let transaction = database.transaction().map_err(|e| format!("Cannot upgrade citadel:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, COLONY_REQUEST_COST)?;
+    ship::update_ship(&transaction, &ship)?;
+    planet::update_planet(&transaction, &planet)?;
+    transaction.commit().map_err(|e| format!("Cannot upgrade citadel:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    ship::publish_ship(ship.clone());
+    planet::publish_planet(planet.clone());
+    Ok(planet)
+}
+
+/// Attacks the planet in the sector where the user's ship is located, which must belong to another player.
+/// The ship's fighters and shields fight the planet's garrison and shields (see combat::resolve_battle);
+/// if the planet's defenses are destroyed, the user captures the planet.
+/// Losses on both sides are persisted, and both players are sent a message describing the outcome.
+///
+/// # Arguments
+/// * `database` a connected database
+/// * `user_id` the attacking user
+/// * `rng` random number generator, which determines the course of the battle
+pub fn attack_planet(database: &mut Connection, user_id: UserId, rng: &mut impl Rng) -> Result<AssaultReport, String> {
+    let mut ship = get_ship(user_id)?;
+    let mut planet = get_planet_in_sector(&ship)?;
+    let defender_user_id = match planet.owner_user_id {
+        Some(owner_user_id) if owner_user_id == user_id => { return Err(format!("You already own {}", planet.planet_name)); },
+        Some(owner_user_id) => owner_user_id,
+        None => { return Err(format!("{} is unclaimed - there is no need to attack it", planet.planet_name)); },
+    };
+    if ship.fighters == 0 {
+        return Err("You have no fighters with which to attack".to_string());
+    }
+
+    let battle = combat::resolve_battle(Combatant::from_ship(&ship), Combatant::from_planet(&planet), rng);
+    let is_captured = battle.winner == BattleSide::Attacker;
+
+    ship.fighters = battle.attacker.fighters;
+    ship.shields = battle.attacker.shields;
+    ship.hull = battle.attacker.hull;
+    if ship.hull == 0 {
+        ship.wreck();
+    }
+    planet.fighters = battle.defender.fighters;
+    planet.shields = battle.defender.shields;
+    if is_captured {
+        planet.owner_user_id = Some(user_id);
+    }
+
+    let transaction = database.transaction().map_err(|e| format!("Cannot attack planet:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, COLONY_REQUEST_COST)?;
+    ship::update_ship(&transaction, &ship)?;
+    planet::update_planet(&transaction, &planet)?;
+    transaction.commit().map_err(|e| format!("Cannot attack planet:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    ship::publish_ship(ship.clone());
+    planet::publish_planet(planet.clone());
+
+    let report = AssaultReport { planet, ship, battle, is_captured };
+    report.notify(database, user_id, defender_user_id);
+    Ok(report)
+}
+
+impl AssaultReport {
+    /// Creates a vector of strings describing the attack, from the attacker's point of view
+    pub fn get_description(&self) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        if self.is_captured {
+            result.push(format!("You captured {}", self.planet.planet_name));
+        } else {
+            result.push(format!("The defenders of {} drove you off", self.planet.planet_name));
+        }
+        result.extend(self.battle.get_description());
+        result
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "is_captured": self.is_captured,
+            "battle": self.battle.to_json(),
+            "planet": self.planet.to_json(),
+            "ship": self.ship.to_json(),
+        })
+    }
+
+    // Sends messages describing the outcome to the attacker and the planet's former (or current) owner.
+    // The attack has already been committed by now, so failing to send is only worth a warning.
+    fn notify(&self, database: &Connection, attacker_user_id: UserId, defender_user_id: UserId) {
+        let attacker_name = user::get_user(attacker_user_id).map(|user| user.game_name).unwrap_or_default();
+        let outcome = if self.is_captured { "captured" } else { "was repelled from" };
+        let summary = format!("{} attacked and {} {}. Attacker lost {} fighters; defenders lost {} fighters.",
+                              attacker_name, outcome, self.planet.planet_name,
+                              self.battle.attacker_fighters_lost(), self.battle.defender_fighters_lost());
+
+        for to_user_id in [attacker_user_id, defender_user_id] {
+            match message::create_message(database, user::ADMIN_USER_ID, to_user_id, &summary) {
+                Ok(msg) => message::publish_message(msg),
+                Err(e) => log_warn!("Cannot send assault report to user {}:{}", to_user_id, e),
+            }
+        }
+    }
+}
+
+fn get_ship(user_id: UserId) -> Result<Ship, String> {
+    ship::get_ship_for_user(user_id).ok_or("You do not have a ship".to_string())
+}
+
+fn get_planet_in_sector(ship: &Ship) -> Result<Planet, String> {
+    match sector::get_sector(ship.sector_id).unwrap().planet_id.and_then(planet::get_planet) {
+        Some(planet) => Ok(planet),
+        None => Err(format!("There is no planet in sector {}", ship.sector_id)),
+    }
+}
+
+fn get_owned_planet(ship: &Ship, user_id: UserId) -> Result<Planet, String> {
+    let planet = get_planet_in_sector(ship)?;
+    if planet.owner_user_id != Some(user_id) {
+        return Err(format!("You do not own {}", planet.planet_name));
+    }
+    Ok(planet)
+}
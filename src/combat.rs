@@ -0,0 +1,636 @@
+use rand::Rng;
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use crate::deployment::Deployment;
+use crate::planet::Planet;
+use crate::sector::SectorId;
+use crate::ship::{Ship, ShipId};
+use crate::user::UserId;
+use crate::{deployment, log_warn, message, sector, ship, user};
+
+const MAX_ROUNDS: u32 = 50;
+const MIN_DAMAGE_PERCENT: i64 = 75;
+const MAX_DAMAGE_PERCENT: i64 = 125;
+// Damage dealt by each fighter in a round, as a percentage of one point. Ships fight one another at a fifth of
+// the rate of battles with planets and deployed fighters, so that an engagement lasts for several rounds -
+// long enough for orders to retreat or flee to come into play before a ship's hull is destroyed.
+const BATTLE_FIGHTER_DAMAGE_PERCENT: i64 = 100;
+const ENGAGEMENT_FIGHTER_DAMAGE_PERCENT: i64 = 20;
+const RETREAT_PERCENT: i32 = 50; // a side under orders to retreat breaks off once it has lost this share of its fighters
+const COMBAT_REQUEST_COST: i32 = 1;
+const MINE_HIT_PERCENT: i32 = 20; // chance of each mine detonating against a ship which forces its way into a sector
+const MINE_DAMAGE: i32 = 15;
+
+/// One side of a battle. Each fighter deals one point of damage per round in a battle, or a fifth of a point
+/// in an engagement between ships (scaled by strength_percent, and by a random factor).
+/// Damage taken is absorbed by shields first - one point per shield unit -
+/// then destroys one fighter per point, and anything left over damages the hull.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Combatant {
+    pub fighters: i32,
+    pub shields: i32,
+    pub hull: i32, // ships only - planets have no hull
+    pub strength_percent: i32, // 100 for ordinary fighters; more for fighters with an advantage (such as a citadel)
+    pub escape_percent: i32, // chance, each round, that a side under orders to flee gets away
+}
+
+/// What a ship does when it is in combat. A ship's standing order (see ship::Ship::combat_order)
+/// determines how it responds when attacked; an attacker chooses its order with each attack.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CombatOrder {
+    Attack,  // fight until one side is beaten
+    Retreat, // fight, but break off after losing RETREAT_PERCENT of the fighters
+    Flee,    // hold fire, and try to escape to an adjacent sector
+}
+
+/// How a battle ended for one side
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CombatOutcome {
+    Victorious, // the other side was beaten, or fled
+    Held,       // the battle ended with neither side beaten
+    Retreated,  // broke off the fight, but stayed in the sector
+    Fled,       // escaped to an adjacent sector
+    Defeated,   // lost all fighters and shields, and fled to an adjacent sector
+    Wrecked,    // hull destroyed - see ship::Ship::wreck
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BattleSide {
+    Attacker,
+    Defender,
+}
+
+/// The outcome of a battle against a planet, including what remains of each side
+#[derive(Clone, Copy, Debug)]
+pub struct BattleResult {
+    pub winner: BattleSide,
+    pub rounds: u32,
+    pub attacker_before: Combatant,
+    pub defender_before: Combatant,
+    pub attacker: Combatant,
+    pub defender: Combatant,
+}
+
+/// The outcome of an engagement between two ships, including what remains of each side
+#[derive(Clone, Copy, Debug)]
+pub struct EngagementResult {
+    pub rounds: u32,
+    pub attacker_before: Combatant,
+    pub defender_before: Combatant,
+    pub attacker: Combatant,
+    pub defender: Combatant,
+    pub attacker_outcome: CombatOutcome,
+    pub defender_outcome: CombatOutcome,
+}
+
+/// The outcome of an attack by one ship on another, with both ships as they were left afterwards
+pub struct CombatReport {
+    pub sector_id: SectorId, // where the engagement took place
+    pub attacker: Ship,
+    pub defender: Ship,
+    pub engagement: EngagementResult,
+}
+
+/// Fights a battle against a planet, round by round, until one side is defeated or MAX_ROUNDS have been fought.
+/// In each round both sides fire at once. The attacker is defeated when it has no fighters left;
+/// the defender only when it has neither fighters nor shields left. If neither side is defeated
+/// (or both are, in the same round), the defender holds.
+/// The outcome depends only on the combatants and the random number generator, so a seeded generator
+/// gives a reproducible battle.
+pub fn resolve_battle(attacker: Combatant, defender: Combatant, rng: &mut impl Rng) -> BattleResult {
+    let mut result = BattleResult {
+        winner: BattleSide::Defender,
+        rounds: 0,
+        attacker_before: attacker,
+        defender_before: defender,
+        attacker,
+        defender,
+    };
+
+    while result.rounds < MAX_ROUNDS && result.attacker.fighters > 0 && !result.defender.is_defeated() {
+        result.rounds += 1;
+        let attacker_damage = result.attacker.roll_damage(BATTLE_FIGHTER_DAMAGE_PERCENT, rng);
+        let defender_damage = result.defender.roll_damage(BATTLE_FIGHTER_DAMAGE_PERCENT, rng);
+        result.defender.take_damage(attacker_damage);
+        result.attacker.take_damage(defender_damage);
+    }
+
+    if result.attacker.fighters > 0 && result.defender.is_defeated() {
+        result.winner = BattleSide::Attacker;
+    }
+    result
+}
+
+/// Sends a ship through a minefield (see deployment::Deployment). Each mine has MINE_HIT_PERCENT chance of
+/// detonating against the ship, dealing MINE_DAMAGE; detonated mines are used up.
+/// As with resolve_battle(), a seeded generator gives a reproducible result.
+///
+/// # Returns
+/// What is left of the ship, and the number of mines which detonated
+pub fn detonate_mines(target: Combatant, mines: i32, rng: &mut impl Rng) -> (Combatant, i32) {
+    let mut target = target;
+    let mut detonated = 0;
+    for _ in 0..mines {
+        if rng.random_range(0..100) < MINE_HIT_PERCENT {
+            detonated += 1;
+            target.take_damage(MINE_DAMAGE);
+        }
+    }
+    (target, detonated)
+}
+
+/// Fights an engagement between two ships, each following its own orders, round by round.
+/// Before each round, a side under orders to flee tries to escape; while it has not, it takes fire without
+/// returning it. The engagement ends when a side escapes, is defeated or wrecked, or retreats -
+/// or when neither side has any fighters left, or MAX_ROUNDS have been fought.
+/// As with resolve_battle(), a seeded generator gives a reproducible engagement.
+pub fn resolve_engagement(attacker: Combatant,
+                          attacker_order: CombatOrder,
+                          defender: Combatant,
+                          defender_order: CombatOrder,
+                          rng: &mut impl Rng) -> EngagementResult {
+    let mut result = EngagementResult {
+        rounds: 0,
+        attacker_before: attacker,
+        defender_before: defender,
+        attacker,
+        defender,
+        attacker_outcome: CombatOutcome::Held,
+        defender_outcome: CombatOutcome::Held,
+    };
+
+    while result.rounds < MAX_ROUNDS && (result.attacker.fighters > 0 || result.defender.fighters > 0) {
+        if defender_order == CombatOrder::Flee && result.defender.roll_escape(rng) {
+            result.defender_outcome = CombatOutcome::Fled;
+            break;
+        }
+        if attacker_order == CombatOrder::Flee && result.attacker.roll_escape(rng) {
+            result.attacker_outcome = CombatOutcome::Fled;
+            break;
+        }
+
+        result.rounds += 1;
+        let attacker_damage = if attacker_order == CombatOrder::Flee { 0 } else {
+            result.attacker.roll_damage(ENGAGEMENT_FIGHTER_DAMAGE_PERCENT, rng)
+        };
+        let defender_damage = if defender_order == CombatOrder::Flee { 0 } else {
+            result.defender.roll_damage(ENGAGEMENT_FIGHTER_DAMAGE_PERCENT, rng)
+        };
+        result.defender.take_damage(attacker_damage);
+        result.attacker.take_damage(defender_damage);
+
+        let attacker_outcome = result.attacker.check_outcome(&result.attacker_before, attacker_order);
+        let defender_outcome = result.defender.check_outcome(&result.defender_before, defender_order);
+        if attacker_outcome.is_some() || defender_outcome.is_some() {
+            result.attacker_outcome = attacker_outcome.unwrap_or(CombatOutcome::Held);
+            result.defender_outcome = defender_outcome.unwrap_or(CombatOutcome::Held);
+            break;
+        }
+    }
+
+    // Whoever is left in the field when the other side is beaten (or has run) is the victor
+    if result.attacker_outcome == CombatOutcome::Held && result.defender_outcome.is_driven_off() {
+        result.attacker_outcome = CombatOutcome::Victorious;
+    }
+    if result.defender_outcome == CombatOutcome::Held && result.attacker_outcome.is_driven_off() {
+        result.defender_outcome = CombatOutcome::Victorious;
+    }
+    result
+}
+
+/// Attacks another ship in the same sector as the user's ship. The engagement is fought according to the
+/// given order for the attacker, and the defender's standing order (see resolve_engagement()).
+/// A ship which flees or is defeated leaves through a randomly chosen link from the sector, into a sector which is
+/// not guarded against it (see deployment::Deployment) - if there is none, it stays where it is. A wrecked ship
+/// is towed to its galaxy's root sector. Both ships are persisted, and both players are sent a message
+/// describing the outcome.
+///
+/// # Arguments
+/// * `database` a connected database
+/// * `user_id` the attacking user
+/// * `target_ship_id` the ship to be attacked
+/// * `order` the attacker's order - an attacker cannot flee from its own attack
+/// * `rng` random number generator, which determines the course of the engagement and where any losers flee
+pub fn attack_ship(database: &mut Connection,
+                   user_id: UserId,
+                   target_ship_id: ShipId,
+                   order: CombatOrder,
+                   rng: &mut impl Rng) -> Result<CombatReport, String> {
+    if order == CombatOrder::Flee {
+        return Err("You cannot flee from your own attack".to_string());
+    }
+
+    let mut attacker = ship::get_ship_for_user(user_id).ok_or("You do not have a ship".to_string())?;
+    let mut defender = match ship::get_ship(target_ship_id) {
+        Some(ship) if ship.ship_id == attacker.ship_id => { return Err("You cannot attack your own ship".to_string()); },
+        Some(ship) if ship.sector_id == attacker.sector_id => ship,
+        _ => { return Err(format!("There is no ship {} in sector {}", target_ship_id, attacker.sector_id)); },
+    };
+    if attacker.fighters == 0 {
+        return Err("You have no fighters with which to attack".to_string());
+    }
+
+    let sector_id = attacker.sector_id;
+    let engagement = resolve_engagement(Combatant::from_ship(&attacker), order,
+                                        Combatant::from_ship(&defender), defender.combat_order, rng);
+    apply_engagement(&mut attacker, &engagement.attacker, engagement.attacker_outcome, rng);
+    apply_engagement(&mut defender, &engagement.defender, engagement.defender_outcome, rng);
+
+    let transaction = database.transaction().map_err(|e| format!("Cannot attack ship:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, COMBAT_REQUEST_COST)?;
+    ship::update_ship(&transaction, &attacker)?;
+    ship::update_ship(&transaction, &defender)?;
+    transaction.commit().map_err(|e| format!("Cannot attack ship:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    ship::publish_ship(attacker.clone());
+    ship::publish_ship(defender.clone());
+
+    let report = CombatReport { sector_id, attacker, defender, engagement };
+    report.notify(database);
+    Ok(report)
+}
+
+// Writes what is left of one side back to its ship, then moves the ship if it has fled, been defeated, or been wrecked
+fn apply_engagement(ship: &mut Ship, combatant: &Combatant, outcome: CombatOutcome, rng: &mut impl Rng) {
+    ship.fighters = combatant.fighters;
+    ship.shields = combatant.shields;
+    ship.hull = combatant.hull;
+    match outcome {
+        CombatOutcome::Fled | CombatOutcome::Defeated => {
+            // A ship cannot flee past another player's fighters and mines, so guarded sectors are no refuge.
+            // Sort the links, so that the same generator always picks the same one
+            let hostile_sector_ids = deployment::get_hostile_sector_ids(ship.owner_user_id);
+            let mut links: Vec<_> = sector::get_sector(ship.sector_id).unwrap().sector_links.into_iter()
+                .filter(|link| !hostile_sector_ids.contains(link))
+                .collect();
+            links.sort();
+            if !links.is_empty() {
+                ship.sector_id = links[rng.random_range(0..links.len())];
+            }
+        },
+        CombatOutcome::Wrecked => {
+            ship.wreck();
+        },
+        _ => (),
+    }
+}
+
+impl Combatant {
+    pub fn from_ship(ship: &Ship) -> Combatant {
+        Combatant {
+            fighters: ship.fighters,
+            shields: ship.shields,
+            hull: ship.hull,
+            strength_percent: 100,
+            escape_percent: ship.hull_class.escape_percent(),
+        }
+    }
+
+    /// The fighters left to guard a sector - they have no shields or hull, and cannot flee
+    pub fn from_deployment(deployment: &Deployment) -> Combatant {
+        Combatant { fighters: deployment.fighters, shields: 0, hull: 0, strength_percent: 100, escape_percent: 0 }
+    }
+
+    pub fn from_planet(planet: &Planet) -> Combatant {
+        Combatant {
+            fighters: planet.fighters,
+            shields: planet.shields,
+            hull: 0,
+            strength_percent: planet.get_strength_percent(),
+            escape_percent: 0,
+        }
+    }
+
+    fn is_defeated(&self) -> bool {
+        self.fighters == 0 && self.shields == 0
+    }
+
+    // Decides whether this side is out of the fight, given how it started and its orders
+    fn check_outcome(&self, before: &Combatant, order: CombatOrder) -> Option<CombatOutcome> {
+        if self.hull == 0 && before.hull > 0 {
+            Some(CombatOutcome::Wrecked)
+        } else if self.is_defeated() {
+            Some(CombatOutcome::Defeated)
+        } else if order == CombatOrder::Retreat && self.fighters * 100 < before.fighters * RETREAT_PERCENT {
+            Some(CombatOutcome::Retreated)
+        } else {
+            None
+        }
+    }
+
+    // The damage dealt by this side in one round, given the damage dealt by each fighter (as a percentage of one point)
+    fn roll_damage(&self, fighter_damage_percent: i64, rng: &mut impl Rng) -> i32 {
+        if self.fighters == 0 {
+            return 0;
+        }
+        let percent = rng.random_range(MIN_DAMAGE_PERCENT..(MAX_DAMAGE_PERCENT + 1));
+        let damage = self.fighters as i64 * fighter_damage_percent * self.strength_percent as i64 * percent / 1000000;
+        damage.clamp(1, i32::MAX as i64) as i32
+    }
+
+    fn roll_escape(&self, rng: &mut impl Rng) -> bool {
+        rng.random_range(0..100) < self.escape_percent
+    }
+
+    fn take_damage(&mut self, damage: i32) {
+        let absorbed = damage.min(self.shields);
+        self.shields -= absorbed;
+        let destroyed = (damage - absorbed).min(self.fighters);
+        self.fighters -= destroyed;
+        self.hull -= (damage - absorbed - destroyed).min(self.hull);
+    }
+}
+
+impl CombatOrder {
+    /// Integer code used to represent the order in the database
+    pub fn code(&self) -> i32 {
+        match self {
+            CombatOrder::Attack => 1,
+            CombatOrder::Retreat => 2,
+            CombatOrder::Flee => 3,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Option<CombatOrder> {
+        match code {
+            1 => Some(CombatOrder::Attack),
+            2 => Some(CombatOrder::Retreat),
+            3 => Some(CombatOrder::Flee),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CombatOrder> {
+        match name.trim().to_lowercase().as_str() {
+            "attack" | "fight" => Some(CombatOrder::Attack),
+            "retreat" => Some(CombatOrder::Retreat),
+            "flee" | "run" => Some(CombatOrder::Flee),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CombatOrder::Attack => "Attack",
+            CombatOrder::Retreat => "Retreat",
+            CombatOrder::Flee => "Flee",
+        }
+    }
+}
+
+impl CombatOutcome {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CombatOutcome::Victorious => "Victorious",
+            CombatOutcome::Held => "Held",
+            CombatOutcome::Retreated => "Retreated",
+            CombatOutcome::Fled => "Fled",
+            CombatOutcome::Defeated => "Defeated",
+            CombatOutcome::Wrecked => "Wrecked",
+        }
+    }
+
+    // True if this side has left the field to the other
+    fn is_driven_off(&self) -> bool {
+        matches!(self, CombatOutcome::Fled | CombatOutcome::Defeated | CombatOutcome::Wrecked)
+    }
+}
+
+impl BattleResult {
+    pub fn attacker_fighters_lost(&self) -> i32 {
+        self.attacker_before.fighters - self.attacker.fighters
+    }
+
+    pub fn defender_fighters_lost(&self) -> i32 {
+        self.defender_before.fighters - self.defender.fighters
+    }
+
+    /// Creates a vector of strings describing the battle, from a neutral point of view
+    pub fn get_description(&self) -> Vec<String> {
+        let winner = match self.winner {
+            BattleSide::Attacker => "attacker",
+            BattleSide::Defender => "defender",
+        };
+        vec![
+            format!("Battle lasted {} rounds - the {} prevailed", self.rounds, winner),
+            format!("  Attacker: {} fighters lost, {} fighters and {} shields remaining",
+                    self.attacker_fighters_lost(), self.attacker.fighters, self.attacker.shields),
+            format!("  Defender: {} fighters lost, {} fighters and {} shields remaining",
+                    self.defender_fighters_lost(), self.defender.fighters, self.defender.shields),
+        ]
+    }
+
+    pub fn to_json(&self) -> Value {
+        let winner = match self.winner {
+            BattleSide::Attacker => "attacker",
+            BattleSide::Defender => "defender",
+        };
+        json!({
+            "winner": winner,
+            "rounds": self.rounds,
+            "attacker": {"fighters": self.attacker.fighters, "shields": self.attacker.shields,
+                         "fighters_lost": self.attacker_fighters_lost()},
+            "defender": {"fighters": self.defender.fighters, "shields": self.defender.shields,
+                         "fighters_lost": self.defender_fighters_lost()},
+        })
+    }
+}
+
+impl CombatReport {
+    /// Creates a vector of strings describing the engagement, from a neutral point of view
+    pub fn get_description(&self) -> Vec<String> {
+        let engagement = &self.engagement;
+        vec![
+            format!("Engagement lasted {} rounds", engagement.rounds),
+            describe_side("Attacker", &self.attacker, self.sector_id, &engagement.attacker_before, &engagement.attacker,
+                          engagement.attacker_outcome),
+            describe_side("Defender", &self.defender, self.sector_id, &engagement.defender_before, &engagement.defender,
+                          engagement.defender_outcome),
+        ]
+    }
+
+    pub fn to_json(&self) -> Value {
+        let engagement = &self.engagement;
+        json!({
+            "sector_id": self.sector_id,
+            "rounds": engagement.rounds,
+            "attacker": side_to_json(&self.attacker, &engagement.attacker_before, &engagement.attacker, engagement.attacker_outcome),
+            "defender": side_to_json(&self.defender, &engagement.defender_before, &engagement.defender, engagement.defender_outcome),
+        })
+    }
+
+    // Sends the description of the engagement to both players.
+    // The engagement has already been committed by now, so failing to send is only worth a warning.
+    fn notify(&self, database: &Connection) {
+        let attacker_name = user::get_user(self.attacker.owner_user_id).map(|user| user.game_name).unwrap_or_default();
+        let defender_name = user::get_user(self.defender.owner_user_id).map(|user| user.game_name).unwrap_or_default();
+        let mut lines = vec![format!("{} attacked {} in sector {}", attacker_name, defender_name, self.sector_id)];
+        lines.extend(self.get_description());
+        let text = lines.join("\n");
+
+        for to_user_id in [self.attacker.owner_user_id, self.defender.owner_user_id] {
+            match message::create_message(database, user::ADMIN_USER_ID, to_user_id, &text) {
+                Ok(msg) => message::publish_message(msg),
+                Err(e) => log_warn!("Cannot send combat report to user {}:{}", to_user_id, e),
+            }
+        }
+    }
+}
+
+fn describe_side(label: &str,
+                 ship: &Ship,
+                 sector_id: SectorId,
+                 before: &Combatant,
+                 after: &Combatant,
+                 outcome: CombatOutcome) -> String {
+    let mut text = format!("  {}: {} - lost {} fighters, {} shields and {} hull",
+                           label, outcome.name(), before.fighters - after.fighters,
+                           before.shields - after.shields, before.hull - after.hull);
+    match outcome {
+        CombatOutcome::Fled | CombatOutcome::Defeated if ship.sector_id == sector_id => text.push_str(", but had nowhere to flee"),
+        CombatOutcome::Fled | CombatOutcome::Defeated => text.push_str(format!(", and fled to sector {}", ship.sector_id).as_str()),
+        CombatOutcome::Wrecked => text.push_str(format!(", and was towed to sector {}", ship.sector_id).as_str()),
+        _ => (),
+    }
+    text
+}
+
+fn side_to_json(ship: &Ship, before: &Combatant, after: &Combatant, outcome: CombatOutcome) -> Value {
+    json!({
+        "ship_id": ship.ship_id,
+        "outcome": outcome.name(),
+        "sector_id": ship.sector_id,
+        "fighters": ship.fighters,
+        "shields": ship.shields,
+        "hull": ship.hull,
+        "fighters_lost": before.fighters - after.fighters,
+        "shields_lost": before.shields - after.shields,
+        "hull_lost": before.hull - after.hull,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use crate::database::testing;
+    use crate::ship::HullClass;
+
+    const SEED: u64 = 7;
+
+    // A ship with the given fighters, shields, and hull, and a Merchant's chance of escape
+    fn ship_combatant(fighters: i32, shields: i32, hull: i32) -> Combatant {
+        Combatant { fighters, shields, hull, strength_percent: 100, escape_percent: 40 }
+    }
+
+    fn engage(attacker: Combatant, defender: Combatant, defender_order: CombatOrder) -> EngagementResult {
+        resolve_engagement(attacker, CombatOrder::Attack, defender, defender_order, &mut ChaCha8Rng::seed_from_u64(SEED))
+    }
+
+    #[test]
+    fn attack_fights_until_defeat() {
+        let result = engage(ship_combatant(50, 20, 150), ship_combatant(20, 10, 150), CombatOrder::Attack);
+        assert_eq!(result.rounds, 4);
+        assert_eq!(result.attacker_outcome, CombatOutcome::Victorious);
+        assert_eq!(result.defender_outcome, CombatOutcome::Defeated);
+        assert_eq!(result.attacker, ship_combatant(50, 10, 150));
+        assert_eq!(result.defender, ship_combatant(0, 0, 144));
+    }
+
+    #[test]
+    fn retreat_breaks_off_after_losing_half_the_fighters() {
+        let result = engage(ship_combatant(50, 20, 150), ship_combatant(40, 10, 150), CombatOrder::Retreat);
+        assert_eq!(result.rounds, 4);
+        assert_eq!(result.attacker_outcome, CombatOutcome::Held);
+        assert_eq!(result.defender_outcome, CombatOutcome::Retreated);
+        assert_eq!(result.attacker, ship_combatant(47, 0, 150));
+        assert_eq!(result.defender, ship_combatant(14, 0, 150));
+    }
+
+    #[test]
+    fn flee_escapes_without_fighting() {
+        let result = engage(ship_combatant(50, 20, 150), ship_combatant(20, 10, 150), CombatOrder::Flee);
+        assert_eq!(result.rounds, 0);
+        assert_eq!(result.attacker_outcome, CombatOutcome::Victorious);
+        assert_eq!(result.defender_outcome, CombatOutcome::Fled);
+        assert_eq!(result.defender, result.defender_before);
+    }
+
+    #[test]
+    fn undefended_ship_is_wrecked() {
+        let result = engage(ship_combatant(50, 0, 150), ship_combatant(0, 0, 8), CombatOrder::Attack);
+        assert_eq!(result.rounds, 1);
+        assert_eq!(result.attacker_outcome, CombatOutcome::Victorious);
+        assert_eq!(result.defender_outcome, CombatOutcome::Wrecked);
+        assert_eq!(result.defender, ship_combatant(0, 0, 0));
+    }
+
+    #[test]
+    fn ship_without_fighters_or_shields_is_defeated() {
+        let result = engage(ship_combatant(50, 0, 150), ship_combatant(0, 5, 150), CombatOrder::Attack);
+        assert_eq!(result.rounds, 1);
+        assert_eq!(result.attacker_outcome, CombatOutcome::Victorious);
+        assert_eq!(result.defender_outcome, CombatOutcome::Defeated);
+        assert_eq!(result.defender, ship_combatant(0, 0, 147));
+    }
+
+    #[test]
+    fn battle_fighters_deal_a_point_of_damage_each() {
+        let planet = Combatant { fighters: 30, shields: 20, hull: 0, strength_percent: 100, escape_percent: 0 };
+        let result = resolve_battle(ship_combatant(50, 20, 150), planet, &mut ChaCha8Rng::seed_from_u64(SEED));
+        assert_eq!(result.rounds, 2);
+        assert_eq!(result.winner, BattleSide::Attacker);
+        assert_eq!(result.attacker, ship_combatant(36, 0, 150));
+        assert_eq!(result.defender.fighters + result.defender.shields, 0);
+    }
+
+    #[test]
+    fn mines_detonate_against_shields_then_fighters_then_hull() {
+        let (target, detonated) = detonate_mines(ship_combatant(10, 20, 150), 20, &mut ChaCha8Rng::seed_from_u64(SEED));
+        assert_eq!(detonated, 7);
+        assert_eq!(target, ship_combatant(0, 0, 75));
+    }
+
+    // Builds a sector linked to three others, guards those of the others given (by index) with user 3's fighters,
+    // and has a ship belonging to user 2 flee from the first. Returns the ship's destination, and the sectors in order.
+    fn flee(guarded: &[usize]) -> (SectorId, Vec<SectorId>) {
+        let (_game, database) = testing::create_game();
+        let sector_ids: Vec<SectorId> = (0..4).map(|_| sector::create_sector(&database).unwrap()).collect();
+        for to_sector_id in &sector_ids[1..] {
+            sector::link_sectors(&database, sector_ids[0], *to_sector_id).unwrap();
+        }
+        for index in guarded {
+            database.execute("INSERT INTO sector_deployments (sectorId, ownerUserId, fighters, mines) VALUES (?1, 3, 10, 0);",
+                             [sector_ids[*index]]).unwrap();
+        }
+        deployment::load_deployments(&database).unwrap();
+        let ship_id = ship::create_ship(&database, 2, 1, sector_ids[0], HullClass::Merchant).unwrap();
+        let mut ship = ship::get_ship(ship_id).unwrap();
+
+        let combatant = ship_combatant(5, 0, 150);
+        apply_engagement(&mut ship, &combatant, CombatOutcome::Fled, &mut ChaCha8Rng::seed_from_u64(SEED));
+        assert_eq!((ship.fighters, ship.shields, ship.hull), (5, 0, 150));
+        (ship.sector_id, sector_ids)
+    }
+
+    #[test]
+    fn fleeing_ship_leaves_through_a_random_link() {
+        let (destination, sector_ids) = flee(&[]);
+        assert_eq!(destination, sector_ids[1]);
+    }
+
+    #[test]
+    fn fleeing_ship_avoids_guarded_sectors() {
+        let (destination, sector_ids) = flee(&[1, 2]);
+        assert_eq!(destination, sector_ids[3]);
+    }
+
+    #[test]
+    fn fleeing_ship_with_nowhere_to_go_stays_put() {
+        let (destination, sector_ids) = flee(&[1, 2, 3]);
+        assert_eq!(destination, sector_ids[0]);
+    }
+}
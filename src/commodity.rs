@@ -0,0 +1,56 @@
+/// The goods which are traded at ports and carried in the holds of ships.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Commodity {
+    FuelOre,
+    Organics,
+    Equipment,
+}
+
+impl Commodity {
+    pub const ALL: [Commodity; 3] = [Commodity::FuelOre, Commodity::Organics, Commodity::Equipment];
+
+    /// The price (per unit) around which the port prices for this commodity float.
+    pub fn base_price(&self) -> i32 {
+        match self {
+            Commodity::FuelOre => 20,
+            Commodity::Organics => 35,
+            Commodity::Equipment => 60,
+        }
+    }
+
+    /// Integer code used to represent the commodity in the database
+    pub fn code(&self) -> i32 {
+        match self {
+            Commodity::FuelOre => 1,
+            Commodity::Organics => 2,
+            Commodity::Equipment => 3,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Option<Commodity> {
+        match code {
+            1 => Some(Commodity::FuelOre),
+            2 => Some(Commodity::Organics),
+            3 => Some(Commodity::Equipment),
+            _ => None,
+        }
+    }
+
+    /// Interprets a user-supplied commodity name - we are fairly forgiving about what we accept.
+    pub fn from_name(name: &str) -> Option<Commodity> {
+        match name.trim().to_lowercase().replace(['_', '-', ' '], "").as_str() {
+            "fuelore" | "fuel" | "ore" => Some(Commodity::FuelOre),
+            "organics" | "organic" | "org" => Some(Commodity::Organics),
+            "equipment" | "equip" | "equ" => Some(Commodity::Equipment),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Commodity::FuelOre => "Fuel Ore",
+            Commodity::Organics => "Organics",
+            Commodity::Equipment => "Equipment",
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{LazyLock, Mutex};
+use serde::Deserialize;
+use crate::logging::LogLevel;
+use crate::{galaxy, server, session, user};
+
+/// Name of the configuration file which is used if none is specified
+pub const DEFAULT_CONFIG_PATH: &str = "space-trader.toml";
+
+static CONFIG: LazyLock<Mutex<Config>> = LazyLock::new(|| Mutex::new(Config::default()));
+
+/// Game configuration, as read from a TOML file. Every setting is optional in the file,
+/// and anything not specified takes the default value.
+/// For example:
+/// ```toml
+/// [server]
+/// bind_address = "0.0.0.0:2000"
+/// database_path = "space-trader.db"
+/// log_level = "info"
+///
+/// [sessions]
+/// idle_timeout_seconds = 300
+/// max_lifetime_seconds = 86400
+///
+/// [users]
+/// requests_per_day = 150
+/// admin_password = "admin"
+///
+/// [galaxy]
+/// distance_limit = 20
+/// sectors_per_port = 15
+/// sectors_per_planet = 25
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub sessions: SessionConfig,
+    pub users: UserConfig,
+    pub galaxy: GalaxyConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub database_path: String,
+    pub log_level: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub idle_timeout_seconds: u64,
+    pub max_lifetime_seconds: Option<u64>, // if None, sessions live as long as they are in use
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserConfig {
+    pub requests_per_day: i32,
+    pub admin_password: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GalaxyConfig {
+    pub distance_limit: isize,   // furthest any sector may be from the root sector of a conventional galaxy
+    pub sectors_per_port: usize, // one port is created for this many sectors
+    pub sectors_per_planet: usize, // one planet is created for this many sectors
+}
+
+/// Reads the configuration from the given file. If no path is given, the default file is read if it exists,
+/// otherwise the default configuration is used. If a path is given, the file must exist.
+/// The result is not validated - see Config::validate().
+pub fn load_config(path: Option<&str>) -> Result<Config, String> {
+    let path = match path {
+        Some(path) => path,
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => DEFAULT_CONFIG_PATH,
+        None => { return Ok(Config::default()); },
+    };
+
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Cannot read configuration file {}:{}", path, e))?;
+    toml::from_str(&text).map_err(|e| format!("Cannot parse configuration file {}:{}", path, e))
+}
+
+/// Gets a clone of the configuration in effect
+pub fn get_config() -> Config {
+    CONFIG.lock().unwrap().clone()
+}
+
+/// Establishes the configuration to be used by the rest of the game. Should be invoked at startup,
+/// after the configuration has been loaded and validated.
+pub fn set_config(config: Config) {
+    *CONFIG.lock().unwrap() = config;
+}
+
+impl Config {
+    /// Checks that the settings make sense, returning a description of the first problem found
+    pub fn validate(&self) -> Result<(), String> {
+        if self.server.bind_address.parse::<SocketAddr>().is_err() {
+            return Err(format!("Invalid bind address '{}' - expected ip-address:port", self.server.bind_address));
+        }
+        if self.server.database_path.trim().is_empty() {
+            return Err("Database path must not be empty".to_string());
+        }
+        if LogLevel::from_name(&self.server.log_level).is_none() {
+            return Err(format!("Invalid log level '{}' - expected error, warn, info, or debug", self.server.log_level));
+        }
+        if self.sessions.idle_timeout_seconds < server::POLL_TIMEOUT_SECONDS {
+            // otherwise a session could expire while its user is waiting for messages
+            return Err(format!("Session idle timeout must be at least {} seconds", server::POLL_TIMEOUT_SECONDS));
+        }
+        if self.sessions.max_lifetime_seconds == Some(0) {
+            return Err("Session maximum lifetime must be greater than zero".to_string());
+        }
+        if self.users.requests_per_day <= 0 {
+            return Err("Requests per day must be greater than zero".to_string());
+        }
+        if self.users.admin_password.is_empty() {
+            return Err("Admin password must not be empty".to_string());
+        }
+        if self.galaxy.distance_limit <= 0 {
+            return Err("Galaxy distance limit must be greater than zero".to_string());
+        }
+        if self.galaxy.sectors_per_port == 0 {
+            return Err("Sectors per port must be greater than zero".to_string());
+        }
+        if self.galaxy.sectors_per_planet == 0 {
+            return Err("Sectors per planet must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+
+    /// The log level - only to be used on a validated configuration
+    pub fn get_log_level(&self) -> LogLevel {
+        LogLevel::from_name(&self.server.log_level).unwrap_or(LogLevel::Info)
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: "127.0.0.1:2000".to_string(),
+            database_path: "space-trader.db".to_string(),
+            log_level: "info".to_string(),
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig { idle_timeout_seconds: session::DEFAULT_SESSION_TIMEOUT_SECONDS, max_lifetime_seconds: None }
+    }
+}
+
+impl Default for UserConfig {
+    fn default() -> Self {
+        UserConfig {
+            requests_per_day: user::DEFAULT_REQUESTS_PER_DAY,
+            admin_password: user::DEFAULT_ADMIN_PASSWORD.to_string(),
+        }
+    }
+}
+
+impl Default for GalaxyConfig {
+    fn default() -> Self {
+        GalaxyConfig {
+            distance_limit: galaxy::DEFAULT_DISTANCE_LIMIT,
+            sectors_per_port: galaxy::DEFAULT_SECTORS_PER_PORT,
+            sectors_per_planet: galaxy::DEFAULT_SECTORS_PER_PLANET,
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::sync::{LazyLock, Mutex};
+use rusqlite::Connection;
+use crate::{log_info, planet, ship};
+
+static DATABASE: LazyLock<Mutex<Option<Connection>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Statements which build the game database from scratch - dropping any existing tables first.
+pub const BUILD_STATEMENTS: &[&str] = &[
+    "DROP TABLE IF EXISTS galaxies_to_sectors;",
+    "DROP TABLE IF EXISTS sector_deployments;",
+    "DROP TABLE IF EXISTS sector_links;",
+    "DROP TABLE IF EXISTS sectors_to_planets;",
+    "DROP TABLE IF EXISTS planet_resources;",
+    "DROP TABLE IF EXISTS sectors_to_ports;",
+    "DROP TABLE IF EXISTS sectors;",
+    "DROP TABLE IF EXISTS planets;",
+    "DROP TABLE IF EXISTS port_commodities;",
+    "DROP TABLE IF EXISTS ports;",
+    "DROP TABLE IF EXISTS galaxies;",
+    "DROP TABLE IF EXISTS trades;",
+    "DROP TABLE IF EXISTS ship_cargo;",
+    "DROP TABLE IF EXISTS ships;",
+    "DROP TABLE IF EXISTS messages;",
+    "DROP TABLE IF EXISTS users;",
+    "DROP TABLE IF EXISTS daily_runs;",
+
+    "CREATE TABLE users ( \
+                userId INTEGER PRIMARY KEY NOT NULL, \
+                userName TEXT NOT NULL UNIQUE, \
+                password TEXT, \
+                gameName TEXT, \
+                lastLoginTimeStamp INTEGER, \
+                isDisabled INTEGER NOT NULL,\
+                requestsPerDay INTEGER, \
+                requestsRemaining INTEGER);",
+
+    "CREATE TABLE messages ( \
+                messageId INTEGER PRIMARY KEY NOT NULL, \
+                fromUserId INTEGER REFERENCES users(userId), \
+                toUserId INTEGER NOT NULL REFERENCES users(userId), \
+                timeStamp INTEGER NOT NULL, \
+                text STRING, \
+                isDelivered INTEGER NOT NULL DEFAULT 0);",
+
+    "CREATE TABLE galaxies ( \
+                galaxyId INTEGER PRIMARY KEY NOT NULL, \
+                galaxyName TEXT NOT NULL, \
+                galaxyKind INTEGER NOT NULL, \
+                seed INTEGER);",
+
+    "CREATE TABLE sectors ( \
+                sectorId INTEGER PRIMARY KEY NOT NULL);",
+
+    "CREATE TABLE planets ( \
+                planetId INTEGER NOT NULL,\
+                planetName STRING, \
+                ownerUserId INTEGER REFERENCES users(userId), \
+                population INTEGER NOT NULL, \
+                maxPopulation INTEGER NOT NULL, \
+                fighters INTEGER NOT NULL, \
+                shields INTEGER NOT NULL, \
+                citadelLevel INTEGER NOT NULL);",
+
+    "CREATE TABLE planet_resources ( \
+                planetId INTEGER NOT NULL REFERENCES planets(planetId), \
+                commodity INTEGER NOT NULL, \
+                deposit INTEGER NOT NULL, \
+                stockpile INTEGER NOT NULL, \
+                capacity INTEGER NOT NULL, \
+                ratePerDay INTEGER NOT NULL, \
+                PRIMARY KEY (planetId, commodity));",
+
+    "CREATE TABLE ports ( \
+                portId INTEGER NOT NULL,\
+                portName STRING);",
+
+    "CREATE TABLE port_commodities ( \
+                portId INTEGER NOT NULL REFERENCES ports(portId), \
+                commodity INTEGER NOT NULL, \
+                isSelling INTEGER NOT NULL, \
+                quantity INTEGER NOT NULL, \
+                capacity INTEGER NOT NULL, \
+                ratePerCycle INTEGER NOT NULL, \
+                PRIMARY KEY (portId, commodity));",
+
+    "CREATE TABLE ships ( \
+                shipId INTEGER PRIMARY KEY NOT NULL, \
+                ownerUserId INTEGER NOT NULL REFERENCES users(userId), \
+                galaxyId INTEGER NOT NULL REFERENCES galaxies(galaxyId), \
+                sectorId INTEGER NOT NULL REFERENCES sectors(sectorId), \
+                hullClass INTEGER NOT NULL, \
+                fuel INTEGER NOT NULL, \
+                credits INTEGER NOT NULL, \
+                colonists INTEGER NOT NULL, \
+                fighters INTEGER NOT NULL, \
+                shields INTEGER NOT NULL, \
+                hull INTEGER NOT NULL, \
+                combatOrder INTEGER NOT NULL);",
+
+    "CREATE TABLE ship_cargo ( \
+                shipId INTEGER NOT NULL REFERENCES ships(shipId), \
+                commodity INTEGER NOT NULL, \
+                quantity INTEGER NOT NULL, \
+                PRIMARY KEY (shipId, commodity));",
+
+    "CREATE TABLE trades ( \
+                tradeId INTEGER PRIMARY KEY AUTOINCREMENT, \
+                timeStamp INTEGER NOT NULL, \
+                portId INTEGER NOT NULL REFERENCES ports(portId), \
+                shipId INTEGER NOT NULL REFERENCES ships(shipId), \
+                commodity INTEGER NOT NULL, \
+                quantity INTEGER NOT NULL, \
+                unitPrice INTEGER NOT NULL, \
+                isPurchase INTEGER NOT NULL);",
+
+    "CREATE TABLE sector_links ( \
+                fromSectorId INTEGER REFERENCES sectors(sectorId), \
+                toSectorId INTEGER REFERENCES sectors(sectorId), \
+                PRIMARY KEY (fromSectorId, toSectorId));",
+
+    "CREATE TABLE sectors_to_planets ( \
+                sectorId INTEGER REFERENCES sectors(sectorId), \
+                planetId INTEGER REFERENCES planets(planetId), \
+                PRIMARY KEY (sectorId, planetId));",
+
+    "CREATE TABLE sectors_to_ports ( \
+                sectorId INTEGER REFERENCES sectors(sectorId), \
+                portId INTEGER REFERENCES ports(portId), \
+                PRIMARY KEY (sectorId, PortId));",
+
+    "CREATE TABLE sector_deployments ( \
+                sectorId INTEGER PRIMARY KEY NOT NULL REFERENCES sectors(sectorId), \
+                ownerUserId INTEGER NOT NULL REFERENCES users(userId), \
+                fighters INTEGER NOT NULL, \
+                mines INTEGER NOT NULL);",
+
+    "CREATE TABLE galaxies_to_sectors ( \
+                galaxyId INTEGER REFERENCES galaxy(galaxyId), \
+                sectorId INTEGER REFERENCES sectors(sectorId), \
+                PRIMARY KEY (galaxyId, SectorId));",
+
+    "CREATE TABLE daily_runs ( \
+                runDate TEXT PRIMARY KEY NOT NULL);",
+];
+
+// Columns added to existing tables since databases were first built by init: table, column, column definition,
+// and optionally a function to fill in the column for existing rows (run only when the column is added).
+// Each definition must have a default (if NOT NULL), which existing rows get unless the function says otherwise.
+const COLUMN_UPGRADES: &[(&str, &str, &str, Option<ColumnFiller>)] = &[
+    ("messages", "isDelivered", "INTEGER NOT NULL DEFAULT 0", None),
+    ("galaxies", "seed", "INTEGER", None),
+    // galaxies generated before their kind was recorded are taken to be conventional (GalaxyKind::Conventional)
+    ("galaxies", "galaxyKind", "INTEGER NOT NULL DEFAULT 1", None),
+    // planets from before owners and colonists are unclaimed and uninhabited, but get a random maximum population
+    // (and deposits, in planet_resources) as though they had just been created
+    ("planets", "ownerUserId", "INTEGER REFERENCES users(userId)", None),
+    ("planets", "population", "INTEGER NOT NULL DEFAULT 0", None),
+    ("planets", "maxPopulation", "INTEGER NOT NULL DEFAULT 0", Some(planet::upgrade_planets)),
+    ("planets", "fighters", "INTEGER NOT NULL DEFAULT 0", None),
+    ("planets", "shields", "INTEGER NOT NULL DEFAULT 0", None),
+    ("planets", "citadelLevel", "INTEGER NOT NULL DEFAULT 0", None),
+    ("ships", "colonists", "INTEGER NOT NULL DEFAULT 0", None),
+    ("ships", "fighters", "INTEGER NOT NULL DEFAULT 0", None),
+    ("ships", "shields", "INTEGER NOT NULL DEFAULT 0", None),
+    // ships from before hull damage are undamaged
+    ("ships", "hull", "INTEGER NOT NULL DEFAULT 0", Some(ship::upgrade_hulls)),
+    // the standing order of new ships (CombatOrder::Retreat)
+    ("ships", "combatOrder", "INTEGER NOT NULL DEFAULT 2", None),
+];
+
+// Fills in a newly added column for the existing rows of its table
+type ColumnFiller = fn(&Connection) -> Result<(), String>;
+
+// Tables added since databases were first built by init - each is created if it does not exist
+const TABLE_UPGRADES: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS daily_runs ( \
+                runDate TEXT PRIMARY KEY NOT NULL);",
+
+    "CREATE TABLE IF NOT EXISTS planet_resources ( \
+                planetId INTEGER NOT NULL REFERENCES planets(planetId), \
+                commodity INTEGER NOT NULL, \
+                deposit INTEGER NOT NULL, \
+                stockpile INTEGER NOT NULL, \
+                capacity INTEGER NOT NULL, \
+                ratePerDay INTEGER NOT NULL, \
+                PRIMARY KEY (planetId, commodity));",
+
+    "CREATE TABLE IF NOT EXISTS sector_deployments ( \
+                sectorId INTEGER PRIMARY KEY NOT NULL REFERENCES sectors(sectorId), \
+                ownerUserId INTEGER NOT NULL REFERENCES users(userId), \
+                fighters INTEGER NOT NULL, \
+                mines INTEGER NOT NULL);",
+];
+
+/// Hands the game database over to be shared by the request handlers (and anything else which
+/// needs to persist changes while the game is running). Should be invoked after everything is loaded.
+pub fn set_database(database: Connection) {
+    *DATABASE.lock().unwrap() = Some(database);
+}
+
+/// Runs the given function against the shared game database.
+/// Access is serialized, so the function should not do anything which might block for any length of time.
+/// The in-memory maps (users, ships, etc.) may be locked from within the function, but the database
+/// must never be requested while holding one of those locks.
+pub fn with_database<T>(func: impl FnOnce(&mut Connection) -> Result<T, String>) -> Result<T, String> {
+    let mut lock = DATABASE.lock().unwrap();
+    match lock.as_mut() {
+        Some(database) => func(database),
+        None => Err("Database is not available".to_string()),
+    }
+}
+
+/// Brings a database built by an older version of init up to date, by adding any tables and columns it lacks
+/// (see TABLE_UPGRADES and COLUMN_UPGRADES), and filling in the new columns for existing rows.
+/// The upgrade is done in a single transaction, so a failed upgrade leaves the database as it was.
+/// Should be invoked before anything is loaded from the database.
+pub fn upgrade_schema(database: &Connection) -> Result<(), String> {
+    let transaction = database.unchecked_transaction().map_err(|e| format!("Cannot upgrade database:{}", e))?;
+    for statement in TABLE_UPGRADES {
+        transaction.execute(statement, []).map_err(|e| format!("Cannot upgrade database:{}", e))?;
+    }
+    for (table, column, definition, filler) in COLUMN_UPGRADES {
+        if !has_column(&transaction, table, column).map_err(|e| format!("Cannot read the schema of {}:{}", table, e))? {
+            log_info!("Upgrading database: adding {}.{}", table, column);
+            let statement = format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, definition);
+            transaction.execute(&statement, [])
+                .map_err(|e| format!("Cannot add column {}.{}:{}", table, column, e))?;
+            if let Some(filler) = filler {
+                filler(&transaction)?;
+            }
+        }
+    }
+    transaction.commit().map_err(|e| format!("Cannot upgrade database:{}", e))
+}
+
+// Determines whether the given table has the given column
+fn has_column(database: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut stmt = database.prepare(&format!("PRAGMA table_info({});", table))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let name: String = row.get(1)?;
+        if name == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::sync::{Mutex, MutexGuard};
+    use rusqlite::Connection;
+    use crate::{deployment, galaxy, message, planet, port, sector, ship, user};
+
+    // The game's in-memory state is shared by every test, and tests run concurrently -
+    // so tests which use that state take turns.
+    static GAME_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Builds an empty game in an in-memory database, and empties the in-memory state to match.
+    /// The game is only ours for as long as we hold on to the returned guard.
+    pub fn create_game() -> (MutexGuard<'static, ()>, Connection) {
+        let guard = GAME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let database = Connection::open_in_memory().unwrap();
+        for statement in super::BUILD_STATEMENTS {
+            database.execute(statement, ()).unwrap();
+        }
+
+        user::load_users(&database).unwrap();
+        message::load_messages(&database).unwrap();
+        planet::load_planets(&database).unwrap();
+        port::load_ports(&database).unwrap();
+        sector::load_sectors(&database).unwrap();
+        galaxy::load_galaxies(&database).unwrap();
+        ship::load_ships(&database).unwrap();
+        deployment::load_deployments(&database).unwrap();
+        (guard, database)
+    }
+}
@@ -0,0 +1,543 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+use rand::Rng;
+use rusqlite::{params, Connection};
+use serde_json::{json, Value};
+use crate::combat::{BattleResult, BattleSide, Combatant};
+use crate::sector::SectorId;
+use crate::ship::Ship;
+use crate::user::UserId;
+use crate::{combat, galaxy, log_info, log_warn, message, ship, user};
+
+static DEPLOYMENTS: LazyLock<Mutex<HashMap<SectorId, Deployment>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+const DEPLOY_REQUEST_COST: i32 = 1;
+const MINE_PRICE: i64 = 50; // mines are bought as they are laid
+const MAX_MINES_PER_SECTOR: i32 = 200;
+const TOLL_PER_FIGHTER: i64 = 10;
+const TOLL_PER_MINE: i64 = 5;
+
+/// Fighters and mines left in a sector by a player to guard it. There is at most one deployment per sector;
+/// another player must clear it (by fighting their way in) before laying their own.
+/// Other players' ships may only enter a guarded sector by paying a toll to the owner, or by fighting
+/// (see enter_sector()). Deployments never hinder their owner.
+#[derive(Clone)]
+pub struct Deployment {
+    pub sector_id: SectorId,
+    pub owner_user_id: UserId,
+    pub fighters: i32,
+    pub mines: i32,
+}
+
+/// How a ship responds on meeting another player's deployment in the sector it is entering
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EncounterResponse {
+    PayToll, // pay the owner, and pass unharmed
+    Fight,   // run the minefield, then fight the fighters - entering the sector only if they are beaten
+    Retreat, // turn back, having spent the fuel for the move
+}
+
+/// The outcome of a ship's attempt to enter a guarded sector
+pub struct EncounterReport {
+    pub sector_id: SectorId, // the guarded sector
+    pub response: EncounterResponse,
+    pub is_entered: bool,
+    pub is_wrecked: bool,
+    pub toll_paid: i64,
+    pub mines_detonated: i32,
+    pub battle: Option<BattleResult>,
+    pub deployment: Deployment, // as left after the encounter
+    pub ship: Ship,
+}
+
+/// Lays fighters (from the user's ship) and mines (bought at MINE_PRICE credits each) in the sector where the
+/// user's ship is located. The root sector of each galaxy cannot be guarded, nor can a sector already guarded
+/// by another player.
+///
+/// # Returns
+/// The deployment in the sector, including anything laid previously
+pub fn deploy(database: &mut Connection, user_id: UserId, fighters: i32, mines: i32) -> Result<Deployment, String> {
+    if fighters < 0 || mines < 0 || fighters + mines == 0 {
+        return Err("Nothing to deploy".to_string());
+    }
+
+    let mut ship = ship::get_ship_for_user(user_id).ok_or("You do not have a ship".to_string())?;
+    if Some(ship.sector_id) == galaxy::get_galaxy(ship.galaxy_id).and_then(|galaxy| galaxy.get_root_sector_id()) {
+        return Err(format!("Sector {} cannot be guarded", ship.sector_id));
+    }
+
+    let mut deployment = match get_deployment(ship.sector_id) {
+        Some(deployment) if deployment.owner_user_id != user_id => {
+            return Err(format!("Sector {} is already guarded by {}", ship.sector_id, deployment.get_description()));
+        },
+        Some(deployment) => deployment,
+        None => Deployment { sector_id: ship.sector_id, owner_user_id: user_id, fighters: 0, mines: 0 },
+    };
+
+    if fighters > ship.fighters {
+        return Err(format!("You only have {} fighters aboard", ship.fighters));
+    }
+    if deployment.mines + mines > MAX_MINES_PER_SECTOR {
+        return Err(format!("There is only room for {} more mines in this sector", MAX_MINES_PER_SECTOR - deployment.mines));
+    }
+    let total_price = mines as i64 * MINE_PRICE;
+    if total_price > ship.credits {
+        return Err(format!("{} mines cost {} credits, but you only have {}", mines, total_price, ship.credits));
+    }
+
+    ship.fighters -= fighters;
+    ship.credits -= total_price;
+    deployment.fighters += fighters;
+    deployment.mines += mines;
+    let transaction = database.transaction().map_err(|e| format!("Cannot deploy:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, DEPLOY_REQUEST_COST)?;
+    ship::update_ship(&transaction, &ship)?;
+    update_deployment(&transaction, &deployment)?;
+    transaction.commit().map_err(|e| format!("Cannot deploy:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    ship::publish_ship(ship.clone());
+    publish_deployment(deployment.clone());
+    Ok(deployment)
+}
+
+/// Takes the user's fighters back aboard from the sector where the user's ship is located.
+/// Mines cannot be recovered once laid.
+///
+/// # Returns
+/// The ship, with the fighters aboard
+pub fn recall_fighters(database: &mut Connection, user_id: UserId, fighters: i32) -> Result<Ship, String> {
+    if fighters <= 0 {
+        return Err("Quantity must be greater than zero".to_string());
+    }
+
+    let mut ship = ship::get_ship_for_user(user_id).ok_or("You do not have a ship".to_string())?;
+    let mut deployment = match get_deployment(ship.sector_id) {
+        Some(deployment) if deployment.owner_user_id == user_id => deployment,
+        _ => { return Err(format!("You have nothing deployed in sector {}", ship.sector_id)); },
+    };
+    if fighters > deployment.fighters {
+        return Err(format!("You only have {} fighters deployed here", deployment.fighters));
+    }
+    if ship.fighters + fighters > ship.hull_class.max_fighters() {
+        return Err(format!("You only have room for {} more fighters", ship.hull_class.max_fighters() - ship.fighters));
+    }
+
+    ship.fighters += fighters;
+    deployment.fighters -= fighters;
+    let transaction = database.transaction().map_err(|e| format!("Cannot recall fighters:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, DEPLOY_REQUEST_COST)?;
+    ship::update_ship(&transaction, &ship)?;
+    update_deployment(&transaction, &deployment)?;
+    transaction.commit().map_err(|e| format!("Cannot recall fighters:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    ship::publish_ship(ship.clone());
+    publish_deployment(deployment);
+    Ok(ship)
+}
+
+/// Moves the user's ship into an adjacent sector which is guarded by another player's deployment,
+/// responding to the deployment as given. The move costs fuel and a request whatever the response;
+/// if the ship does not get in, it stays where it was (unless wrecked - see ship::Ship::wreck).
+/// Tolls are paid to the owner's ship, if they have one. The owner is sent a message describing the encounter.
+///
+/// # Arguments
+/// * `database` a connected database
+/// * `user_id` the user whose ship is moving
+/// * `to_sector_id` the guarded sector
+/// * `response` what the ship does on meeting the deployment
+/// * `rng` random number generator, which determines the effect of mines and the course of any battle
+pub fn enter_sector(database: &mut Connection,
+                    user_id: UserId,
+                    to_sector_id: SectorId,
+                    response: EncounterResponse,
+                    rng: &mut impl Rng) -> Result<EncounterReport, String> {
+    let mut ship = ship::get_ship_for_user(user_id).ok_or("You do not have a ship".to_string())?;
+    ship.check_move(to_sector_id)?;
+    let mut deployment = match get_hostile_deployment(to_sector_id, user_id) {
+        Some(deployment) => deployment,
+        None => { return Err(format!("Sector {} is not guarded", to_sector_id)); },
+    };
+
+    let mut report = EncounterReport {
+        sector_id: to_sector_id,
+        response,
+        is_entered: false,
+        is_wrecked: false,
+        toll_paid: 0,
+        mines_detonated: 0,
+        battle: None,
+        deployment: deployment.clone(),
+        ship: ship.clone(),
+    };
+    let mut owner_ship: Option<Ship> = None;
+    ship.fuel -= ship.hull_class.fuel_per_move();
+
+    match response {
+        EncounterResponse::PayToll => {
+            let toll = deployment.get_toll();
+            if toll > ship.credits {
+                return Err(format!("The toll is {} credits, but you only have {}", toll, ship.credits));
+            }
+            ship.credits -= toll;
+            owner_ship = ship::get_ship_for_user(deployment.owner_user_id).map(|mut owner_ship| {
+                owner_ship.credits += toll;
+                owner_ship
+            });
+            report.toll_paid = toll;
+            report.is_entered = true;
+        },
+        EncounterResponse::Fight => {
+            if deployment.fighters > 0 && ship.fighters == 0 {
+                return Err("You have no fighters with which to fight".to_string());
+            }
+            let (mut combatant, mines_detonated) = combat::detonate_mines(Combatant::from_ship(&ship), deployment.mines, rng);
+            deployment.mines -= mines_detonated;
+            report.mines_detonated = mines_detonated;
+            report.is_entered = true;
+            if combatant.hull > 0 && deployment.fighters > 0 {
+                let battle = combat::resolve_battle(combatant, Combatant::from_deployment(&deployment), rng);
+                combatant = battle.attacker;
+                deployment.fighters = battle.defender.fighters;
+                report.is_entered = battle.winner == BattleSide::Attacker;
+                report.battle = Some(battle);
+            }
+
+            ship.fighters = combatant.fighters;
+            ship.shields = combatant.shields;
+            ship.hull = combatant.hull;
+            if ship.hull == 0 {
+                ship.wreck();
+                report.is_entered = false;
+                report.is_wrecked = true;
+            }
+        },
+        EncounterResponse::Retreat => (),
+    }
+
+    if report.is_entered {
+        ship.sector_id = to_sector_id;
+    }
+
+    let transaction = database.transaction().map_err(|e| format!("Cannot enter sector:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, ship::MOVE_REQUEST_COST)?;
+    ship::update_ship(&transaction, &ship)?;
+    if let Some(owner_ship) = &owner_ship {
+        ship::update_ship(&transaction, owner_ship)?;
+    }
+    update_deployment(&transaction, &deployment)?;
+    transaction.commit().map_err(|e| format!("Cannot enter sector:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    ship::publish_ship(ship.clone());
+    if let Some(owner_ship) = owner_ship {
+        ship::publish_ship(owner_ship);
+    }
+    publish_deployment(deployment.clone());
+
+    report.ship = ship;
+    report.deployment = deployment;
+    report.notify(database);
+    Ok(report)
+}
+
+// Gets a clone of the deployment in the given sector, if there is one
+pub fn get_deployment(sector_id: SectorId) -> Option<Deployment> {
+    DEPLOYMENTS.lock().unwrap().get(&sector_id).cloned()
+}
+
+// Gets a clone of the deployment in the given sector, if there is one which is hostile to the given user
+pub fn get_hostile_deployment(sector_id: SectorId, user_id: UserId) -> Option<Deployment> {
+    get_deployment(sector_id).filter(|deployment| deployment.owner_user_id != user_id)
+}
+
+/// Finds all the sectors guarded by deployments which are hostile to the given user - for instance,
+/// to be avoided by galaxy::Galaxy::find_shortest_path_avoiding()
+pub fn get_hostile_sector_ids(user_id: UserId) -> HashSet<SectorId> {
+    DEPLOYMENTS.lock().unwrap().values()
+        .filter(|deployment| deployment.owner_user_id != user_id)
+        .map(|deployment| deployment.sector_id)
+        .collect()
+}
+
+/// Creates a deployment map describing everything deployed in the universe - Used when a game starts up.
+pub fn load_deployments(database: &Connection) -> Result<(), String> {
+    DEPLOYMENTS.lock().unwrap().clear();
+
+    match || -> rusqlite::Result<()> {
+        let mut stmt = database.prepare("SELECT sectorId, ownerUserId, fighters, mines FROM sector_deployments")?;
+        let deployment_iter = stmt.query_map([], |row| {
+            Ok(Deployment {
+                sector_id: row.get(0)?,
+                owner_user_id: row.get(1)?,
+                fighters: row.get(2)?,
+                mines: row.get(3)?,
+            })
+        })?;
+
+        for deployment_result in deployment_iter {
+            let deployment = deployment_result?;
+            DEPLOYMENTS.lock().unwrap().insert(deployment.sector_id, deployment);
+        }
+        log_info!("Loaded {} deployments", DEPLOYMENTS.lock().unwrap().len());
+        Ok(())
+    }() {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Cannot load deployments:{}", e)),
+    }
+}
+
+// Writes the given deployment to the database. A deployment with nothing left in it is removed altogether,
+// leaving the sector unguarded. Our in-memory copy is left alone - see publish_deployment().
+fn update_deployment(database: &Connection, deployment: &Deployment) -> Result<(), String> {
+    match || -> rusqlite::Result<()> {
+        if deployment.is_empty() {
+            database.execute("DELETE FROM sector_deployments WHERE sectorId = ?1;", params![deployment.sector_id])?;
+        } else {
+            let statement = "INSERT OR REPLACE INTO sector_deployments (sectorId, ownerUserId, fighters, mines) \
+                            VALUES (?1, ?2, ?3, ?4);";
+            database.execute(statement, params![deployment.sector_id, deployment.owner_user_id,
+                deployment.fighters, deployment.mines])?;
+        }
+        Ok(())
+    }() {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Cannot update deployment:{}", e)),
+    }
+}
+
+// Replaces our in-memory copy of the deployment (or removes it, if there is nothing left in it) -
+// to be called only after the deployment's changes are committed.
+fn publish_deployment(deployment: Deployment) {
+    let mut lock = DEPLOYMENTS.lock().unwrap();
+    if deployment.is_empty() {
+        lock.remove(&deployment.sector_id);
+    } else {
+        lock.insert(deployment.sector_id, deployment);
+    }
+}
+
+impl Deployment {
+    pub fn is_empty(&self) -> bool {
+        self.fighters == 0 && self.mines == 0
+    }
+
+    /// The price, in credits, of passing this deployment unharmed
+    pub fn get_toll(&self) -> i64 {
+        self.fighters as i64 * TOLL_PER_FIGHTER + self.mines as i64 * TOLL_PER_MINE
+    }
+
+    pub fn get_owner_name(&self) -> String {
+        user::get_user(self.owner_user_id).map(|user| user.game_name).unwrap_or_default()
+    }
+
+    /// A one-line description of the deployment, such as "10 fighters and 5 mines belonging to Neo"
+    pub fn get_description(&self) -> String {
+        format!("{} fighters and {} mines belonging to {}", self.fighters, self.mines, self.get_owner_name())
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "sector_id": self.sector_id,
+            "owner_user_id": self.owner_user_id,
+            "owner_name": self.get_owner_name(),
+            "fighters": self.fighters,
+            "mines": self.mines,
+            "toll": self.get_toll(),
+        })
+    }
+}
+
+impl EncounterResponse {
+    pub fn from_name(name: &str) -> Option<EncounterResponse> {
+        match name.trim().to_lowercase().as_str() {
+            "toll" | "pay" => Some(EncounterResponse::PayToll),
+            "fight" | "attack" => Some(EncounterResponse::Fight),
+            "retreat" => Some(EncounterResponse::Retreat),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EncounterResponse::PayToll => "Pay toll",
+            EncounterResponse::Fight => "Fight",
+            EncounterResponse::Retreat => "Retreat",
+        }
+    }
+}
+
+impl EncounterReport {
+    /// Creates a vector of strings describing the encounter, from the point of view of the ship's owner
+    pub fn get_description(&self) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        match self.response {
+            EncounterResponse::PayToll => result.push(format!("Paid a toll of {} credits to enter sector {}", self.toll_paid, self.sector_id)),
+            EncounterResponse::Retreat => result.push(format!("Turned back from sector {}", self.sector_id)),
+            EncounterResponse::Fight => {
+                if self.mines_detonated > 0 {
+                    result.push(format!("{} mines detonated against your ship", self.mines_detonated));
+                }
+                if let Some(battle) = &self.battle {
+                    result.extend(battle.get_description());
+                }
+                if self.is_entered {
+                    result.push(format!("You fought your way into sector {}", self.sector_id));
+                } else if self.is_wrecked {
+                    result.push(format!("Your ship was wrecked, and towed to sector {}", self.ship.sector_id));
+                } else {
+                    result.push(format!("You were driven back from sector {}", self.sector_id));
+                }
+            },
+        }
+        result.extend(self.ship.get_description());
+        result
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "sector_id": self.sector_id,
+            "response": self.response.name(),
+            "is_entered": self.is_entered,
+            "is_wrecked": self.is_wrecked,
+            "toll_paid": self.toll_paid,
+            "mines_detonated": self.mines_detonated,
+            "battle": self.battle.map(|battle| battle.to_json()),
+            "deployment": self.deployment.to_json(),
+            "ship": self.ship.to_json(),
+        })
+    }
+
+    // Tells the deployment's owner what happened.
+    // The encounter has already been committed by now, so failing to send is only worth a warning.
+    fn notify(&self, database: &Connection) {
+        let visitor_name = user::get_user(self.ship.owner_user_id).map(|user| user.game_name).unwrap_or_default();
+        let text = match self.response {
+            EncounterResponse::PayToll => format!("{} paid a toll of {} credits to enter sector {}", visitor_name, self.toll_paid, self.sector_id),
+            EncounterResponse::Retreat => format!("{} turned back from sector {}", visitor_name, self.sector_id),
+            EncounterResponse::Fight => {
+                let outcome = if self.is_entered {
+                    "fought their way into"
+                } else if self.is_wrecked {
+                    "was wrecked trying to enter"
+                } else {
+                    "was driven back from"
+                };
+                format!("{} {} sector {}. {} mines detonated; {} fighters and {} mines remain.",
+                        visitor_name, outcome, self.sector_id, self.mines_detonated,
+                        self.deployment.fighters, self.deployment.mines)
+            },
+        };
+
+        match message::create_message(database, user::ADMIN_USER_ID, self.deployment.owner_user_id, &text) {
+            Ok(msg) => message::publish_message(msg),
+            Err(e) => log_warn!("Cannot send encounter report to user {}:{}", self.deployment.owner_user_id, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::MutexGuard;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use crate::database::testing;
+    use crate::sector;
+
+    const SEED: u64 = 7;
+
+    // A game with two players whose ships are in the root sector of a small galaxy: an intruder carrying
+    // the given fighters and shields, and a guard who has deployed the given fighters and mines next door.
+    struct Encounter {
+        database: Connection,
+        intruder_user_id: UserId,
+        guard_user_id: UserId,
+        guarded_sector_id: SectorId,
+    }
+
+    fn set_up(intruder_fighters: i32, intruder_shields: i32, fighters: i32, mines: i32) -> (MutexGuard<'static, ()>, Encounter) {
+        let (game, database) = testing::create_game();
+        let galaxy_id = galaxy::create_conventional_galaxy(&database, "Testing".to_string(), 20, 15, 10, Some(SEED)).unwrap();
+        let intruder_user_id = user::create_normal_user(&database, "intruder".to_string(), "x".to_string(), "Intruder".to_string()).unwrap();
+        let guard_user_id = user::create_normal_user(&database, "guard".to_string(), "x".to_string(), "Guard".to_string()).unwrap();
+
+        let root_sector_id = galaxy::get_galaxy(galaxy_id).unwrap().get_root_sector_id().unwrap();
+        let mut links: Vec<SectorId> = sector::get_sector(root_sector_id).unwrap().sector_links.into_iter().collect();
+        links.sort();
+        let guarded_sector_id = links[0];
+
+        let mut intruder = ship::get_ship_for_user(intruder_user_id).unwrap();
+        intruder.fighters = intruder_fighters;
+        intruder.shields = intruder_shields;
+        ship::update_ship(&database, &intruder).unwrap();
+        ship::publish_ship(intruder);
+        let deployment = Deployment { sector_id: guarded_sector_id, owner_user_id: guard_user_id, fighters, mines };
+        update_deployment(&database, &deployment).unwrap();
+        publish_deployment(deployment);
+
+        (game, Encounter { database, intruder_user_id, guard_user_id, guarded_sector_id })
+    }
+
+    fn enter(encounter: &mut Encounter, response: EncounterResponse) -> EncounterReport {
+        enter_sector(&mut encounter.database, encounter.intruder_user_id, encounter.guarded_sector_id, response,
+                     &mut ChaCha8Rng::seed_from_u64(SEED)).unwrap()
+    }
+
+    #[test]
+    fn paying_the_toll_enters_the_sector() {
+        let (_game, mut encounter) = set_up(0, 0, 10, 20);
+        let report = enter(&mut encounter, EncounterResponse::PayToll);
+        assert!(report.is_entered);
+        assert_eq!(report.toll_paid, 10 * TOLL_PER_FIGHTER + 20 * TOLL_PER_MINE);
+        assert_eq!(report.ship.sector_id, encounter.guarded_sector_id);
+        assert_eq!(report.ship.credits, ship::STARTING_CREDITS - report.toll_paid);
+        assert_eq!(ship::get_ship_for_user(encounter.guard_user_id).unwrap().credits, ship::STARTING_CREDITS + report.toll_paid);
+        assert_eq!(get_deployment(encounter.guarded_sector_id).unwrap().fighters, 10);
+    }
+
+    #[test]
+    fn retreating_stays_put_but_spends_fuel() {
+        let (_game, mut encounter) = set_up(0, 0, 10, 20);
+        let before = ship::get_ship_for_user(encounter.intruder_user_id).unwrap();
+        let report = enter(&mut encounter, EncounterResponse::Retreat);
+        assert!(!report.is_entered);
+        assert_eq!(report.ship.sector_id, before.sector_id);
+        assert_eq!(report.ship.fuel, before.fuel - before.hull_class.fuel_per_move());
+        assert_eq!(ship::get_ship_for_user(encounter.intruder_user_id).unwrap().fuel, report.ship.fuel);
+    }
+
+    #[test]
+    fn fighting_through_destroys_the_fighters() {
+        let (_game, mut encounter) = set_up(50, 100, 10, 5);
+        let report = enter(&mut encounter, EncounterResponse::Fight);
+        assert!(report.is_entered);
+        assert!(!report.is_wrecked);
+        assert_eq!(report.mines_detonated, 4);
+        let battle = report.battle.unwrap();
+        assert_eq!((battle.winner, battle.rounds), (BattleSide::Attacker, 1));
+        assert_eq!((report.ship.fighters, report.ship.shields, report.ship.hull), (50, 33, 150));
+        assert_eq!(report.ship.sector_id, encounter.guarded_sector_id);
+        let deployment = get_deployment(encounter.guarded_sector_id).unwrap();
+        assert_eq!((deployment.fighters, deployment.mines), (0, 1));
+    }
+
+    #[test]
+    fn fighters_drive_back_a_weaker_ship() {
+        let (_game, mut encounter) = set_up(5, 0, 40, 0);
+        let before = ship::get_ship_for_user(encounter.intruder_user_id).unwrap();
+        let report = enter(&mut encounter, EncounterResponse::Fight);
+        assert!(!report.is_entered);
+        assert!(!report.is_wrecked);
+        assert_eq!(report.mines_detonated, 0);
+        let battle = report.battle.unwrap();
+        assert_eq!((battle.winner, battle.rounds), (BattleSide::Defender, 1));
+        assert_eq!((report.ship.fighters, report.ship.shields, report.ship.hull), (0, 0, 122));
+        assert_eq!(report.ship.sector_id, before.sector_id);
+        assert_eq!(get_deployment(encounter.guarded_sector_id).unwrap().fighters, 36);
+    }
+}
@@ -1,21 +1,93 @@
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use rusqlite::{params, Connection};
-use std::collections::{HashMap, HashSet, LinkedList};
-use std::sync::{LazyLock, Mutex};
-use crate::{port, sector};
-use crate::sector::SectorId;
+use serde_json::{json, Value};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, LinkedList, VecDeque};
+use std::sync::{Arc, LazyLock, Mutex};
+use crate::{config, planet, port, sector};
+use crate::port::{Port, PortId};
+use crate::{log_debug, log_info, log_warn};
+use crate::sector::{SectorId, MAX_LINKS_PER_SECTOR};
 
 pub type GalaxyId = usize;
 
+pub const DEFAULT_DISTANCE_LIMIT: isize = 20;
+pub const DEFAULT_SECTORS_PER_PORT: usize = 15;
+pub const DEFAULT_SECTORS_PER_PLANET: usize = 25;
+
 static NEXT_GALAXY_ID: LazyLock<Mutex<GalaxyId>> = LazyLock::new(|| Mutex::new(1));
 static GALAXIES: LazyLock<Mutex<HashMap<GalaxyId, Galaxy>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+static DISTANCE_CACHE: LazyLock<Mutex<DistanceCache>> = LazyLock::new(|| Mutex::new(DistanceCache::default()));
+
+// Distances (in hops) from a sector to every sector reachable from it
+type DistanceMap = HashMap<SectorId, usize>;
+
+// Distances from each sector for which they have been requested, computed on demand.
+// The whole cache is discarded whenever the links change (as indicated by sector::get_link_generation()).
+#[derive(Default)]
+struct DistanceCache {
+    link_generation: u64,
+    distances_from: HashMap<SectorId, Arc<DistanceMap>>,
+}
 
+/// The scheme by which a galaxy was generated - this determines which topology rules it should follow
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GalaxyKind {
+    Conventional,
+    Tree,
+}
+
+#[derive(Clone)]
 pub struct Galaxy {
     galaxy_id: GalaxyId,
     galaxy_name: String,
+    kind: GalaxyKind,
+    seed: Option<u64>, // seed from which the galaxy was generated, if it was generated
     sector_ids: HashSet<SectorId>,
 }
 
+/// A breach of one of the topology rules which galaxy generation is meant to guarantee - see Galaxy::validate()
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GalaxyViolation {
+    UnreachableFromRoot { sector_ids: Vec<SectorId> },
+    CannotReachRoot { sector_ids: Vec<SectorId> },
+    TooManyDeadEnds { count: usize, limit: usize },
+    TooFarFromRoot { sector_id: SectorId, distance: usize, limit: usize },
+    TooManyLinks { sector_id: SectorId, link_count: usize },
+    LinkOutsideGalaxy { sector_id: SectorId, to_sector_id: SectorId },
+}
+
+/// Statistics describing the shape of a galaxy - see Galaxy::get_stats().
+/// Distances are in hops, following links in their given direction.
+pub struct GalaxyStats {
+    pub sector_count: usize,
+    pub link_count: usize, // one-way links count once, two-way links twice
+    pub port_count: usize,
+    pub degree_histogram: BTreeMap<usize, usize>, // number of sectors having each number of links
+    pub dead_end_count: usize,
+    pub diameter: usize, // longest shortest path between any two sectors
+    pub root_eccentricity: usize, // longest shortest path from the root sector to any sector
+    pub port_spacing: Option<PortSpacing>, // None if there are fewer than two ports
+}
+
+/// The validation results and statistics for a galaxy, for reporting to an admin
+pub struct GalaxyReport {
+    pub galaxy_id: GalaxyId,
+    pub galaxy_name: String,
+    pub kind: GalaxyKind,
+    pub seed: Option<u64>,
+    pub stats: GalaxyStats,
+    pub violations: Vec<GalaxyViolation>,
+}
+
+/// For each port, the distance to the nearest other port, summarized
+pub struct PortSpacing {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+}
+
 /// Creates a legacy Galaxy, and incorporates it into the universe.
 /// Such a galaxy has a fixed number of sector. It has a root sector, at sector ID 1.
 /// 1) Create the root sector
@@ -25,24 +97,35 @@ pub struct Galaxy {
 /// Note that connecting one disjoint sector may indirectly connect multiple other disjoint sectors.
 /// 4) Locate any sectors which are greater than some fixed distance from sector 1,
 /// and link them one-way to sector 1 (this ensuring that any ship in the galaxy is no further
-/// than this distance from sector 1, which will contain a port with fuel).
+/// than this distance from sector 1, where fuel can be bought - see ship::refuel_ship).
 ///
 /// # Arguments
 /// * `database` a connected database
 /// * `galaxy_name` admin-supplied galaxy name. Be creative.
 /// * `sector_count` number of sectors to be created for this galaxy
+/// * `sectors_per_port` one port is created for (roughly) this many sectors
+/// * `sectors_per_planet` one planet is created for (roughly) this many sectors
+/// * `seed` seed for the random number generator - if None, a seed is chosen at random.
+///   Either way, the seed is recorded with the galaxy. Given the same seed and the same pre-existing
+///   sectors, ports, and planets, the same galaxy (including its ports and planets) is generated.
 pub fn create_conventional_galaxy(database: &Connection,
                                   galaxy_name: String,
-                                  sector_count: usize) -> Result<GalaxyId, String> {
+                                  sector_count: usize,
+                                  sectors_per_port: usize,
+                                  sectors_per_planet: usize,
+                                  seed: Option<u64>) -> Result<GalaxyId, String> {
     let mut next_galaxy_id = NEXT_GALAXY_ID.lock().unwrap();
     let galaxy_id = *next_galaxy_id;
     *next_galaxy_id += 1;
 
-    let mut galaxy = Galaxy{galaxy_id, galaxy_name, sector_ids: HashSet::new()};
+    let seed = seed.unwrap_or_else(|| rand::rng().random());
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut galaxy = Galaxy{galaxy_id, galaxy_name, kind: GalaxyKind::Conventional, seed: Some(seed), sector_ids: HashSet::new()};
+    log_info!("Generating galaxy {} with seed {}", galaxy.galaxy_name, seed);
 
     // create all the sectors first.
     // start with root sector, then do all the rest.
-    println!("Creating {} sectors...", sector_count);
+    log_info!("Creating {} sectors...", sector_count);
     let root_sector_id = sector::create_sector(database)?;
     galaxy.sector_ids.insert(root_sector_id);
 
@@ -56,9 +139,8 @@ pub fn create_conventional_galaxy(database: &Connection,
     // Now do initial random-ish linking. For each sector, link to another sector with a
     // sector-id within 10 (inclusive) above or below. Don't allow any sector to have
     // more than the max allowable number of links.
-    println!("Linking sectors...");
+    log_info!("Linking sectors...");
     let highest_sector_id = (root_sector_id + sector_count - 1) as SectorId;
-    let mut rng = rand::rng();
     for sector_id in root_sector_id..(last_sector_id + 1) as SectorId {
         if !sector::get_sector(sector_id).unwrap().has_max_links() {
             loop {
@@ -78,8 +160,12 @@ pub fn create_conventional_galaxy(database: &Connection,
                     }
 
                     if sector_id != target_id as SectorId {
-                        if !sector::get_sector(target_id as SectorId).unwrap().has_max_links() {
-                            sector::link_sectors(sector_id, target_id as SectorId);
+                        let target = sector::get_sector(target_id as SectorId).unwrap();
+                        if target.sector_links.contains(&sector_id) {
+                            break; // already linked, which will do
+                        }
+                        if !target.has_max_links() {
+                            sector::link_sectors(database, sector_id, target_id as SectorId)?;
                             break;
                         }
                     }
@@ -90,7 +176,7 @@ pub fn create_conventional_galaxy(database: &Connection,
 
     // Separate the mess into disjoint graphs, then link the disjoint parts so that we
     // have one completely connected graph.
-    println!("Cross-linking disjoint globs...");
+    log_info!("Cross-linking disjoint globs...");
     let mut disjoint_sets = galaxy.create_disjoint_sector_sets(root_sector_id);
 
     // Note that we don't need to merge these globs - they're going away almost immediately.
@@ -101,15 +187,16 @@ pub fn create_conventional_galaxy(database: &Connection,
         let disjoint_glob = disjoint_sets.pop_front().unwrap();
 
         // choose a random sector from the base glob and the disjoint glob and link them.
+        // (The main glob always has room - its sectors are all linked, and cannot all be at max links.)
         let mut ix = rng.random_range(0..main_glob.len());
-        while main_glob[ix] == root_sector_id {
+        while main_glob[ix] == root_sector_id || sector::get_sector(main_glob[ix]).unwrap().has_max_links() {
             ix = rng.random_range(0..main_glob.len());
         }
         let sector_id1 = main_glob[ix];
         let iy = rng.random_range(0..disjoint_glob.len());
         let sector_id2 = disjoint_glob[iy];
-        println!("  Linking disjoint {} to {}", sector_id2, sector_id1);
-        sector::link_sectors(sector_id1, sector_id2);
+        log_debug!("  Linking disjoint {} to {}", sector_id2, sector_id1);
+        sector::link_sectors(database, sector_id1, sector_id2)?;
 
         for disjoint_sector_id in disjoint_glob {
             main_glob.push(disjoint_sector_id);
@@ -118,9 +205,10 @@ pub fn create_conventional_galaxy(database: &Connection,
 
     // Find all the dead-ends. We like dead-ends because things can hide there.
     // But we don't want too many of them.
-    println!("Cross-linking excess dead ends...");
+    log_info!("Cross-linking excess dead ends...");
+    // (We visit the sectors in order, so that the pairing of dead ends is reproducible.)
     let mut dead_ends = LinkedList::<SectorId>::new();
-    for sector_id in galaxy.sector_ids.clone() {
+    for sector_id in galaxy.get_sorted_sector_ids() {
         if sector::get_sector(sector_id).unwrap().get_link_count() == 1 {
             dead_ends.push_back(sector_id);
         }
@@ -129,26 +217,7 @@ pub fn create_conventional_galaxy(database: &Connection,
     while dead_ends.len() > sector_count / 10 {
         let sector_id1 = dead_ends.pop_front().unwrap();
         let sector_id2 = dead_ends.pop_front().unwrap();
-        sector::link_sectors(sector_id1, sector_id2);
-    }
-
-    // breadth-first recursion - external caller should invoke with base_id set to root sector id,
-    // and the distances map pre-populated with key of root sector id having value of zero.
-    // We iterate over the neighbors setting them in the map with the distance one more than
-    // that of the given base sector, then iterate again, recursing.
-    fn distance_func(sector_ids: &HashSet<SectorId>, distances: &mut HashMap<SectorId, isize>, base_id: SectorId) {
-        let neighbor_distance = distances.get(&base_id).unwrap() + 1;
-        let mut neighbors_to_visit: HashSet<SectorId> = HashSet::new();
-        for neighbor_sector_id in sector::get_sector(base_id).unwrap().sector_links {
-            if !distances.contains_key(&neighbor_sector_id) {
-                neighbors_to_visit.insert(neighbor_sector_id);
-                distances.insert(neighbor_sector_id, neighbor_distance);
-            }
-        }
-
-        for neighbor_sector_id in neighbors_to_visit.iter() {
-            distance_func(sector_ids, distances, *neighbor_sector_id);
-        }
+        sector::link_sectors(database, sector_id1, sector_id2)?;
     }
 
     fn distance_recalculate_func(galaxy: &Galaxy, distances: &mut HashMap<SectorId, isize>, base_id: SectorId) {
@@ -168,47 +237,53 @@ pub fn create_conventional_galaxy(database: &Connection,
     // Create a map of sectors and their distance from the root sector.
     // Note that we're really interested in the distance from that sector to the root,
     // not vice versa. At this point however, these two values are the same.
-    // This should really be a closure since rust functions cannot see things in their containing scope...
-    // but closures in rust cannot recurse, and we need to do that.
-    let mut distances = HashMap::<SectorId, isize>::new();
-    distances.insert(root_sector_id, 0);
-    distance_func(&galaxy.sector_ids, &mut distances, root_sector_id);
+    let mut distances = galaxy.get_distances_from(root_sector_id);
 
     // Look at the distances. As we find sectors which are too far from the root sector,
     // link them one-way thereto, then recalculate distances for proximate sectors so we don't
     // link more than we have to. This should also be a closure, but...
-    println!("Creating one-way links back to root sector if/as necessary...");
-    const DISTANCE_LIMIT: isize = 20;
+    log_info!("Creating one-way links back to root sector if/as necessary...");
+    let distance_limit = config::get_config().galaxy.distance_limit;
     for sector_id in root_sector_id..(last_sector_id + 1) as SectorId {
-        if distances[&sector_id] > DISTANCE_LIMIT {
-            println!("  Linking sector {} to root sector", sector_id);
-            sector::get_sector(sector_id).unwrap().insert_link_to(root_sector_id);
+        if distances[&sector_id] > distance_limit {
+            if sector::get_sector(sector_id).unwrap().has_max_links() {
+                log_warn!("  Sector {} is too far from the root sector, but has no room for another link", sector_id);
+                continue;
+            }
+            log_debug!("  Linking sector {} to root sector", sector_id);
+            sector::link_sector_one_way(database, sector_id, root_sector_id)?;
             distances.insert(sector_id, 1);
             distance_recalculate_func(&galaxy, &mut distances, sector_id);
         }
     }
 
-    // Create some ports. We create 1 port per 15 sectors,
+    // Create some ports. By default we create 1 port per 15 sectors (see GalaxyConfig::sectors_per_port),
     // so a galaxy of 1000 sectors would contain 66 ports.
     // Ports are randomly assigned to sectors according to the following rules:
     // * the sector must be at least 3 sectors from the root
     // * a sector can have at most one port.
-    println!("Creating ports...");
-    let mut remaining = sector_count / 15;
+    log_info!("Creating ports...");
+    let eligible_count = distances.values().filter(|&&distance| distance >= 3).count();
+    let mut remaining = (sector_count / sectors_per_port).min(eligible_count);
     while remaining > 0 {
         let sector_id = rng.random_range(root_sector_id..(last_sector_id + 1) as SectorId);
         let sector = sector::get_sector(sector_id).unwrap();
         if !sector.has_port() && distances[&sector_id] >= 3 {
-            let new_port_id = port::create_port(database)?;
+            let new_port_id = port::create_port(database, &mut rng)?;
             let new_port = port::get_port(new_port_id).unwrap();
-            println!("Port {} ({}) is at sector {}", new_port_id, new_port.port_name, sector_id);
-            sector::set_sector_port_id(sector_id, new_port_id);
+            log_info!("Port {} ({}) is at sector {}", new_port_id, new_port.port_name, sector_id);
+            sector::assign_port(database, sector_id, new_port_id)?;
             remaining -= 1;
         }
     }
 
+    create_planets(database, &galaxy, sectors_per_planet, &mut rng)?;
+
     match galaxy.persist(database) {
-        Ok(_) => Ok(galaxy_id),
+        Ok(_) => {
+            GALAXIES.lock().unwrap().insert(galaxy_id, galaxy);
+            Ok(galaxy_id)
+        },
         Err(e) => Err(e.to_string()),
     }
 }
@@ -218,41 +293,58 @@ pub fn create_conventional_galaxy(database: &Connection,
 /// number of branches to child sectors (see branch_count), excepting the final sectors
 /// at the conceptual edge of the galaxy. Each sector excluding the root sector will also
 /// have a link back to its root branch, for a total number of n+1 links, where n is branch_count.
-/// We guarantee at least sector_count sectors, but we may create a few additional sectors.
+/// We guarantee at least sector_count sectors, but we may create a few additional sectors
+/// (we always give a sector all of its branches).
 ///
 /// # Arguments
 /// * `database` a connected database
 /// * `galaxy_name` admin-supplied galaxy name. Be creative.
-/// * `branch_count` number of branches per sector
+/// * `branch_count` number of branches per sector - 1 to 5, as each sector also links back to its parent
 /// * `sector_count` least number of sectors to be created for this galaxy
+/// * `sectors_per_port` one port is created for (roughly) this many sectors
+/// * `sectors_per_planet` one planet is created for (roughly) this many sectors
+/// * `seed` seed for the random number generator - if None, a seed is chosen at random.
+///   Either way, the seed is recorded with the galaxy.
 pub fn create_tree_galaxy(database: &Connection,
                           galaxy_name: String,
                           branch_count: usize,
-                          sector_count: usize) -> Result<GalaxyId, String> {
+                          sector_count: usize,
+                          sectors_per_port: usize,
+                          sectors_per_planet: usize,
+                          seed: Option<u64>) -> Result<GalaxyId, String> {
+    if branch_count == 0 || branch_count >= MAX_LINKS_PER_SECTOR {
+        return Err(format!("Branch count must be from 1 to {}", MAX_LINKS_PER_SECTOR - 1));
+    }
+
     let mut next_galaxy_id = NEXT_GALAXY_ID.lock().unwrap();
     let galaxy_id = *next_galaxy_id;
     *next_galaxy_id += 1;
 
-    let mut galaxy = Galaxy{galaxy_id, galaxy_name, sector_ids: HashSet::new()};
+    let seed = seed.unwrap_or_else(|| rand::rng().random());
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut galaxy = Galaxy{galaxy_id, galaxy_name, kind: GalaxyKind::Tree, seed: Some(seed), sector_ids: HashSet::new()};
+    log_info!("Generating galaxy {} with seed {}", galaxy.galaxy_name, seed);
 
-    println!("Creating ~{} sectors...", sector_count);
-    let mut base_sector_id = sector::create_sector(database)?;
-    galaxy.sector_ids.insert(base_sector_id);
+    log_info!("Creating ~{} sectors...", sector_count);
+    let root_sector_id = sector::create_sector(database)?;
+    galaxy.sector_ids.insert(root_sector_id);
 
-    let mut last_sector_id = base_sector_id;
-    for _ in 1..sector_count {
+    // Sector ids are handed out in sequence, so each sector in turn becomes the base of the next branches.
+    let mut base_sector_id = root_sector_id;
+    let mut last_sector_id = root_sector_id;
+    while galaxy.sector_ids.len() < sector_count {
         for _ in 0..branch_count {
-            let new_sector_id = sector::create_sector(database)?;
-            sector::link_sectors(base_sector_id, new_sector_id);
+            last_sector_id = sector::create_sector(database)?;
+            sector::link_sectors(database, base_sector_id, last_sector_id)?;
+            galaxy.sector_ids.insert(last_sector_id);
         }
 
-        last_sector_id = base_sector_id;
         base_sector_id += 1;
     }
 
-    // Create some ports. We create 1 port per 15 sectors,
+    // Create some ports. By default we create 1 port per 15 sectors (see GalaxyConfig::sectors_per_port),
     // so a galaxy of 1000 sectors would contain 66 ports.
-    // Ports are randomly assign to sectors according to the following rules:
+    // Ports are randomly assigned to sectors according to the following rules:
     // * the sector must be at least 3 sectors from the root sector.
     // * a sector can have at most one port.
     /*
@@ -261,27 +353,167 @@ pub fn create_tree_galaxy(database: &Connection,
         branch count 3:  1  2,3,4  5,6,7,8,9,10,11,12,13  -> 14 = 3**2 + 5
         branch count 4:  1  2,3,4,5  6,7,8,9,10,11,12,13,14,15,16,17,18,29,20,21  -> 22 = 4**2 + 6
      */
-    println!("Creating ports...");
-    let mut rng = rand::rng();
-    let lowest_target_sector_id = base_sector_id + (branch_count * branch_count) + branch_count + 2;
-    let mut remaining = sector_count / 15;
+    log_info!("Creating ports...");
+    let lowest_target_sector_id = root_sector_id + (branch_count * branch_count) + branch_count + 1;
+    let eligible_count = (last_sector_id + 1).saturating_sub(lowest_target_sector_id);
+    let mut remaining = (sector_count / sectors_per_port).min(eligible_count);
     while remaining > 0 {
         let sector_id = rng.random_range(lowest_target_sector_id..(last_sector_id + 1) as SectorId);
         if !sector::get_sector(sector_id).unwrap().has_port() {
-            let new_port_id = port::create_port(database)?;
+            let new_port_id = port::create_port(database, &mut rng)?;
             let new_port = port::get_port(new_port_id).unwrap();
-            println!("Port {} ({}) is at sector {}", new_port_id, new_port.port_name, sector_id);
-            sector::set_sector_port_id(sector_id, new_port_id);
+            log_info!("Port {} ({}) is at sector {}", new_port_id, new_port.port_name, sector_id);
+            sector::assign_port(database, sector_id, new_port_id)?;
             remaining -= 1;
         }
     }
 
+    create_planets(database, &galaxy, sectors_per_planet, &mut rng)?;
+
     match galaxy.persist(database) {
-        Ok(_) => Ok(galaxy_id),
+        Ok(_) => {
+            GALAXIES.lock().unwrap().insert(galaxy_id, galaxy);
+            Ok(galaxy_id)
+        },
         Err(e) => Err(e.to_string()),
     }
 }
 
+// Creates planets and places them in the galaxy's sectors - one planet for every sectors_per_planet sectors.
+// Planets are placed at random, in any sector other than the root sector, with at most one planet per sector.
+// (This is done after the ports are created, so that the links and ports generated from a given seed
+// do not depend on the number of planets.)
+fn create_planets(database: &Connection, galaxy: &Galaxy, sectors_per_planet: usize, rng: &mut impl Rng) -> Result<(), String> {
+    log_info!("Creating planets...");
+    let root_sector_id = galaxy.get_root_sector_id();
+    let mut eligible_sector_ids: Vec<SectorId> = galaxy.get_sorted_sector_ids().into_iter()
+        .filter(|sector_id| Some(*sector_id) != root_sector_id)
+        .collect();
+    let mut remaining = (galaxy.sector_ids.len() / sectors_per_planet.max(1)).min(eligible_sector_ids.len());
+    while remaining > 0 {
+        let sector_id = eligible_sector_ids.swap_remove(rng.random_range(0..eligible_sector_ids.len()));
+        let new_planet_id = planet::create_planet(database, None, rng)?;
+        let new_planet = planet::get_planet(new_planet_id).unwrap();
+        log_info!("Planet {} ({}) is at sector {}", new_planet_id, new_planet.planet_name, sector_id);
+        sector::assign_planet(database, sector_id, new_planet_id)?;
+        remaining -= 1;
+    }
+    Ok(())
+}
+
+// Gets a clone of a galaxy - only for looking at information, not for changing it
+pub fn get_galaxy(galaxy_id: GalaxyId) -> Option<Galaxy> {
+    GALAXIES.lock().unwrap().get(&galaxy_id).cloned()
+}
+
+pub fn get_galaxy_ids() -> Vec<GalaxyId> {
+    let mut galaxy_ids: Vec<GalaxyId> = GALAXIES.lock().unwrap().keys().copied().collect();
+    galaxy_ids.sort();
+    galaxy_ids
+}
+
+/// Finds a galaxy by name, ignoring case
+pub fn find_galaxy_by_name(galaxy_name: &str) -> Option<Galaxy> {
+    GALAXIES.lock().unwrap().values()
+        .find(|galaxy| galaxy.galaxy_name.to_lowercase() == galaxy_name.to_lowercase())
+        .cloned()
+}
+
+/// Identifies the galaxy in which new players begin - this is the galaxy with the lowest id.
+pub fn get_starting_galaxy_id() -> Option<GalaxyId> {
+    GALAXIES.lock().unwrap().keys().min().copied()
+}
+
+/// Finds the galaxy containing the given sector
+pub fn find_galaxy_for_sector(sector_id: SectorId) -> Option<Galaxy> {
+    GALAXIES.lock().unwrap().values().find(|galaxy| galaxy.contains_sector(sector_id)).cloned()
+}
+
+// Galaxy editing --------------------------------------------------------------------------------
+// These are for use by admins while the game is running, so each edit is checked to ensure that it
+// leaves the galaxy playable. They should be invoked from within database::with_database(), so that
+// edits cannot interleave.
+
+/// Links two sectors of the same galaxy, either both ways or only from the first sector to the second.
+/// Adding a link cannot disconnect anything, so we need only check that the sectors have room for it.
+pub fn add_link(database: &Connection, from_sector_id: SectorId, to_sector_id: SectorId, is_one_way: bool) -> Result<(), String> {
+    let galaxy = find_galaxy_for_sector(from_sector_id).ok_or(format!("Sector {} does not exist", from_sector_id))?;
+    if !galaxy.contains_sector(to_sector_id) {
+        return Err(format!("Sector {} is not in galaxy {}", to_sector_id, galaxy.galaxy_name));
+    }
+
+    if is_one_way {
+        sector::link_sector_one_way(database, from_sector_id, to_sector_id)
+    } else {
+        sector::link_sectors(database, from_sector_id, to_sector_id)
+    }
+}
+
+/// Removes the link between two sectors, either both ways or only from the first sector to the second.
+/// The link is not removed if doing so would leave any sector unreachable from the galaxy's root sector,
+/// or unable to reach it.
+pub fn remove_link(database: &Connection, from_sector_id: SectorId, to_sector_id: SectorId, is_one_way: bool) -> Result<(), String> {
+    let galaxy = find_galaxy_for_sector(from_sector_id).ok_or(format!("Sector {} does not exist", from_sector_id))?;
+
+    // try the change on a copy of the links first
+    let mut link_map = galaxy.get_link_map();
+    link_map.entry(from_sector_id).or_default().retain(|link| *link != to_sector_id);
+    if !is_one_way {
+        link_map.entry(to_sector_id).or_default().retain(|link| *link != from_sector_id);
+    }
+    galaxy.check_root_connectivity(&link_map)
+        .map_err(|e| format!("Cannot remove the link between sectors {} and {}: {}", from_sector_id, to_sector_id, e))?;
+
+    if is_one_way {
+        sector::unlink_sector_one_way(database, from_sector_id, to_sector_id)
+    } else {
+        sector::unlink_sectors(database, from_sector_id, to_sector_id)
+    }
+}
+
+/// Moves a port from the sector it is in to another sector of the same galaxy, which does not already have a port
+pub fn move_port(database: &Connection, port_id: PortId, to_sector_id: SectorId) -> Result<(), String> {
+    let from_sector_id = sector::find_sector_with_port(port_id).ok_or(format!("Port {} is not in any sector", port_id))?;
+    let galaxy = find_galaxy_for_sector(from_sector_id).ok_or(format!("Sector {} does not exist", from_sector_id))?;
+    if !galaxy.contains_sector(to_sector_id) {
+        return Err(format!("Sector {} is not in galaxy {}", to_sector_id, galaxy.galaxy_name));
+    }
+
+    sector::move_port(database, port_id, to_sector_id)
+}
+
+/// Renames a galaxy. Galaxy names must be unique, ignoring case.
+pub fn rename_galaxy(database: &Connection, galaxy_id: GalaxyId, galaxy_name: &str) -> Result<(), String> {
+    let galaxy_name = galaxy_name.trim();
+    if galaxy_name.is_empty() {
+        return Err("Galaxy name must not be empty".to_string());
+    }
+
+    let mut lock = GALAXIES.lock().unwrap();
+    if !lock.contains_key(&galaxy_id) {
+        return Err(format!("Galaxy {} does not exist", galaxy_id));
+    }
+    if lock.values().any(|galaxy| galaxy.galaxy_id != galaxy_id && galaxy.galaxy_name.eq_ignore_ascii_case(galaxy_name)) {
+        return Err(format!("There is already a galaxy named {}", galaxy_name));
+    }
+
+    let statement = "UPDATE galaxies SET galaxyName = ?1 WHERE galaxyId = ?2;";
+    database.execute(statement, params![galaxy_name, galaxy_id])
+        .map_err(|e| format!("Cannot rename galaxy {}:{}", galaxy_id, e))?;
+
+    lock.get_mut(&galaxy_id).unwrap().galaxy_name = galaxy_name.to_string();
+    Ok(())
+}
+
+/// Reloads the planets, ports, sectors, and galaxies from the database, discarding our in-memory copies.
+/// For use after rolling back a transaction in which any of them were edited.
+pub fn reload_galaxies(database: &Connection) -> Result<(), String> {
+    planet::load_planets(database)?;
+    port::load_ports(database)?;
+    sector::load_sectors(database)?;
+    load_galaxies(database)
+}
+
 /// Loads all the galaxies from the given database connection.
 /// Only to be invoked after loading all the ports, planets, and sectors.
 ///
@@ -291,9 +523,16 @@ pub fn load_galaxies(database: &Connection) -> Result<(), String> {
     GALAXIES.lock().unwrap().clear();
 
     match || -> rusqlite::Result<()> {
-        let mut stmt = database.prepare("SELECT galaxyId, galaxyName FROM galaxies ORDER BY galaxyId")?;
+        let mut stmt = database.prepare("SELECT galaxyId, galaxyName, galaxyKind, seed FROM galaxies ORDER BY galaxyId")?;
         let galaxy_iter = stmt.query_map([], |row| {
-            Ok(Galaxy { galaxy_id: row.get(0)?, galaxy_name: row.get(1)?, sector_ids: Default::default() })
+            let kind_code: i32 = row.get(2)?;
+            let seed: Option<i64> = row.get(3)?;
+            Ok(Galaxy {
+                galaxy_id: row.get(0)?,
+                galaxy_name: row.get(1)?,
+                kind: GalaxyKind::from_code(kind_code).unwrap_or(GalaxyKind::Conventional),
+                seed: seed.map(|seed| seed as u64),
+                sector_ids: Default::default() })
         })?;
 
         let mut highest_galaxy_id = 0;
@@ -315,7 +554,7 @@ pub fn load_galaxies(database: &Connection) -> Result<(), String> {
                 galaxy.sector_ids.insert(sector_id_result?);
             }
 
-            println!("Loaded galaxy {}:{} with {} sectors", galaxy.galaxy_id, galaxy.galaxy_name, galaxy.sector_ids.len());
+            log_info!("Loaded galaxy {}:{} with {} sectors", galaxy.galaxy_id, galaxy.galaxy_name, galaxy.sector_ids.len());
             GALAXIES.lock().unwrap().insert(galaxy.galaxy_id, galaxy);
         }
 
@@ -327,6 +566,166 @@ pub fn load_galaxies(database: &Connection) -> Result<(), String> {
     }
 }
 
+impl GalaxyKind {
+    /// Integer code used to represent the galaxy kind in the database
+    pub fn code(&self) -> i32 {
+        match self {
+            GalaxyKind::Conventional => 1,
+            GalaxyKind::Tree => 2,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Option<GalaxyKind> {
+        match code {
+            1 => Some(GalaxyKind::Conventional),
+            2 => Some(GalaxyKind::Tree),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GalaxyKind::Conventional => "conventional",
+            GalaxyKind::Tree => "tree",
+        }
+    }
+}
+
+impl GalaxyViolation {
+    pub fn get_description(&self) -> String {
+        match self {
+            GalaxyViolation::UnreachableFromRoot { sector_ids } =>
+                format!("{} sectors cannot be reached from the root sector: {}", sector_ids.len(), join_ids(sector_ids)),
+            GalaxyViolation::CannotReachRoot { sector_ids } =>
+                format!("{} sectors have no path to the root sector: {}", sector_ids.len(), join_ids(sector_ids)),
+            GalaxyViolation::TooManyDeadEnds { count, limit } =>
+                format!("{} dead ends exceeds the limit of {}", count, limit),
+            GalaxyViolation::TooFarFromRoot { sector_id, distance, limit } =>
+                format!("Sector {} is {} hops from the root sector, exceeding the limit of {}", sector_id, distance, limit),
+            GalaxyViolation::TooManyLinks { sector_id, link_count } =>
+                format!("Sector {} has {} links, exceeding the maximum of {}", sector_id, link_count, MAX_LINKS_PER_SECTOR),
+            GalaxyViolation::LinkOutsideGalaxy { sector_id, to_sector_id } =>
+                format!("Sector {} links to sector {}, which is not in the galaxy", sector_id, to_sector_id),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            GalaxyViolation::UnreachableFromRoot { sector_ids } =>
+                json!({"violation": "unreachable_from_root", "sector_ids": sector_ids}),
+            GalaxyViolation::CannotReachRoot { sector_ids } =>
+                json!({"violation": "cannot_reach_root", "sector_ids": sector_ids}),
+            GalaxyViolation::TooManyDeadEnds { count, limit } =>
+                json!({"violation": "too_many_dead_ends", "count": count, "limit": limit}),
+            GalaxyViolation::TooFarFromRoot { sector_id, distance, limit } =>
+                json!({"violation": "too_far_from_root", "sector_id": sector_id, "distance": distance, "limit": limit}),
+            GalaxyViolation::TooManyLinks { sector_id, link_count } =>
+                json!({"violation": "too_many_links", "sector_id": sector_id, "link_count": link_count}),
+            GalaxyViolation::LinkOutsideGalaxy { sector_id, to_sector_id } =>
+                json!({"violation": "link_outside_galaxy", "sector_id": sector_id, "to_sector_id": to_sector_id}),
+        }
+    }
+}
+
+impl GalaxyStats {
+    /// Creates a vector of strings to be sent to a user, describing the statistics
+    pub fn get_description(&self) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        result.push(format!("  Sectors: {}", self.sector_count));
+        result.push(format!("  Links: {}", self.link_count));
+        result.push(format!("  Ports: {}", self.port_count));
+        result.push(format!("  Dead ends: {}", self.dead_end_count));
+        result.push(format!("  Diameter: {}", self.diameter));
+        result.push(format!("  Eccentricity from root: {}", self.root_eccentricity));
+        match &self.port_spacing {
+            Some(spacing) => result.push(format!("  Port spacing: min {} max {} mean {:.2}",
+                                                 spacing.min, spacing.max, spacing.mean)),
+            None => result.push("  Port spacing: n/a".to_string()),
+        }
+        result.push("  Links per sector:".to_string());
+        for (degree, count) in self.degree_histogram.iter() {
+            result.push(format!("    {:>2}: {}", degree, count));
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> Value {
+        let histogram: Vec<Value> = self.degree_histogram.iter()
+            .map(|(degree, count)| json!({"links": degree, "sectors": count}))
+            .collect();
+        let port_spacing = self.port_spacing.as_ref()
+            .map(|spacing| json!({"min": spacing.min, "max": spacing.max, "mean": spacing.mean}));
+        json!({
+            "sector_count": self.sector_count,
+            "link_count": self.link_count,
+            "port_count": self.port_count,
+            "degree_histogram": histogram,
+            "dead_end_count": self.dead_end_count,
+            "diameter": self.diameter,
+            "root_eccentricity": self.root_eccentricity,
+            "port_spacing": port_spacing,
+        })
+    }
+}
+
+impl GalaxyReport {
+    /// Creates a vector of strings to be sent to a user, describing the report
+    pub fn get_description(&self) -> Vec<String> {
+        let seed = self.seed.map(|seed| seed.to_string()).unwrap_or("none".to_string());
+        let mut result: Vec<String> = Vec::new();
+        result.push(format!("Galaxy {}:{} ({}, seed {})", self.galaxy_id, self.galaxy_name, self.kind.name(), seed));
+        result.extend(self.stats.get_description());
+        if self.violations.is_empty() {
+            result.push("  No violations".to_string());
+        } else {
+            result.push(format!("  Violations: {}", self.violations.len()));
+            result.extend(self.violations.iter().map(|violation| format!("    {}", violation.get_description())));
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> Value {
+        let violations: Vec<Value> = self.violations.iter().map(|violation| violation.to_json()).collect();
+        json!({
+            "galaxy_id": self.galaxy_id,
+            "galaxy_name": self.galaxy_name,
+            "kind": self.kind.name(),
+            "seed": self.seed,
+            "stats": self.stats.to_json(),
+            "violations": violations,
+        })
+    }
+}
+
+// Formats a list of sector ids for display, abbreviating long lists
+fn join_ids(sector_ids: &[SectorId]) -> String {
+    const MAX_DISPLAYED: usize = 20;
+    let mut text = sector_ids.iter().take(MAX_DISPLAYED).map(|id| id.to_string()).collect::<Vec<String>>().join(" ");
+    if sector_ids.len() > MAX_DISPLAYED {
+        text.push_str(" ...");
+    }
+    text
+}
+
+// Finds the number of hops from the given sector to every sector reachable from it, given a map of
+// each sector to the sectors it links to. Sectors which are not reachable have no entry in the result.
+fn breadth_first_distances(link_map: &HashMap<SectorId, Vec<SectorId>>, from_sector_id: SectorId) -> HashMap<SectorId, usize> {
+    let mut distances = HashMap::<SectorId, usize>::new();
+    distances.insert(from_sector_id, 0);
+    let mut to_visit = VecDeque::<SectorId>::new();
+    to_visit.push_back(from_sector_id);
+    while let Some(sector_id) = to_visit.pop_front() {
+        let neighbor_distance = distances[&sector_id] + 1;
+        for neighbor_sector_id in link_map.get(&sector_id).into_iter().flatten() {
+            if !distances.contains_key(neighbor_sector_id) {
+                distances.insert(*neighbor_sector_id, neighbor_distance);
+                to_visit.push_back(*neighbor_sector_id);
+            }
+        }
+    }
+    distances
+}
+
 impl Galaxy {
     // segregates the sector map into disjoint globs.
     // The glob containing the root sector id will be the first in the vector.
@@ -341,7 +740,10 @@ impl Galaxy {
             if from_catalog.contains(&sector_id) {
                 to_set.push(sector_id);
                 from_catalog.remove(&sector_id);
-                for neighbor_sector_id in sector::get_sector(sector_id).unwrap().sector_links {
+                let mut neighbor_sector_ids: Vec<SectorId> =
+                    sector::get_sector(sector_id).unwrap().sector_links.into_iter().collect();
+                neighbor_sector_ids.sort();
+                for neighbor_sector_id in neighbor_sector_ids {
                     move_sector_id(sectors, neighbor_sector_id, from_catalog, to_set);
                 }
             }
@@ -351,8 +753,9 @@ impl Galaxy {
         move_sector_id(&self.sector_ids, root_sector_id, &mut unassigned_sectors, &mut disjoint_set);
         disjoint_sector_sets.push_back(disjoint_set);
 
+        // Sectors (and their neighbors) are visited in order, so that the globs are reproducible.
         loop {
-            let entry = { unassigned_sectors.iter().next() };
+            let entry = { unassigned_sectors.iter().min() };
             if entry.is_none() {
                 break
             }
@@ -366,6 +769,218 @@ impl Galaxy {
         disjoint_sector_sets
     }
 
+    /// Finds the number of hops from the given sector to every sector reachable from it, via a breadth-first search.
+    /// Sectors which are not reachable have no entry in the result.
+    pub fn get_distances_from(&self, from_sector_id: SectorId) -> HashMap<SectorId, isize> {
+        breadth_first_distances(&self.get_link_map(), from_sector_id).into_iter()
+            .map(|(sector_id, distance)| (sector_id, distance as isize))
+            .collect()
+    }
+
+    // Takes a snapshot of the links from each sector in the galaxy to the other sectors in the galaxy,
+    // so that we can traverse the galaxy without repeatedly locking (and cloning) sectors.
+    // Links to sectors outside the galaxy are omitted.
+    fn get_link_map(&self) -> HashMap<SectorId, Vec<SectorId>> {
+        sector::get_link_map(&self.sector_ids)
+    }
+
+    // Checks that, given the links in the link map, every sector can be reached from the root sector,
+    // and can reach the root sector.
+    fn check_root_connectivity(&self, link_map: &HashMap<SectorId, Vec<SectorId>>) -> Result<(), String> {
+        let sector_ids = self.get_sorted_sector_ids();
+        let Some(root_sector_id) = self.get_root_sector_id() else { return Ok(()) };
+
+        let from_root = breadth_first_distances(link_map, root_sector_id);
+        let unreachable: Vec<SectorId> = sector_ids.iter().filter(|id| !from_root.contains_key(id)).copied().collect();
+        if !unreachable.is_empty() {
+            return Err(format!("sectors {} would be unreachable from the root sector", join_ids(&unreachable)));
+        }
+
+        let mut reverse_link_map: HashMap<SectorId, Vec<SectorId>> = HashMap::new();
+        for (sector_id, links) in link_map {
+            for to_sector_id in links {
+                reverse_link_map.entry(*to_sector_id).or_default().push(*sector_id);
+            }
+        }
+        let to_root = breadth_first_distances(&reverse_link_map, root_sector_id);
+        let stranded: Vec<SectorId> = sector_ids.iter().filter(|id| !to_root.contains_key(id)).copied().collect();
+        if !stranded.is_empty() {
+            return Err(format!("sectors {} would be unable to reach the root sector", join_ids(&stranded)));
+        }
+        Ok(())
+    }
+
+    /// Checks the galaxy against the topology rules which galaxy generation is meant to guarantee:
+    /// * every sector can be reached from the root sector, and can reach the root sector
+    /// * no sector has more than the maximum number of links, and no link leads out of the galaxy
+    /// * for conventional galaxies, at most 10% of sectors are dead ends, and no sector is more than
+    ///   the configured distance limit from the root sector
+    ///
+    /// # Returns
+    /// The violations found - empty if the galaxy is valid
+    pub fn validate(&self) -> Vec<GalaxyViolation> {
+        let mut violations: Vec<GalaxyViolation> = Vec::new();
+        let sector_ids = self.get_sorted_sector_ids();
+        let Some(root_sector_id) = self.get_root_sector_id() else { return violations };
+
+        let mut dead_end_count = 0;
+        let mut reverse_link_map: HashMap<SectorId, Vec<SectorId>> = HashMap::new();
+        for sector_id in sector_ids.iter() {
+            let sector = sector::get_sector(*sector_id).unwrap();
+            if sector.get_link_count() > MAX_LINKS_PER_SECTOR {
+                violations.push(GalaxyViolation::TooManyLinks { sector_id: *sector_id, link_count: sector.get_link_count() });
+            }
+            if sector.get_link_count() == 1 {
+                dead_end_count += 1;
+            }
+
+            let mut links: Vec<SectorId> = sector.sector_links.into_iter().collect();
+            links.sort();
+            for to_sector_id in links {
+                if self.sector_ids.contains(&to_sector_id) {
+                    reverse_link_map.entry(to_sector_id).or_default().push(*sector_id);
+                } else {
+                    violations.push(GalaxyViolation::LinkOutsideGalaxy { sector_id: *sector_id, to_sector_id });
+                }
+            }
+        }
+
+        let from_root = breadth_first_distances(&self.get_link_map(), root_sector_id);
+        let unreachable: Vec<SectorId> = sector_ids.iter().filter(|id| !from_root.contains_key(id)).copied().collect();
+        if !unreachable.is_empty() {
+            violations.push(GalaxyViolation::UnreachableFromRoot { sector_ids: unreachable });
+        }
+
+        // Following the links backwards from the root gives us the distance from each sector to the root
+        let to_root = breadth_first_distances(&reverse_link_map, root_sector_id);
+        let stranded: Vec<SectorId> = sector_ids.iter().filter(|id| !to_root.contains_key(id)).copied().collect();
+        if !stranded.is_empty() {
+            violations.push(GalaxyViolation::CannotReachRoot { sector_ids: stranded });
+        }
+
+        if self.kind == GalaxyKind::Conventional {
+            let dead_end_limit = sector_ids.len() / 10;
+            if dead_end_count > dead_end_limit {
+                violations.push(GalaxyViolation::TooManyDeadEnds { count: dead_end_count, limit: dead_end_limit });
+            }
+
+            let distance_limit = config::get_config().galaxy.distance_limit.max(0) as usize;
+            for sector_id in sector_ids.iter() {
+                if let Some(distance) = to_root.get(sector_id) && *distance > distance_limit {
+                    violations.push(GalaxyViolation::TooFarFromRoot { sector_id: *sector_id, distance: *distance, limit: distance_limit });
+                }
+            }
+        }
+
+        violations
+    }
+
+    /// Validates the galaxy and gathers its statistics
+    pub fn get_report(&self) -> GalaxyReport {
+        GalaxyReport {
+            galaxy_id: self.galaxy_id,
+            galaxy_name: self.galaxy_name.clone(),
+            kind: self.kind,
+            seed: self.seed,
+            stats: self.get_stats(),
+            violations: self.validate(),
+        }
+    }
+
+    /// Gathers statistics describing the shape of the galaxy.
+    /// This visits every sector from every other sector, so it is not cheap for large galaxies.
+    pub fn get_stats(&self) -> GalaxyStats {
+        let link_map = self.get_link_map();
+        let sector_ids = self.get_sorted_sector_ids();
+
+        let mut degree_histogram: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut port_sector_ids: Vec<SectorId> = Vec::new();
+        let mut link_count = 0;
+        for sector_id in sector_ids.iter() {
+            let sector = sector::get_sector(*sector_id).unwrap();
+            *degree_histogram.entry(sector.get_link_count()).or_default() += 1;
+            link_count += sector.get_link_count();
+            if sector.has_port() {
+                port_sector_ids.push(*sector_id);
+            }
+        }
+
+        // The diameter considers only those pairs of sectors which are connected
+        let mut diameter = 0;
+        let mut root_eccentricity = 0;
+        let mut nearest_port_distances: Vec<usize> = Vec::new();
+        for sector_id in sector_ids.iter() {
+            let distances = breadth_first_distances(&link_map, *sector_id);
+            let eccentricity = distances.values().copied().max().unwrap_or(0);
+            diameter = diameter.max(eccentricity);
+            if Some(*sector_id) == self.get_root_sector_id() {
+                root_eccentricity = eccentricity;
+            }
+            if port_sector_ids.contains(sector_id) {
+                let nearest = port_sector_ids.iter()
+                    .filter(|port_sector_id| *port_sector_id != sector_id)
+                    .filter_map(|port_sector_id| distances.get(port_sector_id))
+                    .min();
+                if let Some(nearest) = nearest {
+                    nearest_port_distances.push(*nearest);
+                }
+            }
+        }
+
+        let port_spacing = if nearest_port_distances.is_empty() {
+            None
+        } else {
+            Some(PortSpacing {
+                min: *nearest_port_distances.iter().min().unwrap(),
+                max: *nearest_port_distances.iter().max().unwrap(),
+                mean: nearest_port_distances.iter().sum::<usize>() as f64 / nearest_port_distances.len() as f64,
+            })
+        };
+
+        GalaxyStats {
+            sector_count: sector_ids.len(),
+            link_count,
+            port_count: port_sector_ids.len(),
+            dead_end_count: degree_histogram.get(&1).copied().unwrap_or(0),
+            degree_histogram,
+            diameter,
+            root_eccentricity,
+            port_spacing,
+        }
+    }
+
+    pub fn get_galaxy_id(&self) -> GalaxyId {
+        self.galaxy_id
+    }
+
+    pub fn get_galaxy_name(&self) -> &str {
+        &self.galaxy_name
+    }
+
+    pub fn get_kind(&self) -> GalaxyKind {
+        self.kind
+    }
+
+    pub fn get_seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// The root sector is the first sector created for the galaxy, and thus has the lowest id.
+    /// A galaxy with no sectors has no root sector.
+    pub fn get_root_sector_id(&self) -> Option<SectorId> {
+        self.sector_ids.iter().min().copied()
+    }
+
+    pub fn get_sorted_sector_ids(&self) -> Vec<SectorId> {
+        let mut sector_ids: Vec<SectorId> = self.sector_ids.iter().copied().collect();
+        sector_ids.sort();
+        sector_ids
+    }
+
+    pub fn contains_sector(&self, sector_id: SectorId) -> bool {
+        self.sector_ids.contains(&sector_id)
+    }
+
     // only for debugging purposes
     pub fn dump(self) {
         for sector_id in self.sector_ids {
@@ -393,79 +1008,183 @@ impl Galaxy {
     }
 
     /// Finds the length of the shortest path from one sector to another.
+    /// The result is zero if there is no path, or if from and to are the same.
     ///
     /// # Arguments
     /// * `from` sector id of the starting sector
     /// * `to` sector id of the sector we're trying to reach
     pub fn find_shortest_path_len(&self, from: SectorId, to: SectorId) -> usize {
-        self.find_shortest_path_avoiding(from, to, &HashSet::new()).len()
+        self.get_distance(from, to).unwrap_or(0)
     }
 
-    /// Finds the shortest path from this sector to the indicated sector.
+    /// Finds the number of hops from one sector to another, using the distance cache.
+    /// None if there is no path, or if either sector is not in this galaxy.
+    pub fn get_distance(&self, from: SectorId, to: SectorId) -> Option<usize> {
+        if !self.sector_ids.contains(&to) {
+            return None;
+        }
+        self.get_cached_distances_from(from).get(&to).copied()
+    }
+
+    /// Finds the nearest port to the given sector, among those ports satisfying the given predicate.
+    /// Ties are broken in favor of the lower sector id.
+    ///
+    /// # Returns
+    /// The sector containing the port, the port, and the distance in hops - or None if no such port is reachable
+    pub fn nearest_port_from(&self, from: SectorId, predicate: impl Fn(&Port) -> bool) -> Option<(SectorId, Port, usize)> {
+        self.ports_within(from, usize::MAX).into_iter().find(|(_, port, _)| predicate(port))
+    }
+
+    /// Finds all the ports within the given number of hops from the given sector, nearest first.
+    /// A port in the given sector itself is included, at distance zero.
+    ///
+    /// # Returns
+    /// For each port, the sector containing it, the port, and the distance in hops
+    pub fn ports_within(&self, from: SectorId, hops: usize) -> Vec<(SectorId, Port, usize)> {
+        let sectors = self.sectors_within(from, hops);
+        let port_ids: HashMap<SectorId, PortId> =
+            sector::get_port_ids(&sectors.iter().map(|(sector_id, _)| *sector_id).collect());
+        sectors.into_iter()
+            .filter_map(|(sector_id, distance)| {
+                port_ids.get(&sector_id).and_then(|port_id| port::get_port(*port_id)).map(|port| (sector_id, port, distance))
+            })
+            .collect()
+    }
+
+    /// Finds all the sectors within the given number of hops from the given sector (including that sector),
+    /// ordered by distance and then by sector id.
+    pub fn sectors_within(&self, from: SectorId, hops: usize) -> Vec<(SectorId, usize)> {
+        let mut sectors: Vec<(SectorId, usize)> = self.get_cached_distances_from(from).iter()
+            .filter(|(_, distance)| **distance <= hops)
+            .map(|(sector_id, distance)| (*sector_id, *distance))
+            .collect();
+        sectors.sort_by_key(|(sector_id, distance)| (*distance, *sector_id));
+        sectors
+    }
+
+    // Gets the distances from the given sector to every sector reachable from it, from the cache if possible.
+    // The cache lock is not held while distances are computed.
+    fn get_cached_distances_from(&self, from: SectorId) -> Arc<DistanceMap> {
+        if !self.sector_ids.contains(&from) {
+            return Arc::new(DistanceMap::new());
+        }
+
+        let link_generation = sector::get_link_generation();
+        {
+            let mut cache = DISTANCE_CACHE.lock().unwrap();
+            if cache.link_generation != link_generation {
+                cache.distances_from.clear();
+                cache.link_generation = link_generation;
+            }
+            if let Some(distances) = cache.distances_from.get(&from) {
+                return distances.clone();
+            }
+        }
+
+        let distances = Arc::new(breadth_first_distances(&self.get_link_map(), from));
+        let mut cache = DISTANCE_CACHE.lock().unwrap();
+        if cache.link_generation == link_generation {
+            cache.distances_from.insert(from, distances.clone());
+        }
+        distances
+    }
+
+    /// Finds the shortest path from one sector to the indicated sector, by breadth-first search.
     /// This version observes a provided list of sectors to be avoided.
-    /// The result will contain an ordered list of SectorId values indicating the path
-    /// from this sector (not inclusive), to the targeted sector (inclusive).
-    /// If the result is empty, there is no such path available.
+    /// Links are followed only in their given direction, so one-way links are respected.
+    /// Ties between equally short paths are always broken the same way, so the same path is always produced.
     /// If either sector ID is not found in this galaxy, or if from and to are the same,
     /// the result is empty.
     ///
     /// # Arguments
     /// * `from_sector_id` sector id of the starting sector
     /// * `to_sector_id` sector id of the sector we're trying to reach
-    /// * `avoiding` set of sector ids of sectors we do not wish to traverse, or which we have
-    /// * already traversed (so that we don't infinitely loop)
-    /// 
+    /// * `avoiding` set of sector ids of sectors we do not wish to traverse.
+    ///   The destination sector may be reached even if it is in this set.
+    ///
     /// # Returns
     /// Empty vector if no path is available, else a path to the destination sector presented as a
     /// vector of sector-ids, in order, which must be traversed to reach the target path
     /// (inclusive of the target sector, non-inclusive of the starting sector).
     pub fn find_shortest_path_avoiding(&self, from_sector_id: SectorId, to_sector_id: SectorId, avoiding: &HashSet<SectorId>) -> Vec<SectorId> {
-        // simplest case - the path to ourselves is empty
-        if from_sector_id == to_sector_id {
-            return Vec::new();
+        self.find_cheapest_path(from_sector_id, to_sector_id, |_, to| {
+            if to != to_sector_id && avoiding.contains(&to) { None } else { Some(1) }
+        }).0
+    }
+
+    /// Finds the cheapest path from one sector to another, by Dijkstra's algorithm, where the cost of
+    /// each move is given by a function - for instance the fuel required, or the hazards awaiting in the
+    /// destination sector. Links are followed only in their given direction.
+    /// Ties between equally cheap paths are always broken the same way, so the same path is always produced.
+    ///
+    /// # Arguments
+    /// * `from_sector_id` sector id of the starting sector
+    /// * `to_sector_id` sector id of the sector we're trying to reach
+    /// * `move_cost` given the sector ids for a move (from, to), produces the cost of that move,
+    ///   or None if the move is not to be made
+    ///
+    /// # Returns
+    /// The path in the same form as find_shortest_path_avoiding(), along with its total cost.
+    /// The path is empty if no path is available, if either sector is not in this galaxy, or if from and to
+    /// are the same.
+    pub fn find_cheapest_path(&self,
+                              from_sector_id: SectorId,
+                              to_sector_id: SectorId,
+                              move_cost: impl Fn(SectorId, SectorId) -> Option<u64>) -> (Vec<SectorId>, u64) {
+        if from_sector_id == to_sector_id
+            || !self.sector_ids.contains(&from_sector_id)
+            || !self.sector_ids.contains(&to_sector_id) {
+            return (Vec::new(), 0);
         }
 
-        let from_sector = sector::get_sector(from_sector_id).unwrap();
+        let link_map = self.get_link_map();
+        let mut costs: HashMap<SectorId, u64> = HashMap::new();
+        let mut previous: HashMap<SectorId, SectorId> = HashMap::new();
+        let mut frontier: BinaryHeap<Reverse<(u64, SectorId)>> = BinaryHeap::new();
+        costs.insert(from_sector_id, 0);
+        frontier.push(Reverse((0, from_sector_id)));
 
-        // Look for the short completion (do we have a direct link to the target sector?)
-        // Note that we have to clone sector_links, because if we don't, rust helpfully "moves" it
-        // so that we cannot iterate over it again later on.
-        let sector_links = from_sector.sector_links;
-        for sector_id in sector_links.clone() {
+        while let Some(Reverse((cost, sector_id))) = frontier.pop() {
             if sector_id == to_sector_id {
-                let mut result: Vec<SectorId> = Vec::new();
-                result.push(to_sector_id);
-                return result;
+                break;
+            }
+            if cost > costs[&sector_id] {
+                continue; // we have since found a cheaper way here
             }
-        }
-
-        // Place ourselves into the avoidance set, then recurse over the links which are not in the
-        // newly-expanded avoidance set.
-        let mut pending_result: Vec<SectorId> = Vec::new();
-        let mut sub_avoiding: HashSet<SectorId> = avoiding.clone();
-        sub_avoiding.insert(from_sector_id);
 
-        for sector_id in sector_links {
-            if !sub_avoiding.contains(&sector_id) {
-                let mut sub_result = self.find_shortest_path_avoiding(sector_id, to_sector_id, &sub_avoiding);
-                if !sub_result.is_empty() {
-                    sub_result.insert(0, from_sector_id);
-                    if pending_result.is_empty() || (!sub_result.is_empty() && (sub_result.len() < pending_result.len())) {
-                        pending_result = sub_result;
-                    }
+            for neighbor_sector_id in link_map.get(&sector_id).into_iter().flatten() {
+                let Some(step_cost) = move_cost(sector_id, *neighbor_sector_id) else { continue; };
+                let neighbor_cost = cost + step_cost;
+                if costs.get(neighbor_sector_id).is_none_or(|&known_cost| neighbor_cost < known_cost) {
+                    costs.insert(*neighbor_sector_id, neighbor_cost);
+                    previous.insert(*neighbor_sector_id, sector_id);
+                    frontier.push(Reverse((neighbor_cost, *neighbor_sector_id)));
                 }
             }
         }
 
-        pending_result
+        // Walk back from the destination to build the path
+        let Some(total_cost) = costs.get(&to_sector_id).copied() else { return (Vec::new(), 0); };
+        let mut path = vec![to_sector_id];
+        let mut sector_id = to_sector_id;
+        while let Some(previous_sector_id) = previous.get(&sector_id) {
+            if *previous_sector_id == from_sector_id {
+                break;
+            }
+            path.push(*previous_sector_id);
+            sector_id = *previous_sector_id;
+        }
+        path.reverse();
+        (path, total_cost)
     }
 
     /// Invoked by the initializer to store everything to the database...
     /// Not intended for use during engine processing, since all persistence during execution is piecemeal.
     pub fn persist(&self, database: &Connection) -> Result<(), String> {
         match || -> rusqlite::Result<()> {
-            let statement = "INSERT INTO galaxies (galaxyId, galaxyName) VALUES (?1, ?2);";
-            let params = params![self.galaxy_id, self.galaxy_name];
+            // SQLite integers are signed, so the seed is stored as the i64 with the same bits
+            let statement = "INSERT INTO galaxies (galaxyId, galaxyName, galaxyKind, seed) VALUES (?1, ?2, ?3, ?4);";
+            let params = params![self.galaxy_id, self.galaxy_name, self.kind.code(), self.seed.map(|seed| seed as i64)];
             database.execute(statement, params)?;
 
             for sector_id in self.sector_ids.iter() {
@@ -481,3 +1200,118 @@ impl Galaxy {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+
+    const SEED: u64 = 20260417;
+
+    // Describes everything generated for a galaxy - its sectors, and their links, ports, and planets
+    fn describe_galaxy(galaxy_id: GalaxyId) -> Vec<String> {
+        let galaxy = get_galaxy(galaxy_id).unwrap();
+        galaxy.get_sorted_sector_ids().into_iter().map(|sector_id| {
+            let sector = sector::get_sector(sector_id).unwrap();
+            let mut links: Vec<SectorId> = sector.sector_links.into_iter().collect();
+            links.sort();
+            let port_name = sector.port_id.and_then(port::get_port).map(|port| port.port_name);
+            let planet_name = sector.planet_id.and_then(planet::get_planet).map(|planet| planet.planet_name);
+            format!("{} {:?} {:?} {:?}", sector_id, links, port_name, planet_name)
+        }).collect()
+    }
+
+    // Generates a galaxy of the given kind in a new game, and describes it
+    fn generate(kind: GalaxyKind, seed: u64) -> Vec<String> {
+        let (_game, database) = testing::create_game();
+        let galaxy_id = match kind {
+            GalaxyKind::Conventional =>
+                create_conventional_galaxy(&database, "Testing".to_string(), 200, 15, 10, Some(seed)),
+            GalaxyKind::Tree =>
+                create_tree_galaxy(&database, "Testing".to_string(), 3, 200, 15, 10, Some(seed)),
+        }.unwrap();
+        assert_eq!(get_galaxy(galaxy_id).unwrap().get_seed(), Some(seed));
+        describe_galaxy(galaxy_id)
+    }
+
+    #[test]
+    fn same_seed_generates_same_conventional_galaxy() {
+        let first = generate(GalaxyKind::Conventional, SEED);
+        let second = generate(GalaxyKind::Conventional, SEED);
+        assert_eq!(first.len(), 200);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn same_seed_generates_same_tree_galaxy() {
+        let first = generate(GalaxyKind::Tree, SEED);
+        let second = generate(GalaxyKind::Tree, SEED);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn different_seeds_generate_different_galaxies() {
+        assert_ne!(generate(GalaxyKind::Conventional, SEED), generate(GalaxyKind::Conventional, SEED + 1));
+    }
+
+    // Builds a small galaxy for path finding, with sectors numbered 1 to 7:
+    //   1 <-> 2 <-> 4, the short way from 1 to 4
+    //   1 <-> 5 <-> 6 <-> 4, the long way
+    //   4 -> 3 -> 1, one way only
+    //   7 -> 1, one way only, so that nothing reaches 7
+    fn create_path_galaxy(database: &Connection) -> Galaxy {
+        let sector_ids: HashSet<SectorId> = (0..7).map(|_| sector::create_sector(database).unwrap()).collect();
+        for (from, to) in [(1, 2), (2, 4), (1, 5), (5, 6), (6, 4)] {
+            sector::link_sectors(database, from, to).unwrap();
+        }
+        for (from, to) in [(4, 3), (3, 1), (7, 1)] {
+            sector::link_sector_one_way(database, from, to).unwrap();
+        }
+        Galaxy { galaxy_id: 1, galaxy_name: "Paths".to_string(), kind: GalaxyKind::Conventional, seed: None, sector_ids }
+    }
+
+    #[test]
+    fn path_excludes_start_and_includes_destination() {
+        let (_game, database) = testing::create_game();
+        let galaxy = create_path_galaxy(&database);
+        assert_eq!(galaxy.find_shortest_path(1, 4), vec![2, 4]);
+        assert_eq!(galaxy.find_shortest_path(1, 2), vec![2]);
+    }
+
+    #[test]
+    fn path_does_not_follow_one_way_links_backwards() {
+        let (_game, database) = testing::create_game();
+        let galaxy = create_path_galaxy(&database);
+        assert_eq!(galaxy.find_shortest_path(3, 1), vec![1]);
+        assert_eq!(galaxy.find_shortest_path(1, 3), vec![2, 4, 3]);
+    }
+
+    #[test]
+    fn path_skips_avoided_sectors_except_the_destination() {
+        let (_game, database) = testing::create_game();
+        let galaxy = create_path_galaxy(&database);
+        assert_eq!(galaxy.find_shortest_path_avoiding(1, 4, &HashSet::from([2])), vec![5, 6, 4]);
+        assert_eq!(galaxy.find_shortest_path_avoiding(1, 4, &HashSet::from([4])), vec![2, 4]);
+        assert_eq!(galaxy.find_shortest_path_avoiding(1, 4, &HashSet::from([2, 6])), Vec::<SectorId>::new());
+    }
+
+    #[test]
+    fn path_to_the_same_or_an_unreachable_sector_is_empty() {
+        let (_game, database) = testing::create_game();
+        let galaxy = create_path_galaxy(&database);
+        assert!(galaxy.find_shortest_path(4, 4).is_empty());
+        assert!(galaxy.find_shortest_path(1, 7).is_empty());
+        assert!(galaxy.find_shortest_path(1, 8).is_empty());
+        assert_eq!(galaxy.find_cheapest_path(4, 4, |_, _| Some(1)), (Vec::new(), 0));
+        assert_eq!(galaxy.find_cheapest_path(1, 7, |_, _| Some(1)), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn cheapest_path_may_be_longer_than_the_shortest() {
+        let (_game, database) = testing::create_game();
+        let galaxy = create_path_galaxy(&database);
+        assert_eq!(galaxy.find_cheapest_path(1, 4, |_, _| Some(1)), (vec![2, 4], 2));
+        let expensive_sector_2 = |_, to| if to == 2 { Some(10) } else { Some(1) };
+        assert_eq!(galaxy.find_cheapest_path(1, 4, expensive_sector_2), (vec![5, 6, 4], 3));
+    }
+}
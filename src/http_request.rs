@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use crate::http_response::*;
+
+const MAX_CONTENT_LENGTH: usize = 64 * 1024;
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// A parsed HTTP request, as handed to the request handlers.
+/// Header names and query parameter names are case-insensitive, and are stored in lower case.
+/// The path is also stored in lower case, and never includes the query string.
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+    form: HashMap<String, String>, // decoded from the body if it is url-encoded
+}
+
+impl HttpRequest {
+    /// Reads a request from the given stream - the request line, the headers, and the body
+    /// (if there is one - we only read a body if a Content-Length header is present).
+    /// If the request cannot be read or is malformed, the result is the response to be sent to the client.
+    pub fn read_from(stream: impl Read) -> Result<HttpRequest, HttpResponse> {
+        let mut buf_reader = BufReader::new(stream);
+        let mut text_lines: Vec<String> = Vec::new();
+        loop {
+            let mut line = String::new();
+            match buf_reader.read_line(&mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    let line = line.trim_end_matches(['\r', '\n']).to_string();
+                    if line.is_empty() {
+                        break;
+                    }
+                    text_lines.push(line);
+                },
+                Err(_) => { return Err(HttpResponse::new(HTTP_BAD_REQUEST, "Cannot read request")); },
+            }
+        }
+
+        // Grab the method and the url - we don't care about the http version.
+        let request_line = match text_lines.first() {
+            Some(line) => line.clone(),
+            None => { return Err(HttpResponse::new(HTTP_BAD_REQUEST, "Empty request")); },
+        };
+
+        let leading_parts = request_line.split(" ").collect::<Vec<&str>>();
+        if leading_parts.len() < 2 {
+            return Err(HttpResponse::new(HTTP_BAD_REQUEST, "Badly-formatted method/URL"));
+        }
+
+        let method = leading_parts[0].to_uppercase();
+        let (path, query) = leading_parts[1].split_once('?').unwrap_or((leading_parts[1], ""));
+        let mut path = path.to_lowercase();
+        if path.is_empty() {
+            path = "/".to_string();
+        }
+
+        let headers = decode_headers(&text_lines[1..]);
+
+        let mut body = String::new();
+        if let Some(value) = headers.get("content-length") {
+            let content_length = match value.parse::<usize>() {
+                Ok(length) if length <= MAX_CONTENT_LENGTH => length,
+                Ok(_) => { return Err(HttpResponse::new(HTTP_BAD_REQUEST, "Request body is too large")); },
+                Err(_) => { return Err(HttpResponse::new(HTTP_BAD_REQUEST, "Invalid Content-Length")); },
+            };
+
+            let mut buffer = vec![0u8; content_length];
+            if buf_reader.read_exact(&mut buffer).is_err() {
+                return Err(HttpResponse::new(HTTP_BAD_REQUEST, "Request body is shorter than Content-Length"));
+            }
+            body = String::from_utf8_lossy(&buffer).to_string();
+        }
+
+        let is_form = headers.get("content-type")
+            .map(|value| value.to_ascii_lowercase().starts_with(FORM_CONTENT_TYPE))
+            .unwrap_or(false);
+        let form = if is_form { decode_query(&body) } else { HashMap::new() };
+
+        Ok(HttpRequest { method, path, query: decode_query(query), headers, body, form })
+    }
+
+    /// The first line of the request, more or less - for logging
+    pub fn describe(&self) -> String {
+        format!("{} {}", self.method, self.path)
+    }
+
+    /// Indicates whether the client has asked (via the Accept header) for JSON rather than plain text
+    pub fn wants_json(&self) -> bool {
+        self.get_header("accept")
+            .map(|value| value.to_ascii_lowercase().contains(CONTENT_TYPE_JSON))
+            .unwrap_or(false)
+    }
+
+    pub fn get_header(&self, name: &str) -> Option<&String> {
+        self.headers.get(&name.to_ascii_lowercase())
+    }
+
+    /// Retrieves a named parameter from the query string or, failing that,
+    /// from the body if the body is url-encoded form data.
+    pub fn get_parameter(&self, name: &str) -> Option<&String> {
+        let name = name.to_ascii_lowercase();
+        self.query.get(&name).or_else(|| self.form.get(&name))
+    }
+}
+
+// Decodes the lines representing the headers into a map
+fn decode_headers(text_lines: &[String]) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    for text in text_lines {
+        if let Some((key, value)) = text.split_once(':') {
+            headers.insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    headers
+}
+
+// Decodes a url-encoded string of name=value pairs (a query, or a form body) into a map.
+// Names are case-insensitive, and are thus stored in lower case.
+fn decode_query(query: &str) -> HashMap<String, String> {
+    let mut parameters = HashMap::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        parameters.insert(percent_decode(name).to_ascii_lowercase(), percent_decode(value));
+    }
+    parameters
+}
+
+// Decodes %xx escapes and '+' characters in a url-encoded string
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut bx = 0;
+    while bx < bytes.len() {
+        match bytes[bx] {
+            b'+' => decoded.push(b' '),
+            // from_str_radix() would accept a sign, so check for two hex digits first - anything else stays literal
+            b'%' if bx + 2 < bytes.len() && bytes[bx + 1].is_ascii_hexdigit() && bytes[bx + 2].is_ascii_hexdigit() => {
+                let hex = String::from_utf8_lossy(&bytes[bx + 1..bx + 3]);
+                decoded.push(u8::from_str_radix(&hex, 16).unwrap());
+                bx += 2;
+            },
+            byte => decoded.push(byte),
+        }
+        bx += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> Result<HttpRequest, HttpResponse> {
+        HttpRequest::read_from(text.as_bytes())
+    }
+
+    // HttpResponse isn't Debug, so we can't simply unwrap() the result of read()
+    fn parse(text: &str) -> HttpRequest {
+        match read(text) {
+            Ok(request) => request,
+            Err(_) => panic!("Request was rejected: {:?}", text.lines().next()),
+        }
+    }
+
+    // A POST request with a url-encoded form body, and a Content-Length to match
+    fn post_form(body: &str) -> String {
+        format!("POST /port/buy HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+                FORM_CONTENT_TYPE, body.len(), body)
+    }
+
+    #[test]
+    fn percent_decode_handles_escapes_and_plus() {
+        assert_eq!(percent_decode("a+b%20c%2Bd"), "a b c+d");
+        assert_eq!(percent_decode("%e2%82%ac"), "\u{20ac}");
+    }
+
+    #[test]
+    fn percent_decode_leaves_invalid_escapes_literal() {
+        assert_eq!(percent_decode("%+5"), "% 5");
+        assert_eq!(percent_decode("%-5x"), "%-5x");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%4"), "%4");
+    }
+
+    #[test]
+    fn query_parameters_are_decoded_with_lower_case_names() {
+        let request = parse("GET /Port/Nearest?Selling=Fuel+Ore&page=%32&flag HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/port/nearest");
+        assert_eq!(request.get_parameter("selling").map(String::as_str), Some("Fuel Ore"));
+        assert_eq!(request.get_parameter("PAGE").map(String::as_str), Some("2"));
+        assert_eq!(request.get_parameter("flag").map(String::as_str), Some(""));
+        assert_eq!(request.get_header("host").map(String::as_str), Some("x"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn form_body_is_read_to_content_length_and_decoded() {
+        let request = parse(&post_form("commodity=fuel%20ore&quantity=10"));
+        assert_eq!(request.body, "commodity=fuel%20ore&quantity=10");
+        assert_eq!(request.get_parameter("commodity").map(String::as_str), Some("fuel ore"));
+        assert_eq!(request.get_parameter("quantity").map(String::as_str), Some("10"));
+    }
+
+    #[test]
+    fn query_parameters_take_precedence_over_the_form() {
+        let text = post_form("quantity=10").replacen("/port/buy", "/port/buy?quantity=5", 1);
+        assert_eq!(parse(&text).get_parameter("quantity").map(String::as_str), Some("5"));
+    }
+
+    #[test]
+    fn body_is_read_only_as_far_as_content_length() {
+        let request = parse("POST /message/send HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello, and more");
+        assert_eq!(request.body, "hello");
+        assert!(request.get_parameter("hello").is_none()); // not a form, so not decoded
+    }
+
+    #[test]
+    fn body_shorter_than_content_length_is_rejected() {
+        assert!(read("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort").is_err());
+        assert!(read("POST / HTTP/1.1\r\nContent-Length: ten\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn body_is_limited_to_64kb() {
+        let body = "x".repeat(MAX_CONTENT_LENGTH);
+        assert_eq!(parse(&post_form(&body)).body.len(), MAX_CONTENT_LENGTH);
+        let body = "x".repeat(MAX_CONTENT_LENGTH + 1);
+        assert!(read(&post_form(&body)).is_err());
+    }
+
+    #[test]
+    fn malformed_request_line_is_rejected() {
+        assert!(read("").is_err());
+        assert!(read("GARBAGE\r\n\r\n").is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use serde_json::{json, Value};
 
 pub struct HttpResponse {
     code: u16,
@@ -18,12 +19,22 @@ pub const HTTP_METHOD_NOT_ALLOWED: u16 = 405;
 pub const HTTP_INTERNAL_SERVER_ERROR: u16 = 500;
 pub const HTTP_NOT_IMPLEMENTED: u16 = 501;
 
+pub const CONTENT_TYPE_JSON: &str = "application/json";
+pub const CONTENT_TYPE_TEXT: &str = "text/plain; charset=utf-8";
+
 impl HttpResponse {
 
     pub fn new(code: u16, data: &str) -> HttpResponse {
         HttpResponse{code, headers: HashMap::new(), data: data.into()}
     }
 
+    /// Creates a response with a JSON body and the appropriate content type
+    pub fn new_json(code: u16, value: &Value) -> HttpResponse {
+        let mut response = HttpResponse::new(code, value.to_string().as_str());
+        response.append_header("content-type", CONTENT_TYPE_JSON);
+        response
+    }
+
     pub fn new_with_headers(code: u16, headers: HashMap<String, String>, data: &str) -> HttpResponse {
         HttpResponse{code, headers, data: data.into()}
     }
@@ -32,10 +43,38 @@ impl HttpResponse {
         self.headers.insert(key.into(), value.into());
     }
 
+    pub fn is_json(&self) -> bool {
+        self.headers.get("content-type").map(|value| value == CONTENT_TYPE_JSON).unwrap_or(false)
+    }
+
     pub fn is_successful(&self) -> bool {
         self.code >= 200 && self.code < 299
     }
 
+    /// Converts a plain-text response into an equivalent JSON response, for clients which asked for JSON.
+    /// The text of a successful response is presented as an array of lines under "text",
+    /// while the text of any other response is presented as a message under "error".
+    /// Responses which are already JSON are returned unchanged.
+    pub fn into_json(self) -> HttpResponse {
+        if self.is_json() {
+            return self;
+        }
+
+        let value = if self.is_successful() {
+            json!({"text": self.data.lines().collect::<Vec<&str>>()})
+        } else {
+            json!({"error": self.data})
+        };
+
+        let mut response = HttpResponse::new_json(self.code, &value);
+        for (key, value) in self.headers {
+            if key != "content-type" {
+                response.append_header(&key, &value);
+            }
+        }
+        response
+    }
+
     pub fn to_string(&self) -> String {
         let detail = match self.code {
             HTTP_OK => "OK",
@@ -7,5 +7,15 @@ pub mod planet;
 pub mod action;
 pub mod server;
 pub mod session;
+pub mod ship;
+pub mod trade;
+pub mod colony;
+pub mod combat;
+pub mod deployment;
+pub mod http_request;
 pub mod http_response;
 pub mod ansi;
+pub mod commodity;
+pub mod database;
+pub mod config;
+pub mod logging;
@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Severity of a log message. A message is written only if its level is at or below the current log level.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+}
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::SeqCst);
+}
+
+pub fn is_enabled(level: LogLevel) -> bool {
+    level as u8 <= LOG_LEVEL.load(Ordering::SeqCst)
+}
+
+impl LogLevel {
+    /// Case-insensitive lookup by name, as used in the configuration file
+    pub fn from_name(name: &str) -> Option<LogLevel> {
+        match name.to_ascii_lowercase().as_str() {
+            "error" => Some(LogLevel::Error),
+            "warn" | "warning" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+}
+
+// Errors and warnings go to stderr, everything else to stdout.
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        if $crate::logging::is_enabled($crate::logging::LogLevel::Error) { eprintln!($($arg)*); }
+    };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        if $crate::logging::is_enabled($crate::logging::LogLevel::Warn) { eprintln!($($arg)*); }
+    };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if $crate::logging::is_enabled($crate::logging::LogLevel::Info) { println!($($arg)*); }
+    };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        if $crate::logging::is_enabled($crate::logging::LogLevel::Debug) { println!($($arg)*); }
+    };
+}
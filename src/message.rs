@@ -1,60 +1,120 @@
-use std::collections::HashMap;
-use std::sync::{LazyLock, Mutex};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use rusqlite::{params, Connection};
+use serde_json::{json, Value};
+use chrono::{DateTime, Local};
+use crate::user;
 use crate::user::UserId;
+use crate::log_info;
 
 pub type MessageId = u64;
 
+pub const MAX_MESSAGE_LENGTH: usize = 1000;
+const SEND_REQUEST_COST: i32 = 1;
+
 static NEXT_MESSAGE_ID: LazyLock<Mutex<MessageId>> = LazyLock::new(|| Mutex::new(1));
 static MESSAGES: LazyLock<Mutex<HashMap<MessageId, Message>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
 
+// Per-user queues of messages which have not yet been delivered, in the order in which they were created.
+// Pollers wait on PENDING_SIGNAL, which is notified whenever a message is queued.
+static PENDING_MESSAGES: LazyLock<Mutex<HashMap<UserId, VecDeque<MessageId>>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+static PENDING_SIGNAL: Condvar = Condvar::new();
+
+#[derive(Clone)]
 pub struct Message {
     pub message_id: MessageId,
     pub from_user_id: UserId,
     pub to_user_id: UserId,
     pub date_time: SystemTime,
     pub message: String,
+    pub is_delivered: bool,
 }
 
-pub fn create_message(database: &Connection, from_user_id: UserId, to_user_id: UserId, message: &String) -> Result<MessageId, String> {
+/// Writes a new message to the database. The message is not visible to anyone until the caller
+/// passes it to publish_message(), which it should do only once its transaction is committed.
+pub fn create_message(database: &Connection, from_user_id: UserId, to_user_id: UserId, message: &String) -> Result<Message, String> {
     let mut next_message_id = NEXT_MESSAGE_ID.lock().unwrap();
     let message_id = *next_message_id;
     *next_message_id += 1;
 
-    let msg = Message{message_id, from_user_id, to_user_id, date_time: SystemTime::now(), message: message.clone()};
+    let msg = Message{message_id, from_user_id, to_user_id, date_time: SystemTime::now(), message: message.clone(), is_delivered: false};
     match msg.persist(database) {
-        Ok(_) => (),
-        Err(e) => { return Err(e.to_string()); },
+        Ok(_) => Ok(msg),
+        Err(e) => Err(e.to_string()),
     }
+}
 
+/// Adds a committed message to our in-memory copies, and queues it for delivery to its recipient.
+pub fn publish_message(msg: Message) {
+    let message_id = msg.message_id;
+    let to_user_id = msg.to_user_id;
     MESSAGES.lock().unwrap().insert(message_id, msg);
-    Ok(message_id)
+    PENDING_MESSAGES.lock().unwrap().entry(to_user_id).or_default().push_back(message_id);
+    PENDING_SIGNAL.notify_all();
+}
+
+/// Retrieves one page of the messages sent to the given user, most recent first.
+/// Pages are numbered from 1.
+///
+/// # Returns
+/// The messages on the requested page, and the total number of messages in the inbox
+pub fn get_inbox(user_id: UserId, page: usize, page_size: usize) -> (Vec<Message>, usize) {
+    get_page(|msg| msg.to_user_id == user_id, page, page_size)
+}
+
+/// Retrieves one page of the messages sent by the given user, most recent first.
+/// Pages are numbered from 1.
+///
+/// # Returns
+/// The messages on the requested page, and the total number of messages in the outbox
+pub fn get_outbox(user_id: UserId, page: usize, page_size: usize) -> (Vec<Message>, usize) {
+    get_page(|msg| msg.from_user_id == user_id, page, page_size)
+}
+
+// Common code for inbox and outbox
+fn get_page(filter: impl Fn(&Message) -> bool, page: usize, page_size: usize) -> (Vec<Message>, usize) {
+    let lock = MESSAGES.lock().unwrap();
+    let mut messages: Vec<&Message> = lock.values().filter(|msg| filter(msg)).collect();
+    messages.sort_by_key(|msg| Reverse(msg.message_id));
+    let total = messages.len();
+    let page_messages = messages.into_iter()
+        .skip(page.saturating_sub(1) * page_size)
+        .take(page_size)
+        .cloned()
+        .collect();
+    (page_messages, total)
 }
 
 pub fn load_messages(database: &Connection) -> Result<(), String> {
     MESSAGES.lock().unwrap().clear();
-    
+    PENDING_MESSAGES.lock().unwrap().clear();
+
     match || -> rusqlite::Result<()> {
-        let mut stmt = database.prepare("SELECT messageId, fromUserId, toUserId, timeStamp, text \
+        let mut stmt = database.prepare("SELECT messageId, fromUserId, toUserId, timeStamp, text, isDelivered \
                                                     FROM messages ORDER BY messageId")?;
         let message_iter = stmt.query_map([], |row| {
             Ok(Message::new(row.get(0)?,
                             row.get(1)?,
                             row.get(2)?,
                             row.get(3)?,
-                            row.get(4)?))
+                            row.get(4)?,
+                            row.get(5)?))
         })?;
 
         let mut highest_message_id: MessageId = 0;
         for message_result in message_iter {
             let msg = message_result?;
             highest_message_id = msg.message_id;
+            if !msg.is_delivered {
+                PENDING_MESSAGES.lock().unwrap().entry(msg.to_user_id).or_default().push_back(msg.message_id);
+            }
             MESSAGES.lock().unwrap().insert(msg.message_id, msg);
         }
 
         *NEXT_MESSAGE_ID.lock().unwrap() = highest_message_id + 1;
-        println!("Loaded {} messages", MESSAGES.lock().unwrap().len());
+        log_info!("Loaded {} messages", MESSAGES.lock().unwrap().len());
         Ok(())
     }() {
         Ok(()) => Ok(()),
@@ -62,19 +122,150 @@ pub fn load_messages(database: &Connection) -> Result<(), String> {
     }
 }
 
+/// Sends a message from one user to another, on behalf of the sending user.
+/// Sending a message counts against the sender's daily requests.
+pub fn send_message(database: &mut Connection,
+                    from_user_id: UserId,
+                    to_user_id: UserId,
+                    text: &str) -> Result<MessageId, String> {
+    let text = check_text(text)?;
+    let transaction = database.transaction().map_err(|e| format!("Cannot send message:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, from_user_id, SEND_REQUEST_COST)?;
+    let msg = create_message(&transaction, from_user_id, to_user_id, &text.to_string())?;
+    transaction.commit().map_err(|e| format!("Cannot send message:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(from_user_id, requests_remaining);
+    let message_id = msg.message_id;
+    publish_message(msg);
+    Ok(message_id)
+}
+
+/// Sends the same message to each of the given users, in a single transaction - either every user
+/// gets the message or none do. Unlike send_message(), this costs the sender none of their daily requests.
+///
+/// # Returns
+/// The number of users to whom the message was sent
+pub fn broadcast_message(database: &mut Connection,
+                         from_user_id: UserId,
+                         to_user_ids: &[UserId],
+                         text: &str) -> Result<usize, String> {
+    let text = check_text(text)?;
+    let transaction = database.transaction().map_err(|e| format!("Cannot broadcast message:{}", e))?;
+    let mut messages: Vec<Message> = Vec::new();
+    for to_user_id in to_user_ids {
+        messages.push(create_message(&transaction, from_user_id, *to_user_id, &text.to_string())?);
+    }
+    transaction.commit().map_err(|e| format!("Cannot broadcast message:{}", e))?;
+
+    // Only now that the messages are committed do we queue them for delivery
+    let count = messages.len();
+    for msg in messages {
+        publish_message(msg);
+    }
+    Ok(count)
+}
+
+// Trims the text of a message to be sent, making sure there is something left and that it isn't too long
+fn check_text(text: &str) -> Result<&str, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        Err("Message text is required".to_string())
+    } else if text.chars().count() > MAX_MESSAGE_LENGTH {
+        Err(format!("Messages are limited to {} characters", MAX_MESSAGE_LENGTH))
+    } else {
+        Ok(text)
+    }
+}
+
+/// Marks the given messages as delivered, both in the database and in memory.
+/// Intended to be invoked once the messages returned by wait_for_messages() have been handed to the user.
+pub fn mark_delivered(database: &Connection, message_ids: &[MessageId]) -> Result<(), String> {
+    for message_id in message_ids {
+        let statement = "UPDATE messages SET isDelivered = 1 WHERE messageId = ?1;";
+        if let Err(e) = database.execute(statement, params![message_id]) {
+            return Err(format!("Cannot update message:{}", e));
+        }
+        if let Some(msg) = MESSAGES.lock().unwrap().get_mut(message_id) {
+            msg.is_delivered = true;
+        }
+    }
+    Ok(())
+}
+
+/// Waits for undelivered messages for the given user, up to the given timeout.
+/// Returns all of the user's pending messages, in order, removing them from the user's queue
+/// (they should subsequently be marked delivered via mark_delivered()).
+/// If the timeout expires with nothing pending, the result is empty.
+pub fn wait_for_messages(user_id: UserId, timeout: Duration) -> Vec<Message> {
+    let deadline = Instant::now() + timeout;
+    let mut pending = PENDING_MESSAGES.lock().unwrap();
+    loop {
+        let has_pending = pending.get(&user_id).map(|queue| !queue.is_empty()).unwrap_or(false);
+        let now = Instant::now();
+        if has_pending || now >= deadline {
+            break;
+        }
+        pending = PENDING_SIGNAL.wait_timeout(pending, deadline - now).unwrap().0;
+    }
+
+    let message_ids: Vec<MessageId> = pending.remove(&user_id).map(Vec::from).unwrap_or_default();
+    drop(pending);
+
+    let messages = MESSAGES.lock().unwrap();
+    message_ids.iter().filter_map(|message_id| messages.get(message_id).cloned()).collect()
+}
+
+// The name by which a user is known to other users
+fn get_game_name(user_id: UserId) -> String {
+    user::get_user(user_id)
+        .map(|user| user.game_name)
+        .unwrap_or_else(|| format!("User {}", user_id))
+}
+
 impl Message {
-    fn new(message_id: MessageId, from_user_id: UserId, to_user_id: UserId, seconds: u64, message: String) -> Message {
+    fn new(message_id: MessageId,
+           from_user_id: UserId,
+           to_user_id: UserId,
+           seconds: u64,
+           message: String,
+           is_delivered: bool) -> Message {
         let d = Duration::from_secs(seconds);
         let st = UNIX_EPOCH + d;
-        Message{message_id, from_user_id, to_user_id, date_time: st, message: message.clone()}
+        Message{message_id, from_user_id, to_user_id, date_time: st, message: message.clone(), is_delivered}
+    }
+
+    /// Creates a single line of text describing the message, for presentation to the recipient
+    pub fn get_description(&self) -> String {
+        let datetime: DateTime<Local> = self.date_time.into();
+        format!("[{}] {}: {}", datetime.format("%m/%d/%Y %T"), get_game_name(self.from_user_id), self.message)
+    }
+
+    /// Creates a single line of text describing the message, for presentation to the sender
+    pub fn get_sent_description(&self) -> String {
+        let datetime: DateTime<Local> = self.date_time.into();
+        format!("[{}] To {}: {}", datetime.format("%m/%d/%Y %T"), get_game_name(self.to_user_id), self.message)
+    }
+
+    /// Creates a JSON representation of the message, with the time stamp in seconds since the epoch
+    pub fn to_json(&self) -> Value {
+        json!({
+            "message_id": self.message_id,
+            "from_user_id": self.from_user_id,
+            "to_user_id": self.to_user_id,
+            "time_stamp": self.date_time.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            "message": self.message,
+            "is_delivered": self.is_delivered,
+        })
     }
 
     pub fn persist(&self, database: &Connection) -> Result<(), String> {
         match || -> rusqlite::Result<()> {
-            let statement = "INSERT INTO messages (messageId, fromUserId, toUserId, timeStamp, text) VALUES (?1, ?2, ?3, ?4, ?5);";
+            let statement = "INSERT INTO messages (messageId, fromUserId, toUserId, timeStamp, text, isDelivered) \
+                            VALUES (?1, ?2, ?3, ?4, ?5, ?6);";
             let unix_time = self.date_time.duration_since(UNIX_EPOCH).unwrap().as_secs();
             let params =
-                params![self.message_id, self.from_user_id, self.to_user_id, unix_time, self.message];
+                params![self.message_id, self.from_user_id, self.to_user_id, unix_time, self.message, self.is_delivered];
             database.execute(statement, params)?;
             Ok(())
         }() {
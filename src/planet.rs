@@ -1,33 +1,150 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{LazyLock, Mutex};
+use rand::Rng;
 use rusqlite::{params, Connection};
+use serde_json::{json, Value};
+use crate::action::Actor;
+use crate::commodity::Commodity;
+use crate::database;
+use crate::user::UserId;
+use crate::{log_error, log_info, log_warn, user};
 
 pub type PlanetId = usize;
 
 static NEXT_PLANET_ID: LazyLock<Mutex<PlanetId>> = LazyLock::new(|| Mutex::new(1));
+static PLANET_NAME_REGISTRY: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
 static PLANETS: LazyLock<Mutex<HashMap<PlanetId, Planet>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
 
+const MIN_MAX_POPULATION: i32 = 5000;
+const MAX_MAX_POPULATION: i32 = 50000;
+const POPULATION_GROWTH_PERCENT: i32 = 2; // daily growth, until the planet's maximum population is reached
+const MIN_DEPOSIT: i32 = 20000;
+const MAX_DEPOSIT: i32 = 200000;
+const MIN_STOCKPILE_CAPACITY: i32 = 1000;
+const MAX_STOCKPILE_CAPACITY: i32 = 5000;
+const MIN_RATE_PER_DAY: i32 = 5;
+const MAX_RATE_PER_DAY: i32 = 50;
+const COLONISTS_PER_WORK_UNIT: i32 = 1000; // rate_per_day is the production of this many colonists
+pub const MAX_CITADEL_LEVEL: i32 = 5;
+const CITADEL_STRENGTH_PERCENT: i32 = 25; // extra strength of the garrison's fighters per citadel level
+const CITADEL_UPGRADE_PRICE: i64 = 5000; // multiplied by the level being built
+
+/// A planet, conceptually located within a sector (see sector::Sector::planet_id).
+/// Planets hold deposits of each commodity, which their colonists extract day by day into a stockpile,
+/// from which the goods may be collected. A planet with no colonists produces nothing.
+/// Populations grow daily, up to the most the planet can support.
+/// An owned planet may be defended by a garrison of fighters and by shields; its citadel strengthens
+/// the garrison's fighters (see combat::Combatant).
+#[derive(Clone)]
 pub struct Planet {
     pub planet_id: PlanetId,
     pub planet_name: String,
+    pub owner_user_id: Option<UserId>, // None if the planet is unclaimed
+    pub population: i32,
+    pub max_population: i32,
+    pub resources: Vec<PlanetResource>, // one entry per commodity, in Commodity::ALL order
+    pub fighters: i32,
+    pub shields: i32,
+    pub citadel_level: i32, // 0 (no citadel) to MAX_CITADEL_LEVEL
+}
+
+/// Describes a planet's holdings of one particular commodity.
+/// Each day, up to rate_per_day units per COLONISTS_PER_WORK_UNIT colonists are extracted from the deposit
+/// and added to the stockpile. Production stops when the deposit is exhausted, or while the stockpile is full.
+#[derive(Clone)]
+pub struct PlanetResource {
+    pub commodity: Commodity,
+    pub deposit: i32,
+    pub stockpile: i32,
+    pub capacity: i32,
+    pub rate_per_day: i32,
 }
 
-// Creates a planet and persists it to the database
-fn create_planet(database: &Connection, planet_name: String) -> Result<PlanetId, String> {
+/// Daily actor which advances production and population growth on every planet
+pub struct PlanetProductionActor;
+
+/// Creates an unclaimed, uninhabited planet with randomly chosen deposits, and persists it to the database.
+/// The planet is not in any sector - see sector::assign_planet.
+///
+/// # Arguments
+/// * `database` a connected database
+/// * `planet_name` name for the planet - if None, a name is chosen at random
+/// * `rng` random number generator, which determines the planet's name (if not given) and deposits
+pub fn create_planet(database: &Connection, planet_name: Option<String>, rng: &mut impl Rng) -> Result<PlanetId, String> {
     let mut next_planet_id = NEXT_PLANET_ID.lock().unwrap();
     let planet_id = *next_planet_id;
     *next_planet_id += 1;
 
-    let planet = Planet { planet_id, planet_name };
+    let planet_name = match planet_name {
+        Some(planet_name) => planet_name,
+        None => {
+            let mut planet_name = format!("Planet {}", planet_id);
+            let mut try_counter = 20;
+            while try_counter > 0 {
+                try_counter -= 1;
+                let proposed_name = PLANET_NAMES[rng.random_range(0..PLANET_NAMES.len())];
+                if !PLANET_NAME_REGISTRY.lock().unwrap().contains(proposed_name) {
+                    planet_name = proposed_name.to_owned();
+                    break;
+                }
+            }
+            planet_name
+        },
+    };
+
+    let max_population = rng.random_range(MIN_MAX_POPULATION..(MAX_MAX_POPULATION + 1));
+    let resources = create_resources(rng);
+
+    let planet = Planet { planet_id, planet_name, owner_user_id: None, population: 0, max_population, resources,
+        fighters: 0, shields: 0, citadel_level: 0 };
     match planet.persist(database) {
         Ok(_) => (),
         Err(e) => { return Err(e.to_string()); },
     }
 
+    PLANET_NAME_REGISTRY.lock().unwrap().insert(planet.planet_name.clone());
     PLANETS.lock().unwrap().insert(planet_id, planet);
     Ok(planet_id)
 }
 
+// Chooses a planet's deposits of each commodity, and how quickly they can be extracted, at random
+fn create_resources(rng: &mut impl Rng) -> Vec<PlanetResource> {
+    let mut resources: Vec<PlanetResource> = Vec::new();
+    for commodity in Commodity::ALL {
+        let deposit = rng.random_range(MIN_DEPOSIT..(MAX_DEPOSIT + 1));
+        let capacity = rng.random_range(MIN_STOCKPILE_CAPACITY..(MAX_STOCKPILE_CAPACITY + 1));
+        let rate_per_day = rng.random_range(MIN_RATE_PER_DAY..(MAX_RATE_PER_DAY + 1));
+        resources.push(PlanetResource { commodity, deposit, stockpile: 0, capacity, rate_per_day });
+    }
+    resources
+}
+
+/// Gives planets from a database built before planets had populations and deposits a random maximum
+/// population and random deposits, as though they had just been created. See database::upgrade_schema.
+pub fn upgrade_planets(database: &Connection) -> Result<(), String> {
+    match || -> rusqlite::Result<()> {
+        let mut stmt = database.prepare("SELECT planetId FROM planets WHERE maxPopulation = 0 ORDER BY planetId")?;
+        let planet_ids: Vec<PlanetId> = stmt.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+
+        let mut rng = rand::rng();
+        for planet_id in planet_ids {
+            let max_population = rng.random_range(MIN_MAX_POPULATION..(MAX_MAX_POPULATION + 1));
+            database.execute("UPDATE planets SET maxPopulation = ?2 WHERE planetId = ?1;", params![planet_id, max_population])?;
+            for resource in create_resources(&mut rng) {
+                let statement = "INSERT OR IGNORE INTO planet_resources \
+                                (planetId, commodity, deposit, stockpile, capacity, ratePerDay) \
+                                VALUES (?1, ?2, ?3, ?4, ?5, ?6);";
+                database.execute(statement, params![planet_id, resource.commodity.code(), resource.deposit,
+                    resource.stockpile, resource.capacity, resource.rate_per_day])?;
+            }
+        }
+        Ok(())
+    }() {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Cannot upgrade planets:{}", e)),
+    }
+}
+
 // Gets a clone of a planet - only for looking at information, not for changing it
 pub fn get_planet(planet_id: PlanetId) -> Option<Planet> {
     for planet in PLANETS.lock().unwrap().values() {
@@ -38,21 +155,82 @@ pub fn get_planet(planet_id: PlanetId) -> Option<Planet> {
     None
 }
 
-/// Creates a planet map describing all the ports in the universe - Used when a game starts up.
+/// Writes the changeable state of the given planet (owner, population, defenses, deposits and stockpiles) to the database.
+/// Our in-memory copy is left alone - the caller passes the planet to publish_planet() once its transaction is committed.
+pub fn update_planet(database: &Connection, planet: &Planet) -> Result<(), String> {
+    planet.persist_state(database).map_err(|e| format!("Cannot update planet:{}", e))
+}
+
+/// Replaces our in-memory copy of the planet - to be called only after the planet's changes are committed.
+pub fn publish_planet(planet: Planet) {
+    PLANETS.lock().unwrap().insert(planet.planet_id, planet);
+}
+
+/// Advances every inhabited planet by one day: each resource is extracted into its stockpile,
+/// and then the population grows. The changes are persisted in a single transaction.
+pub fn run_daily_production(database: &mut Connection) -> Result<(), String> {
+    let transaction = database.transaction().map_err(|e| format!("Cannot run planetary production:{}", e))?;
+    let mut lock = PLANETS.lock().unwrap();
+    let mut updated: Vec<Planet> = Vec::new();
+    for planet in lock.values().filter(|planet| planet.population > 0) {
+        let mut planet = planet.clone();
+        planet.run_day();
+        planet.persist_state(&transaction).map_err(|e| format!("Cannot run planetary production:{}", e))?;
+        updated.push(planet);
+    }
+    transaction.commit().map_err(|e| format!("Cannot run planetary production:{}", e))?;
+
+    // Only now that the changes are committed do we update our in-memory copies
+    for planet in updated {
+        lock.insert(planet.planet_id, planet);
+    }
+    Ok(())
+}
+
+/// Creates a planet map describing all the planets in the universe - Used when a game starts up.
 pub fn load_planets(database: &Connection) -> Result<(), String> {
     PLANETS.lock().unwrap().clear();
+    PLANET_NAME_REGISTRY.lock().unwrap().clear();
 
     match || -> rusqlite::Result<()> {
-        let mut stmt = database.prepare("SELECT planetId, planetName FROM planets ORDER BY planetId")?;
+        let mut stmt = database.prepare("SELECT planetId, planetName, ownerUserId, population, maxPopulation, \
+                                                fighters, shields, citadelLevel FROM planets ORDER BY planetId")?;
         let planet_iter = stmt.query_map([], |row| {
-            Ok(Planet { planet_id: row.get(0)?, planet_name: row.get(1)? })
+            Ok(Planet {
+                planet_id: row.get(0)?,
+                planet_name: row.get(1)?,
+                owner_user_id: row.get(2)?,
+                population: row.get(3)?,
+                max_population: row.get(4)?,
+                resources: Vec::new(),
+                fighters: row.get(5)?,
+                shields: row.get(6)?,
+                citadel_level: row.get(7)?,
+            })
         })?;
 
         let mut highest_planet_id: PlanetId = 0;
         for planet_result in planet_iter {
-            let planet = planet_result?;
+            let mut planet = planet_result?;
             highest_planet_id = planet.planet_id;
-            println!("Loaded planet: {}", planet.planet_name);
+
+            let mut stmt = database.prepare("SELECT commodity, deposit, stockpile, capacity, ratePerDay \
+                                                    FROM planet_resources WHERE planetId = :planetId ORDER BY commodity")?;
+            let resource_iter = stmt.query_map(&[(":planetId", &planet.planet_id)], |row| {
+                let code: i32 = row.get(0)?;
+                Ok((code, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+            })?;
+
+            for resource_result in resource_iter {
+                let (code, deposit, stockpile, capacity, rate_per_day) = resource_result?;
+                match Commodity::from_code(code) {
+                    Some(commodity) => planet.resources.push(PlanetResource { commodity, deposit, stockpile, capacity, rate_per_day }),
+                    None => log_warn!("Planet {} has unknown commodity code {}", planet.planet_id, code),
+                }
+            }
+
+            PLANET_NAME_REGISTRY.lock().unwrap().insert(planet.planet_name.clone());
+            log_info!("Loaded planet: {}", planet.planet_name);
             PLANETS.lock().unwrap().insert(planet.planet_id, planet);
         }
 
@@ -64,23 +242,225 @@ pub fn load_planets(database: &Connection) -> Result<(), String> {
     }
 }
 
+impl Actor for PlanetProductionActor {
+    fn act(&self) {
+        match database::with_database(run_daily_production) {
+            Ok(()) => log_info!("Planetary production complete"),
+            Err(e) => log_error!("{}", e),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
 impl Planet {
-    pub fn clone(&self) -> Planet {
-        Planet {planet_id: self.planet_id, planet_name: self.planet_name.clone()}
+    pub fn get_resource(&self, commodity: Commodity) -> Option<&PlanetResource> {
+        self.resources.iter().find(|resource| resource.commodity == commodity)
+    }
+
+    pub fn get_resource_mut(&mut self, commodity: Commodity) -> Option<&mut PlanetResource> {
+        self.resources.iter_mut().find(|resource| resource.commodity == commodity)
+    }
+
+    /// The name of the planet's owner, if it has one
+    pub fn get_owner_name(&self) -> Option<String> {
+        self.owner_user_id.and_then(user::get_user).map(|user| user.game_name)
+    }
+
+    /// The percentage strength of the garrison's fighters, given the citadel level
+    pub fn get_strength_percent(&self) -> i32 {
+        100 + self.citadel_level * CITADEL_STRENGTH_PERCENT
+    }
+
+    /// The price of building the next citadel level, or None if the citadel is already at MAX_CITADEL_LEVEL
+    pub fn get_citadel_upgrade_price(&self) -> Option<i64> {
+        if self.citadel_level >= MAX_CITADEL_LEVEL {
+            return None;
+        }
+        Some((self.citadel_level + 1) as i64 * CITADEL_UPGRADE_PRICE)
+    }
+
+    /// Creates a vector of strings to be sent to a user, describing the planet and its resources
+    pub fn get_description(&self) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        result.push(format!("Planet {}", self.planet_name));
+        match self.get_owner_name() {
+            Some(owner_name) => result.push(format!("  Owner: {}", owner_name)),
+            None => result.push("  Unclaimed".to_string()),
+        }
+        result.push(format!("  Population: {} (supports {})", self.population, self.max_population));
+        result.push(format!("  Defenses: {} fighters, {} shields, citadel level {}",
+                            self.fighters, self.shields, self.citadel_level));
+        for resource in &self.resources {
+            result.push(format!("  {:<10} deposit {:>6}  stockpile {:>5}/{:<5}  {:>4} per day",
+                                resource.commodity.name(), resource.deposit, resource.stockpile, resource.capacity,
+                                resource.get_daily_output(self.population)));
+        }
+        result
+    }
+
+    /// Creates a JSON representation of the planet, including its resources
+    pub fn to_json(&self) -> Value {
+        let resources: Vec<Value> = self.resources.iter().map(|resource| resource.to_json(self.population)).collect();
+        json!({
+            "planet_id": self.planet_id,
+            "planet_name": self.planet_name,
+            "owner_user_id": self.owner_user_id,
+            "owner_name": self.get_owner_name(),
+            "population": self.population,
+            "max_population": self.max_population,
+            "resources": resources,
+            "fighters": self.fighters,
+            "shields": self.shields,
+            "citadel_level": self.citadel_level,
+            "citadel_upgrade_price": self.get_citadel_upgrade_price(),
+        })
     }
 
     /// Writes information about this planet to the database.
     /// To be used when the planet is first created.
     pub fn persist(&self, database: &Connection) -> Result<(), String> {
         match || -> rusqlite::Result<()> {
-            // TODO resource columns
-            let statement = "INSERT INTO planets (planetId, planetName) VALUES (?1, ?2);";
-            let params = params![self.planet_id, self.planet_name];
+            let statement = "INSERT INTO planets (planetId, planetName, ownerUserId, population, maxPopulation, \
+                            fighters, shields, citadelLevel) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);";
+            let params = params![self.planet_id, self.planet_name, self.owner_user_id, self.population, self.max_population,
+                self.fighters, self.shields, self.citadel_level];
             database.execute(statement, params)?;
+
+            for resource in &self.resources {
+                let statement = "INSERT INTO planet_resources \
+                                (planetId, commodity, deposit, stockpile, capacity, ratePerDay) \
+                                VALUES (?1, ?2, ?3, ?4, ?5, ?6);";
+                let params = params![self.planet_id, resource.commodity.code(), resource.deposit, resource.stockpile,
+                    resource.capacity, resource.rate_per_day];
+                database.execute(statement, params)?;
+            }
             Ok(())
         }() {
             Ok(_) => Ok(()),
             Err(e) => Err(format!("Cannot persist planet:{}", e)),
         }
     }
+
+    // Writes the parts of the planet which change during play - owner, population, defenses, deposits and stockpiles
+    fn persist_state(&self, database: &Connection) -> rusqlite::Result<()> {
+        let statement = "UPDATE planets SET ownerUserId = ?2, population = ?3, fighters = ?4, shields = ?5, \
+                        citadelLevel = ?6 WHERE planetId = ?1;";
+        database.execute(statement, params![self.planet_id, self.owner_user_id, self.population,
+            self.fighters, self.shields, self.citadel_level])?;
+
+        for resource in &self.resources {
+            let statement = "UPDATE planet_resources SET deposit = ?3, stockpile = ?4 WHERE planetId = ?1 AND commodity = ?2;";
+            database.execute(statement, params![self.planet_id, resource.commodity.code(), resource.deposit, resource.stockpile])?;
+        }
+        Ok(())
+    }
+
+    // Advances the planet by one day - production first, based on the population at the start of the day
+    fn run_day(&mut self) {
+        for resource in self.resources.iter_mut() {
+            let output = resource.get_daily_output(self.population);
+            resource.deposit -= output;
+            resource.stockpile += output;
+        }
+        let growth = (self.population * POPULATION_GROWTH_PERCENT / 100).max(1);
+        self.population = (self.population + growth).min(self.max_population);
+    }
+}
+
+impl PlanetResource {
+    /// The number of units which will be added to the stockpile at the end of the day,
+    /// given the planet's population
+    pub fn get_daily_output(&self, population: i32) -> i32 {
+        let output = (population as i64 * self.rate_per_day as i64 / COLONISTS_PER_WORK_UNIT as i64) as i32;
+        output.min(self.deposit).min(self.capacity - self.stockpile).max(0)
+    }
+
+    pub fn to_json(&self, population: i32) -> Value {
+        json!({
+            "commodity": self.commodity.name(),
+            "deposit": self.deposit,
+            "stockpile": self.stockpile,
+            "capacity": self.capacity,
+            "rate_per_day": self.rate_per_day,
+            "daily_output": self.get_daily_output(population),
+        })
+    }
 }
+
+// List of pre-built planet names, from which names are chosen at random as planets are created.
+// Names are not reused while there are any left; once they run out, planets are named by number.
+const PLANET_NAMES: &[&str] = &[
+    "Aegir",
+    "Altair Prime",
+    "Amber",
+    "Arrakeen",
+    "Avalon",
+    "Barnard's Rest",
+    "Beulah",
+    "Bountiful",
+    "Caliban",
+    "Cassandra",
+    "Cinder",
+    "Corvus",
+    "Dagon",
+    "Demeter",
+    "Dustbowl",
+    "Eden",
+    "Elysium",
+    "Erewhon",
+    "Fairhaven",
+    "Farpoint",
+    "Gaspard",
+    "Gehenna",
+    "Glory",
+    "Halcyon",
+    "Harmony",
+    "Hesperus",
+    "Icarus",
+    "Ironhold",
+    "Jericho",
+    "Juno",
+    "Kestrel",
+    "Kepler's Folly",
+    "Lacuna",
+    "Last Chance",
+    "Lethe",
+    "Lorelei",
+    "Mariposa",
+    "Meridian",
+    "Mudball",
+    "Nadir",
+    "New Hope",
+    "Nostromo",
+    "Oberon",
+    "Oasis",
+    "Orison",
+    "Pandora",
+    "Paradox",
+    "Persephone",
+    "Quarry",
+    "Quietus",
+    "Rhea",
+    "Rockbottom",
+    "Rustbelt",
+    "Sanctuary",
+    "Serenity",
+    "Solace",
+    "Tamarind",
+    "Tethys",
+    "Thule",
+    "Umbra",
+    "Utopia",
+    "Valhalla",
+    "Verdant",
+    "Vesper",
+    "Wayfarer",
+    "Whistlestop",
+    "Xanadu",
+    "Yggdrasil",
+    "Zenith",
+    "Zephyr",
+];
@@ -1,21 +1,58 @@
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{LazyLock, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use rand::Rng;
 use rusqlite::{params, Connection};
+use serde_json::{json, Value};
+use crate::action::Actor;
+use crate::commodity::Commodity;
+use crate::database;
+use crate::ship::ShipId;
+use crate::{log_error, log_info, log_warn};
 
 pub type PortId = usize;
 
+const PRODUCTION_CYCLE_SECONDS: u32 = 300;
+
 static NEXT_PORT_ID: LazyLock<Mutex<PortId>> = LazyLock::new(|| Mutex::new(1));
 static PORT_NAME_REGISTRY: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
 static PORTS: LazyLock<Mutex<HashMap<PortId, Port>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
 
+const MIN_COMMODITY_CAPACITY: i32 = 1000;
+const MAX_COMMODITY_CAPACITY: i32 = 3000;
+const CYCLES_TO_REPLENISH: i32 = 20; // number of production cycles to go from empty to full
+
+#[derive(Clone)]
 pub struct Port {
     pub port_id: PortId,
     pub port_name: String, // derived from port_name_index
-    //TODO resource info... generation/usage per cycle, current cost/price
+    pub commodities: Vec<PortCommodity>, // one entry per commodity, in Commodity::ALL order
+}
+
+/// Indicates whether a port buys a commodity from ships, or sells it to them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TradeRole {
+    Buying,
+    Selling,
 }
 
-pub fn create_port(database: &Connection) -> Result<PortId, String> {
+/// Describes how a port deals in one particular commodity.
+/// For a selling port, quantity is the number of units in stock, and the port produces
+/// rate_per_cycle units each production cycle.
+/// For a buying port, quantity is the number of units the port is still willing to buy,
+/// and the port consumes rate_per_cycle units each production cycle (increasing its demand).
+/// In either case, quantity never exceeds capacity.
+#[derive(Clone)]
+pub struct PortCommodity {
+    pub commodity: Commodity,
+    pub role: TradeRole,
+    pub quantity: i32,
+    pub capacity: i32,
+    pub rate_per_cycle: i32,
+}
+
+pub fn create_port(database: &Connection, rng: &mut impl Rng) -> Result<PortId, String> {
     let mut next_port_id = NEXT_PORT_ID.lock().unwrap();
     let port_id = *next_port_id;
     *next_port_id += 1;
@@ -24,7 +61,7 @@ pub fn create_port(database: &Connection) -> Result<PortId, String> {
     let mut try_counter = 20;
     while try_counter > 0 {
         try_counter -= 1;
-        let index = rand::rng().random_range(0..PORT_NAMES.len());
+        let index = rng.random_range(0..PORT_NAMES.len());
         let proposed_name = PORT_NAMES[index];
         if !PORT_NAME_REGISTRY.lock().unwrap().contains(proposed_name) {
             port_name = proposed_name.to_owned();
@@ -33,7 +70,24 @@ pub fn create_port(database: &Connection) -> Result<PortId, String> {
         }
     }
 
-    let port = Port { port_id, port_name };
+    // Every port deals in every commodity, buying some and selling others.
+    // We don't want a port which only buys, or only sells, so if that happens we flip one of them.
+    let mut commodities: Vec<PortCommodity> = Vec::new();
+    for commodity in Commodity::ALL {
+        let role = if rng.random_bool(0.5) { TradeRole::Buying } else { TradeRole::Selling };
+        let capacity = rng.random_range(MIN_COMMODITY_CAPACITY..(MAX_COMMODITY_CAPACITY + 1));
+        let quantity = rng.random_range((capacity / 2)..(capacity + 1));
+        let rate_per_cycle = capacity / CYCLES_TO_REPLENISH;
+        commodities.push(PortCommodity { commodity, role, quantity, capacity, rate_per_cycle });
+    }
+
+    if commodities.iter().all(|pc| pc.role == commodities[0].role) {
+        let index = rng.random_range(0..commodities.len());
+        let entry = &mut commodities[index];
+        entry.role = if entry.role == TradeRole::Buying { TradeRole::Selling } else { TradeRole::Buying };
+    }
+
+    let port = Port { port_id, port_name, commodities };
     match port.persist(database) {
         Ok(_) => (),
         Err(e) => { return Err(e.to_string()); },
@@ -52,22 +106,153 @@ pub fn get_port(port_id: PortId) -> Option<Port> {
     None
 }
 
+/// Deletes a port, its commodities, and the record of past trades with it (which would otherwise refer to a
+/// port which no longer exists). The port should first be removed from its sector (see sector::remove_port).
+pub fn delete_port(database: &Connection, port_id: PortId) -> Result<(), String> {
+    let mut lock = PORTS.lock().unwrap();
+    if !lock.contains_key(&port_id) {
+        return Err(format!("Port {} does not exist", port_id));
+    }
+
+    match || -> rusqlite::Result<()> {
+        database.execute("DELETE FROM trades WHERE portId = ?1;", params![port_id])?;
+        database.execute("DELETE FROM port_commodities WHERE portId = ?1;", params![port_id])?;
+        database.execute("DELETE FROM ports WHERE portId = ?1;", params![port_id])?;
+        Ok(())
+    }() {
+        Ok(()) => (),
+        Err(e) => { return Err(format!("Cannot delete port {}:{}", port_id, e)); },
+    }
+
+    let port = lock.remove(&port_id).unwrap();
+    PORT_NAME_REGISTRY.lock().unwrap().remove(&port.port_name);
+    Ok(())
+}
+
+/// Records a trade between a ship and a port, adjusting the port's stock (or remaining demand)
+/// for the commodity accordingly - prices follow from the adjusted quantity.
+/// Quantity is always positive; the direction of the trade is implied by the port's role for the commodity.
+/// The caller is expected to have verified that the port can accommodate the trade,
+/// and is responsible for any transaction surrounding this function.
+///
+/// # Returns
+/// The port's new quantity of the commodity, which the caller passes to publish_quantity()
+/// once its transaction is committed - our in-memory copy of the port is left alone.
+pub fn record_trade(database: &Connection,
+                    port_id: PortId,
+                    ship_id: ShipId,
+                    commodity: Commodity,
+                    quantity: i32,
+                    unit_price: i32) -> Result<i32, String> {
+    let port = match get_port(port_id) {
+        Some(port) => port,
+        None => { return Err(format!("No such port {}", port_id)); },
+    };
+
+    let pc = match port.get_commodity(commodity) {
+        Some(pc) => pc,
+        None => { return Err(format!("Port {} does not trade in {}", port.port_name, commodity.name())); },
+    };
+
+    let new_quantity = pc.quantity - quantity;
+    match || -> rusqlite::Result<()> {
+        let statement = "UPDATE port_commodities SET quantity = ?3 WHERE portId = ?1 AND commodity = ?2;";
+        database.execute(statement, params![port_id, commodity.code(), new_quantity])?;
+
+        let statement = "INSERT INTO trades (timeStamp, portId, shipId, commodity, quantity, unitPrice, isPurchase) \
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);";
+        let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let params = params![unix_time, port_id, ship_id, commodity.code(), quantity, unit_price,
+            pc.role == TradeRole::Selling];
+        database.execute(statement, params)?;
+        Ok(())
+    }() {
+        Ok(()) => Ok(new_quantity),
+        Err(e) => Err(format!("Cannot record trade:{}", e)),
+    }
+}
+
+/// Updates our in-memory copy of the port's quantity of the commodity, as returned by record_trade() -
+/// to be called only after the transaction in which the trade was recorded is committed.
+pub fn publish_quantity(port_id: PortId, commodity: Commodity, quantity: i32) {
+    let mut lock = PORTS.lock().unwrap();
+    let pc = lock.get_mut(&port_id).and_then(|port| port.commodities.iter_mut().find(|pc| pc.commodity == commodity));
+    if let Some(pc) = pc {
+        pc.quantity = quantity;
+    }
+}
+
+/// Coarse actor which runs a production cycle at every port every PRODUCTION_CYCLE_SECONDS
+#[derive(Default)]
+pub struct ProductionActor {
+    seconds_elapsed: AtomicU32,
+}
+
+/// Applies one production cycle to every commodity at every port, persisting the new quantities.
+/// Commodities which are already at capacity are left alone.
+pub fn run_production_cycle(database: &mut Connection) -> Result<(), String> {
+    let transaction = database.transaction().map_err(|e| format!("Cannot run production cycle:{}", e))?;
+    let mut lock = PORTS.lock().unwrap();
+    let mut updated: Vec<(PortId, Commodity, i32)> = Vec::new();
+    for port in lock.values() {
+        for pc in port.commodities.iter().filter(|pc| pc.quantity < pc.capacity) {
+            let mut pc = pc.clone();
+            pc.run_cycle();
+            let statement = "UPDATE port_commodities SET quantity = ?3 WHERE portId = ?1 AND commodity = ?2;";
+            transaction.execute(statement, params![port.port_id, pc.commodity.code(), pc.quantity])
+                .map_err(|e| format!("Cannot run production cycle:{}", e))?;
+            updated.push((port.port_id, pc.commodity, pc.quantity));
+        }
+    }
+    transaction.commit().map_err(|e| format!("Cannot run production cycle:{}", e))?;
+
+    // Only now that the changes are committed do we update our in-memory copies
+    for (port_id, commodity, quantity) in updated {
+        let port = lock.get_mut(&port_id).unwrap();
+        if let Some(pc) = port.commodities.iter_mut().find(|pc| pc.commodity == commodity) {
+            pc.quantity = quantity;
+        }
+    }
+    Ok(())
+}
+
 /// Creates a port map describing all the ports in the universe - Used when a game starts up.
 pub fn load_ports(database: &Connection) -> Result<(), String> {
     PORTS.lock().unwrap().clear();
+    PORT_NAME_REGISTRY.lock().unwrap().clear();
 
     match || -> rusqlite::Result<()> {
         let mut stmt = database.prepare("SELECT portId, portName FROM ports ORDER BY portId")?;
         let port_iter = stmt.query_map([], |row| {
-            Ok(Port { port_id: row.get(0)?, port_name: row.get(1)? })
+            Ok(Port { port_id: row.get(0)?, port_name: row.get(1)?, commodities: Vec::new() })
         })?;
 
         let mut highest_port_id: PortId = 0;
         for port_result in port_iter {
-            let port = port_result?;
+            let mut port = port_result?;
             highest_port_id = port.port_id;
+
+            let mut stmt = database.prepare("SELECT commodity, isSelling, quantity, capacity, ratePerCycle \
+                                                    FROM port_commodities WHERE portId = :portId ORDER BY commodity")?;
+            let commodity_iter = stmt.query_map(&[(":portId", &port.port_id)], |row| {
+                let code: i32 = row.get(0)?;
+                let is_selling: bool = row.get(1)?;
+                Ok((code, is_selling, row.get(2)?, row.get(3)?, row.get(4)?))
+            })?;
+
+            for commodity_result in commodity_iter {
+                let (code, is_selling, quantity, capacity, rate_per_cycle) = commodity_result?;
+                match Commodity::from_code(code) {
+                    Some(commodity) => {
+                        let role = if is_selling { TradeRole::Selling } else { TradeRole::Buying };
+                        port.commodities.push(PortCommodity { commodity, role, quantity, capacity, rate_per_cycle });
+                    },
+                    None => log_warn!("Port {} has unknown commodity code {}", port.port_id, code),
+                }
+            }
+
             PORT_NAME_REGISTRY.lock().unwrap().insert(port.port_name.clone());
-            println!("Loaded port: {}", port.port_name);
+            log_info!("Loaded port: {}", port.port_name);
             PORTS.lock().unwrap().insert(port.port_id, port);
         }
 
@@ -79,9 +264,50 @@ pub fn load_ports(database: &Connection) -> Result<(), String> {
     }
 }
 
+impl Actor for ProductionActor {
+    fn act(&self) {
+        if self.seconds_elapsed.fetch_add(1, Ordering::SeqCst) + 1 < PRODUCTION_CYCLE_SECONDS {
+            return;
+        }
+        self.seconds_elapsed.store(0, Ordering::SeqCst);
+        if let Err(e) = database::with_database(run_production_cycle) {
+            log_error!("{}", e);
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
 impl Port {
-    pub fn clone(&self) -> Port {
-        Port { port_id: self.port_id, port_name: self.port_name.clone() }
+    pub fn get_commodity(&self, commodity: Commodity) -> Option<&PortCommodity> {
+        self.commodities.iter().find(|pc| pc.commodity == commodity)
+    }
+
+    /// Creates a vector of strings to be sent to a user, describing what the port buys and sells
+    pub fn get_description(&self) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        result.push(format!("Port {}", self.port_name));
+        for pc in &self.commodities {
+            let role = match pc.role {
+                TradeRole::Buying => "Buying",
+                TradeRole::Selling => "Selling",
+            };
+            result.push(format!("  {:<10} {:<8} {:>5} units at {:>4} credits",
+                                pc.commodity.name(), role, pc.quantity, pc.current_price()));
+        }
+        result
+    }
+
+    /// Creates a JSON representation of the port, including current prices
+    pub fn to_json(&self) -> Value {
+        let commodities: Vec<Value> = self.commodities.iter().map(|pc| pc.to_json()).collect();
+        json!({
+            "port_id": self.port_id,
+            "port_name": self.port_name,
+            "commodities": commodities,
+        })
     }
 
     /// Writes information about this port to the database.
@@ -91,6 +317,16 @@ impl Port {
             let statement = "INSERT INTO ports (portId, portName) VALUES (?1, ?2);";
             let params = params![self.port_id, self.port_name];
             database.execute(statement, params)?;
+
+            for pc in &self.commodities {
+                let statement = "INSERT INTO port_commodities \
+                                (portId, commodity, isSelling, quantity, capacity, ratePerCycle) \
+                                VALUES (?1, ?2, ?3, ?4, ?5, ?6);";
+                let params = params![self.port_id, pc.commodity.code(), pc.role == TradeRole::Selling,
+                    pc.quantity, pc.capacity, pc.rate_per_cycle];
+                database.execute(statement, params)?;
+            }
+
             Ok(())
         }() {
             Ok(()) => Ok(()),
@@ -99,6 +335,41 @@ impl Port {
     }
 }
 
+impl PortCommodity {
+    /// The price per unit at which the port currently trades this commodity.
+    /// Prices move with supply - a selling port which is well-stocked sells cheaply,
+    /// while a buying port with a lot of unmet demand pays well.
+    /// A selling port ranges from 120% (empty) down to 80% (full) of the base price,
+    /// while a buying port ranges from 90% (no demand) up to 130% (full demand).
+    pub fn current_price(&self) -> i32 {
+        let fill_percent = self.quantity * 100 / self.capacity.max(1);
+        let percent = match self.role {
+            TradeRole::Selling => 120 - (fill_percent * 40 / 100),
+            TradeRole::Buying => 90 + (fill_percent * 40 / 100),
+        };
+        (self.commodity.base_price() * percent / 100).max(1)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let role = match self.role {
+            TradeRole::Buying => "buying",
+            TradeRole::Selling => "selling",
+        };
+        json!({
+            "commodity": self.commodity.name(),
+            "role": role,
+            "quantity": self.quantity,
+            "capacity": self.capacity,
+            "price": self.current_price(),
+        })
+    }
+
+    /// Applies one production (or consumption) cycle to this commodity
+    pub fn run_cycle(&mut self) {
+        self.quantity = (self.quantity + self.rate_per_cycle).min(self.capacity);
+    }
+}
+
 // List of pre-built port names - corresponds to the port_name_index value.
 // You can add names to this list without reinitializing your game, but this will cause
 // all of your port names to change beyond the point at which you first added a name.
@@ -2,21 +2,31 @@ use crate::planet::PlanetId;
 use crate::port::PortId;
 
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{LazyLock, Mutex};
 use rusqlite::{params, Connection};
+use serde_json::{json, Value};
 use crate::{planet, port};
+use crate::{log_debug, log_info};
 
 pub type SectorId = usize;
 
+pub const MAX_LINKS_PER_SECTOR: usize = 6;
+
 static NEXT_SECTOR_ID: LazyLock<Mutex<SectorId>> = LazyLock::new(|| Mutex::new(1));
 static SECTORS: LazyLock<Mutex<HashMap<SectorId, Sector>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
 
+// Incremented whenever links are changed, so that anything derived from the links (such as cached
+// distances) can tell when it is stale.
+static LINK_GENERATION: AtomicU64 = AtomicU64::new(0);
+
 /// Represents a location in space, conceptually contained within a galaxy.
 /// Sectors have no names, being identified only by their integer id.
 /// A sector may contain a port.
 /// A sector may contain a planet.
 /// Various space-craft (including ships, missiles, fighters, etc.) may be temporarily located in a sector,
-/// but the Sector struct has no knowledge of this.
+/// but the Sector struct has no knowledge of this - see ship::Ship for the location of ships,
+/// and deployment::Deployment for fighters and mines left to guard a sector.
 pub struct Sector {
     pub sector_id: SectorId,
     pub planet_id: Option<PlanetId>,
@@ -49,42 +59,231 @@ pub fn get_sector(sector_id: SectorId) -> Option<Sector> {
     }
 }
 
-pub fn link_sectors(sector_id1: SectorId, sector_id2: SectorId) {
-    println!("{}<->{}", sector_id1, sector_id2);//TODO remove
-    if sector_id1 == sector_id2 {
-        panic!("Attempt to link sector {} to itself", sector_id1);
+/// Takes a snapshot of the links from each of the given sectors to the others among them,
+/// locking the sector map only once. Each list of links is sorted, and links to sectors
+/// outside the given set are omitted.
+pub fn get_link_map(sector_ids: &HashSet<SectorId>) -> HashMap<SectorId, Vec<SectorId>> {
+    let lock = SECTORS.lock().unwrap();
+    sector_ids.iter()
+        .filter_map(|sector_id| lock.get(sector_id))
+        .map(|sector| {
+            let mut links: Vec<SectorId> = sector.sector_links.iter()
+                .filter(|link| sector_ids.contains(link))
+                .copied()
+                .collect();
+            links.sort();
+            (sector.sector_id, links)
+        })
+        .collect()
+}
+
+/// Identifies the current state of the links between sectors - the value changes whenever any link changes
+pub fn get_link_generation() -> u64 {
+    LINK_GENERATION.load(Ordering::SeqCst)
+}
+
+/// Finds the ports in the given sectors, locking the sector map only once
+pub fn get_port_ids(sector_ids: &HashSet<SectorId>) -> HashMap<SectorId, PortId> {
+    let lock = SECTORS.lock().unwrap();
+    sector_ids.iter()
+        .filter_map(|sector_id| lock.get(sector_id))
+        .filter_map(|sector| sector.port_id.map(|port_id| (sector.sector_id, port_id)))
+        .collect()
+}
+
+// Sector mutations ------------------------------------------------------------------------------
+// Each of the following functions writes the change to the database first, and only then applies it to
+// the in-memory sector map - so if the database write fails, neither is changed. Callers making several
+// related changes should do so within a transaction, and should not carry on using the sector map
+// if that transaction is rolled back (as a game server, reload from the database - see galaxy::reload_galaxies).
+
+/// Links two sectors to each other, so that a ship can move in either direction.
+/// Neither sector may already be at the maximum number of links, and they must not already be linked.
+pub fn link_sectors(database: &Connection, sector_id1: SectorId, sector_id2: SectorId) -> Result<(), String> {
+    log_debug!("{}<->{}", sector_id1, sector_id2);
+    let mut lock = SECTORS.lock().unwrap();
+    check_new_link(&lock, sector_id1, sector_id2)?;
+    check_new_link(&lock, sector_id2, sector_id1)?;
+
+    // a single statement, so that we never end up with only one direction of the pair
+    let statement = "INSERT INTO sector_links (fromSectorId, toSectorId) VALUES (?1, ?2), (?2, ?1);";
+    database.execute(statement, params![sector_id1, sector_id2])
+        .map_err(|e| format!("Cannot link sectors {} and {}:{}", sector_id1, sector_id2, e))?;
+
+    lock.get_mut(&sector_id1).unwrap().insert_link_to(sector_id2);
+    lock.get_mut(&sector_id2).unwrap().insert_link_to(sector_id1);
+    LINK_GENERATION.fetch_add(1, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Links one sector to another such that a ship can move from the first to the second, but not back again
+/// (unless the second sector already links to the first). Only the first sector's link count is affected.
+pub fn link_sector_one_way(database: &Connection, from_sector_id: SectorId, to_sector_id: SectorId) -> Result<(), String> {
+    log_debug!("{}->{}", from_sector_id, to_sector_id);
+    let mut lock = SECTORS.lock().unwrap();
+    check_new_link(&lock, from_sector_id, to_sector_id)?;
+    if !lock.contains_key(&to_sector_id) {
+        return Err(format!("Sector {} does not exist", to_sector_id));
+    }
+
+    let statement = "INSERT INTO sector_links (fromSectorId, toSectorId) VALUES (?1, ?2);";
+    database.execute(statement, params![from_sector_id, to_sector_id])
+        .map_err(|e| format!("Cannot link sector {} to {}:{}", from_sector_id, to_sector_id, e))?;
+
+    lock.get_mut(&from_sector_id).unwrap().insert_link_to(to_sector_id);
+    LINK_GENERATION.fetch_add(1, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Removes the links in both directions between two sectors, which must be linked both ways
+pub fn unlink_sectors(database: &Connection, sector_id1: SectorId, sector_id2: SectorId) -> Result<(), String> {
+    log_debug!("{}<x>{}", sector_id1, sector_id2);
+    let mut lock = SECTORS.lock().unwrap();
+    check_existing_link(&lock, sector_id1, sector_id2)?;
+    check_existing_link(&lock, sector_id2, sector_id1)?;
+
+    let statement = "DELETE FROM sector_links WHERE (fromSectorId = ?1 AND toSectorId = ?2) \
+                                                OR (fromSectorId = ?2 AND toSectorId = ?1);";
+    database.execute(statement, params![sector_id1, sector_id2])
+        .map_err(|e| format!("Cannot unlink sectors {} and {}:{}", sector_id1, sector_id2, e))?;
+
+    lock.get_mut(&sector_id1).unwrap().sector_links.remove(&sector_id2);
+    lock.get_mut(&sector_id2).unwrap().sector_links.remove(&sector_id1);
+    LINK_GENERATION.fetch_add(1, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Removes the link from one sector to another, leaving any link in the other direction in place
+pub fn unlink_sector_one_way(database: &Connection, from_sector_id: SectorId, to_sector_id: SectorId) -> Result<(), String> {
+    log_debug!("{}x>{}", from_sector_id, to_sector_id);
+    let mut lock = SECTORS.lock().unwrap();
+    check_existing_link(&lock, from_sector_id, to_sector_id)?;
+
+    let statement = "DELETE FROM sector_links WHERE fromSectorId = ?1 AND toSectorId = ?2;";
+    database.execute(statement, params![from_sector_id, to_sector_id])
+        .map_err(|e| format!("Cannot unlink sector {} from {}:{}", from_sector_id, to_sector_id, e))?;
+
+    lock.get_mut(&from_sector_id).unwrap().sector_links.remove(&to_sector_id);
+    LINK_GENERATION.fetch_add(1, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Places a port in a sector which does not already have one
+pub fn assign_port(database: &Connection, sector_id: SectorId, port_id: PortId) -> Result<(), String> {
+    let mut lock = SECTORS.lock().unwrap();
+    match lock.get(&sector_id) {
+        None => { return Err(format!("Sector {} does not exist", sector_id)); },
+        Some(sector) if sector.has_port() => { return Err(format!("Sector {} already has a port", sector_id)); },
+        Some(_) => (),
     }
 
-    if !SECTORS.lock().unwrap().contains_key(&sector_id1) {
-        panic!("Attempt to link unknown sector {}", sector_id1);
+    let statement = "INSERT INTO sectors_to_ports (sectorId, portId) VALUES (?1, ?2);";
+    database.execute(statement, params![sector_id, port_id])
+        .map_err(|e| format!("Cannot place port {} in sector {}:{}", port_id, sector_id, e))?;
+
+    lock.get_mut(&sector_id).unwrap().port_id.replace(port_id);
+    Ok(())
+}
+
+/// Moves a port from the sector it is in to another sector which does not already have a port
+pub fn move_port(database: &Connection, port_id: PortId, to_sector_id: SectorId) -> Result<(), String> {
+    let mut lock = SECTORS.lock().unwrap();
+    let from_sector_id = match lock.values().find(|sector| sector.port_id == Some(port_id)) {
+        Some(sector) => sector.sector_id,
+        None => { return Err(format!("Port {} is not in any sector", port_id)); },
+    };
+    match lock.get(&to_sector_id) {
+        None => { return Err(format!("Sector {} does not exist", to_sector_id)); },
+        Some(sector) if sector.has_port() => { return Err(format!("Sector {} already has a port", to_sector_id)); },
+        Some(_) => (),
     }
 
-    if !SECTORS.lock().unwrap().contains_key(&sector_id2) {
-        panic!("Attempt to link unknown sector {}", sector_id2);
+    let statement = "UPDATE sectors_to_ports SET sectorId = ?1 WHERE portId = ?2;";
+    database.execute(statement, params![to_sector_id, port_id])
+        .map_err(|e| format!("Cannot move port {} to sector {}:{}", port_id, to_sector_id, e))?;
+
+    lock.get_mut(&from_sector_id).unwrap().port_id = None;
+    lock.get_mut(&to_sector_id).unwrap().port_id = Some(port_id);
+    Ok(())
+}
+
+/// Removes the port (if any) from a sector, returning the id of the port which was removed.
+/// The port itself continues to exist - see port::delete_port.
+pub fn remove_port(database: &Connection, sector_id: SectorId) -> Result<Option<PortId>, String> {
+    let mut lock = SECTORS.lock().unwrap();
+    let port_id = match lock.get(&sector_id) {
+        Some(sector) => sector.port_id,
+        None => { return Err(format!("Sector {} does not exist", sector_id)); },
+    };
+
+    if port_id.is_some() {
+        let statement = "DELETE FROM sectors_to_ports WHERE sectorId = ?1;";
+        database.execute(statement, params![sector_id])
+            .map_err(|e| format!("Cannot remove port from sector {}:{}", sector_id, e))?;
+        lock.get_mut(&sector_id).unwrap().port_id = None;
     }
+    Ok(port_id)
+}
 
+/// Places a planet in a sector which does not already have one
+pub fn assign_planet(database: &Connection, sector_id: SectorId, planet_id: PlanetId) -> Result<(), String> {
     let mut lock = SECTORS.lock().unwrap();
-    {
-        let sector1 = lock.get(&sector_id1).unwrap();
-        if sector1.has_max_links() {
-            panic!("Attempt to link sector {} which has max links", sector_id1);
-        }
+    match lock.get(&sector_id) {
+        None => { return Err(format!("Sector {} does not exist", sector_id)); },
+        Some(sector) if sector.has_planet() => { return Err(format!("Sector {} already has a planet", sector_id)); },
+        Some(_) => (),
+    }
 
-        let sector2 = lock.get(&sector_id2).unwrap();
-        if sector2.has_max_links() {
-            panic!("Attempt to link sector {} which has max links", sector_id2);
-        }
+    let statement = "INSERT INTO sectors_to_planets (sectorId, planetId) VALUES (?1, ?2);";
+    database.execute(statement, params![sector_id, planet_id])
+        .map_err(|e| format!("Cannot place planet {} in sector {}:{}", planet_id, sector_id, e))?;
+
+    lock.get_mut(&sector_id).unwrap().planet_id.replace(planet_id);
+    Ok(())
+}
+
+/// Finds the sector containing the given port
+pub fn find_sector_with_port(port_id: PortId) -> Option<SectorId> {
+    SECTORS.lock().unwrap().values().find(|sector| sector.port_id == Some(port_id)).map(|sector| sector.sector_id)
+}
+
+/// Finds the sector containing the given planet
+pub fn find_sector_with_planet(planet_id: PlanetId) -> Option<SectorId> {
+    SECTORS.lock().unwrap().values().find(|sector| sector.planet_id == Some(planet_id)).map(|sector| sector.sector_id)
+}
+
+// Checks that there is a link from one sector to another, which may be removed
+fn check_existing_link(sectors: &HashMap<SectorId, Sector>, from_sector_id: SectorId, to_sector_id: SectorId) -> Result<(), String> {
+    match sectors.get(&from_sector_id) {
+        None => Err(format!("Sector {} does not exist", from_sector_id)),
+        Some(sector) if !sector.sector_links.contains(&to_sector_id) => {
+            Err(format!("Sector {} is not linked to sector {}", from_sector_id, to_sector_id))
+        },
+        Some(_) => Ok(()),
     }
+}
 
-    lock.get_mut(&sector_id1).unwrap().insert_link_to(sector_id2);
-    lock.get_mut(&sector_id2).unwrap().insert_link_to(sector_id1);
+// Checks that a new link may be added from one sector to another
+fn check_new_link(sectors: &HashMap<SectorId, Sector>, from_sector_id: SectorId, to_sector_id: SectorId) -> Result<(), String> {
+    if from_sector_id == to_sector_id {
+        return Err(format!("Cannot link sector {} to itself", from_sector_id));
+    }
+    match sectors.get(&from_sector_id) {
+        None => Err(format!("Sector {} does not exist", from_sector_id)),
+        Some(sector) if sector.sector_links.contains(&to_sector_id) => {
+            Err(format!("Sector {} is already linked to sector {}", from_sector_id, to_sector_id))
+        },
+        Some(sector) if sector.has_max_links() => Err(format!("Sector {} has the maximum number of links", from_sector_id)),
+        Some(_) => Ok(()),
+    }
 }
 
 // for use by Galaxy::load_galaxies
 // Creates a map of all sectors in the universe - Used at game startup.
 pub fn load_sectors(database: &Connection) -> Result<(), String> {
-    // initial load of all sectors
+    // initial load of all sectors - the links may differ from any we had before, so cached distances are stale
     SECTORS.lock().unwrap().clear();
+    LINK_GENERATION.fetch_add(1, Ordering::SeqCst);
 
     match || -> rusqlite::Result<()> {
         // build sectors
@@ -132,8 +331,9 @@ pub fn load_sectors(database: &Connection) -> Result<(), String> {
             SECTORS.lock().unwrap().insert(sector.sector_id, sector);
         }
 
-        *NEXT_SECTOR_ID.lock().unwrap() = highest_sector_id;
-        println!("Loaded {} sectors", SECTORS.lock().unwrap().len());
+        LINK_GENERATION.fetch_add(1, Ordering::SeqCst);
+        *NEXT_SECTOR_ID.lock().unwrap() = highest_sector_id + 1;
+        log_info!("Loaded {} sectors", SECTORS.lock().unwrap().len());
         Ok(())
     }() {
         Ok(()) => Ok(()),
@@ -141,16 +341,6 @@ pub fn load_sectors(database: &Connection) -> Result<(), String> {
     }
 }
 
-// Stores a PlanetId in this sector. Used only during loading, so we don't need to update the database
-pub fn set_planet_id(sector_id: SectorId, planet_id: PlanetId) {
-    SECTORS.lock().unwrap().get_mut(&sector_id).unwrap().planet_id.replace(planet_id);
-}
-
-// Stores a PortId in this sector. Used only during loading, so we don't need to update the database
-pub fn set_sector_port_id(sector_id: SectorId, port_id: PortId) {
-    SECTORS.lock().unwrap().get_mut(&sector_id).unwrap().port_id.replace(port_id);
-}
-
 impl Sector {
     pub fn clone(&self) -> Sector {
         Sector{ sector_id: self.sector_id,
@@ -168,8 +358,11 @@ impl Sector {
             .collect();
         result.push(format!("  Links to: {}", string_numbers.join(" ")));
         if self.planet_id.is_some() {
-            let planet_name = planet::get_planet(self.planet_id.unwrap()).unwrap().planet_name;
-            result.push(format!("  Planet: {}", planet_name));
+            let planet = planet::get_planet(self.planet_id.unwrap()).unwrap();
+            match planet.get_owner_name() {
+                Some(owner_name) => result.push(format!("  Planet: {} (owned by {})", planet.planet_name, owner_name)),
+                None => result.push(format!("  Planet: {} (unclaimed)", planet.planet_name)),
+            }
         }
         if self.port_id.is_some() {
             let port_name = port::get_port(self.port_id.unwrap()).unwrap().port_name;
@@ -178,12 +371,26 @@ impl Sector {
         result
     }
 
+    /// Creates a JSON representation of the sector, including any port or planet therein
+    pub fn to_json(&self) -> Value {
+        let mut links: Vec<SectorId> = self.sector_links.iter().copied().collect();
+        links.sort();
+        let planet = self.planet_id.and_then(planet::get_planet).map(|planet| planet.to_json());
+        let port = self.port_id.and_then(port::get_port).map(|port| port.to_json());
+        json!({
+            "sector_id": self.sector_id,
+            "links": links,
+            "planet": planet,
+            "port": port,
+        })
+    }
+
     pub fn get_link_count(&self) -> usize {
-        SECTORS.lock().unwrap().len()
+        self.sector_links.len()
     }
 
     pub fn has_max_links(&self) -> bool {
-        self.sector_links.len() >= 6
+        self.sector_links.len() >= MAX_LINKS_PER_SECTOR
     }
 
     pub fn has_planet(&self) -> bool {
@@ -194,12 +401,13 @@ impl Sector {
         self.port_id.is_some()
     }
 
-    pub(crate) fn insert_link_to(&mut self, sector_id: SectorId) {
+    fn insert_link_to(&mut self, sector_id: SectorId) {
         self.sector_links.insert(sector_id);
     }
 
     /// Writes information about this sector to the database.
-    /// To be used when the sector is first created, and only after ports and planets are all persisted.
+    /// To be used when the sector is first created - later changes are written by link_sectors,
+    /// link_sector_one_way, assign_port and assign_planet.
     pub fn persist(&self, database: &Connection) -> rusqlite::Result<(), String> {
         match || -> rusqlite::Result<()> {
             let statement = "INSERT INTO sectors (sectorId) VALUES (?1);";
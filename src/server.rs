@@ -1,7 +1,7 @@
-use std::collections::{HashMap};
-use std::io::{BufReader, prelude::*};
+use std::io::prelude::*;
+use std::str::FromStr;
 use std::net::{TcpListener, TcpStream};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{io, thread};
 use std::sync::{LazyLock, Mutex};
 use std::sync::atomic::AtomicBool;
@@ -10,10 +10,23 @@ use base64::Engine;
 use base64::engine::general_purpose;
 use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use crate::http_request::HttpRequest;
 use crate::http_response::*;
-use crate::session::{Session, SessionId};
-use crate::{session, user};
+use crate::session::{Session, SessionId, SessionStatus};
+use crate::sector::SectorId;
+use crate::commodity::Commodity;
+use crate::planet::PlanetId;
+use crate::port::{Port, PortId, TradeRole};
+use crate::ship::{Ship, ShipId};
+use crate::combat::CombatOrder;
+use crate::deployment::EncounterResponse;
+use crate::message::{Message, MessageId};
+use crate::user::User;
+use crate::{colony, combat, database, deployment, galaxy, message, planet, port, sector, session, ship, trade, user};
+use crate::galaxy::{Galaxy, GalaxyId, GalaxyReport};
 use crate::user::ValidationResult;
+use crate::{log_debug, log_error, log_info};
 
 pub static IS_ACTIVE: AtomicBool = AtomicBool::new(false);
 pub static HANDLER_HANDLES: LazyLock<Mutex<Vec<JoinHandle<()>>>> = LazyLock::new(|| Mutex::new(vec![]));
@@ -21,12 +34,18 @@ pub static JOIN_HANDLE: LazyLock<Mutex<Option<JoinHandle<()>>>> = LazyLock::new(
 pub static TERMINATE_FLAG: AtomicBool = AtomicBool::new(false);
 const MILLISECONDS_BETWEEN_NONBLOCKING_CALLS: u64 = 100;
 const HANDLER_PRUNE_RATIO: i32 = 100;
+pub const POLL_TIMEOUT_SECONDS: u64 = 20; // sessions must not time out while a poll is waiting
+const POLL_SLICE_MILLISECONDS: u64 = 500;
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+const DEFAULT_NEARBY_HOPS: usize = 5;
+const MAX_NEARBY_HOPS: usize = 10;
 
 struct HandlerEntry {
     method: &'static str,
     path: &'static str,
     is_restricted: bool,
-    func: fn (&Session) -> HttpResponse,
+    func: fn (&Session, &HttpRequest) -> HttpResponse,
 }
 
 lazy_static! {
@@ -34,7 +53,45 @@ lazy_static! {
         let mut table = Vec::new();
         table.push(HandlerEntry {method: "POST", path: "/admin/quit", is_restricted: true, func: handle_admin_quit});
         table.push(HandlerEntry {method: "POST", path: "/session/logout", is_restricted: false, func: handle_session_logout});
+        table.push(HandlerEntry {method: "POST", path: "/admin/broadcast", is_restricted: true, func: handle_admin_broadcast});
+        table.push(HandlerEntry {method: "GET", path: "/admin/galaxy/report", is_restricted: true, func: handle_admin_galaxy_report});
+        table.push(HandlerEntry {method: "POST", path: "/admin/galaxy/rename", is_restricted: true, func: handle_admin_galaxy_rename});
+        table.push(HandlerEntry {method: "POST", path: "/admin/sector/link", is_restricted: true, func: handle_admin_sector_link});
+        table.push(HandlerEntry {method: "POST", path: "/admin/sector/unlink", is_restricted: true, func: handle_admin_sector_unlink});
+        table.push(HandlerEntry {method: "POST", path: "/admin/port/move", is_restricted: true, func: handle_admin_port_move});
+        table.push(HandlerEntry {method: "POST", path: "/admin/port/delete", is_restricted: true, func: handle_admin_port_delete});
+        table.push(HandlerEntry {method: "POST", path: "/admin/planet/attach", is_restricted: true, func: handle_admin_planet_attach});
         table.push(HandlerEntry {method: "GET", path: "/message/poll", is_restricted: false, func: handle_message_poll});
+        table.push(HandlerEntry {method: "POST", path: "/message/send", is_restricted: false, func: handle_message_send});
+        table.push(HandlerEntry {method: "GET", path: "/message/inbox", is_restricted: false, func: handle_message_inbox});
+        table.push(HandlerEntry {method: "GET", path: "/message/outbox", is_restricted: false, func: handle_message_outbox});
+        table.push(HandlerEntry {method: "GET", path: "/sector", is_restricted: false, func: handle_sector_look});
+        table.push(HandlerEntry {method: "GET", path: "/sector/path", is_restricted: false, func: handle_sector_path});
+        table.push(HandlerEntry {method: "GET", path: "/sector/distance", is_restricted: false, func: handle_sector_distance});
+        table.push(HandlerEntry {method: "GET", path: "/sector/nearby", is_restricted: false, func: handle_sector_nearby});
+        table.push(HandlerEntry {method: "POST", path: "/sector/deploy", is_restricted: false, func: handle_sector_deploy});
+        table.push(HandlerEntry {method: "POST", path: "/sector/recall", is_restricted: false, func: handle_sector_recall});
+        table.push(HandlerEntry {method: "GET", path: "/ship", is_restricted: false, func: handle_ship_status});
+        table.push(HandlerEntry {method: "POST", path: "/ship/move", is_restricted: false, func: handle_ship_move});
+        table.push(HandlerEntry {method: "GET", path: "/user/who", is_restricted: false, func: handle_user_who});
+        table.push(HandlerEntry {method: "GET", path: "/planet", is_restricted: false, func: handle_planet_scan});
+        table.push(HandlerEntry {method: "POST", path: "/planet/attack", is_restricted: false, func: handle_planet_attack});
+        table.push(HandlerEntry {method: "POST", path: "/planet/citadel", is_restricted: false, func: handle_planet_citadel});
+        table.push(HandlerEntry {method: "POST", path: "/planet/claim", is_restricted: false, func: handle_planet_claim});
+        table.push(HandlerEntry {method: "POST", path: "/planet/fortify", is_restricted: false, func: handle_planet_fortify});
+        table.push(HandlerEntry {method: "POST", path: "/planet/land", is_restricted: false, func: handle_planet_land});
+        table.push(HandlerEntry {method: "POST", path: "/planet/withdraw", is_restricted: false, func: handle_planet_withdraw});
+        table.push(HandlerEntry {method: "POST", path: "/ship/attack", is_restricted: false, func: handle_ship_attack});
+        table.push(HandlerEntry {method: "POST", path: "/ship/orders", is_restricted: false, func: handle_ship_orders});
+        table.push(HandlerEntry {method: "POST", path: "/ship/outfit", is_restricted: false, func: handle_ship_outfit});
+        table.push(HandlerEntry {method: "POST", path: "/ship/recruit", is_restricted: false, func: handle_ship_recruit});
+        table.push(HandlerEntry {method: "POST", path: "/ship/refuel", is_restricted: false, func: handle_ship_refuel});
+        table.push(HandlerEntry {method: "POST", path: "/ship/repair", is_restricted: false, func: handle_ship_repair});
+        table.push(HandlerEntry {method: "GET", path: "/port", is_restricted: false, func: handle_port_report});
+        table.push(HandlerEntry {method: "GET", path: "/port/nearest", is_restricted: false, func: handle_port_nearest});
+        table.push(HandlerEntry {method: "GET", path: "/port/nearby", is_restricted: false, func: handle_port_nearby});
+        table.push(HandlerEntry {method: "POST", path: "/port/buy", is_restricted: false, func: handle_port_buy});
+        table.push(HandlerEntry {method: "POST", path: "/port/sell", is_restricted: false, func: handle_port_sell});
         table.push(HandlerEntry {method: "GET", path: "/", is_restricted: false, func: handle_no_operation});
         table
     };
@@ -46,10 +103,15 @@ pub fn is_active() {
     IS_ACTIVE.store(true, std::sync::atomic::Ordering::SeqCst);
 }
 
-pub fn start() {
+/// Starts the server listening on the given address (ip-address:port).
+/// Fails if the address cannot be bound - otherwise, connections are handled on a separate thread.
+pub fn start(bind_address: &str) -> Result<(), String> {
     let mut join_lock = JOIN_HANDLE.lock().unwrap();
     assert!(join_lock.is_none());
-    *join_lock = Some(thread::spawn(|| worker()));
+    let listener = TcpListener::bind(bind_address).map_err(|e| format!("Cannot listen on {}:{}", bind_address, e))?;
+    log_info!("Listening on {}", bind_address);
+    *join_lock = Some(thread::spawn(|| worker(listener)));
+    Ok(())
 }
 
 pub fn stop() {
@@ -64,17 +126,6 @@ pub fn stop() {
 
 // Private functions -------------------------------------------------------------------------------
 
-// Decodes the lines representing the headers into a map
-fn decode_headers(text_lines: &Vec<String>) -> HashMap<String, String> {
-    let mut headers = HashMap::new();
-    for x in 1..text_lines.len() {
-        let text = &text_lines[x];
-        let parts = text.split(":").collect::<Vec<&str>>();
-        headers.insert(parts[0].to_string().trim().to_ascii_lowercase(), parts[1].to_string().trim().to_string());
-    }
-    headers
-}
-
 // Should be spun off as a separate thread.
 // Handles a new connection represented by the given stream value.
 // We do authentication here...
@@ -88,36 +139,32 @@ fn decode_headers(text_lines: &Vec<String>) -> HashMap<String, String> {
 //      The session-id is checked and, if it exists, it counts as validation.
 //      The request will be handled, and the session-id will be returned in the response header.
 fn handle_connection(stream: &TcpStream) -> HttpResponse {
-    let buf_reader = BufReader::new(stream);
-    let http_request: Vec<String> = buf_reader
-        .lines()
-        .map(|result| result.unwrap())
-        .take_while(|line| !line.is_empty())
-        .collect();
-
-    println!("From {}:{}", stream.peer_addr().unwrap(), http_request.get(0).unwrap());
-
-    // Grab the method and the url - we don't care about the http version.
-    let leading_parts = http_request.get(0).unwrap().split(" ").collect::<Vec<&str>>();
-    if leading_parts.len() < 2 {
-        return HttpResponse::new(HTTP_BAD_REQUEST, "Badly-formatted method/URL");
-    }
+    let request = match HttpRequest::read_from(stream) {
+        Ok(request) => request,
+        Err(response) => { return response; },
+    };
 
-    let method = leading_parts.get(0).unwrap().to_string().to_uppercase();
-    let mut url = leading_parts.get(1).unwrap().to_string().to_lowercase();
-    if url.is_empty() {
-        url = "/".to_string();
+    log_debug!("From {}:{}", stream.peer_addr().unwrap(), request.describe());
+    let response = route_request(&request);
+    if request.wants_json() {
+        response.into_json()
+    } else {
+        response
     }
+}
 
-    let headers = decode_headers(&http_request);
+// Authenticates the request (or handles the login), then finds and invokes the appropriate handler.
+fn route_request(request: &HttpRequest) -> HttpResponse {
+    let method = &request.method;
+    let url = &request.path;
 
     // Special handling for login
-    if url == "/session/login" {
+    if url.as_str() == "/session/login" {
         if method != "POST" {
             return HttpResponse::new(HTTP_METHOD_NOT_ALLOWED, format!("{method} not allowed on path {url}").as_str());
         }
 
-        let auth_value = headers.get("authorization");
+        let auth_value = request.get_header("authorization");
         if auth_value.is_none() {
             return HttpResponse::new(HTTP_UNAUTHORIZED, "Unauthorized");
         }
@@ -136,7 +183,11 @@ fn handle_connection(stream: &TcpStream) -> HttpResponse {
                     data.push_str(format!("\r\nRequests remaining: {}", user.requests_remaining.unwrap()).as_str());
                 }
 
-                let mut response = HttpResponse::new(HTTP_OK, data.as_str());
+                let mut response = if request.wants_json() {
+                    HttpResponse::new_json(HTTP_OK, &json!({"message": "Login Successful", "user": user.to_json()}))
+                } else {
+                    HttpResponse::new(HTTP_OK, data.as_str())
+                };
                 response.append_header("x-session-id", session_id.as_str());
                 response
             },
@@ -146,28 +197,28 @@ fn handle_connection(stream: &TcpStream) -> HttpResponse {
 
     // Not a login, so we require a session id in the request header
     let session_id: SessionId = {
-        let sid = headers.get("x-session-id");
+        let sid = request.get_header("x-session-id");
         if sid.is_none() {
             return HttpResponse::new(HTTP_UNAUTHORIZED, "Unauthorized");
         }
         sid.unwrap().clone()
     };
 
-    let session = session::get_session(&session_id);
-    if session.is_none() {
-        return HttpResponse::new(HTTP_INTERNAL_SERVER_ERROR, "Session disappeared");
-    }
-    let session = session.unwrap();
+    let session = match session::check_session(&session_id) {
+        SessionStatus::Active(session) => session,
+        SessionStatus::Expired => { return HttpResponse::new(HTTP_UNAUTHORIZED, "Session expired"); },
+        SessionStatus::NotFound => { return HttpResponse::new(HTTP_UNAUTHORIZED, "Invalid session"); },
+    };
     session::touch_session(&session_id);
 
     let mut found_path = false;
     for entry in HANDLER_LOOKUP_TABLE.iter() {
-        if url == entry.path {
+        if url.as_str() == entry.path {
             found_path = true;
-            if method == entry.method {
+            if method.as_str() == entry.method {
                 return if entry.is_restricted && !session.is_admin() {
                     HttpResponse::new(HTTP_FORBIDDEN, "You are neither cosmic, nor an overlord.")
-                } else { (entry.func)(&session) }
+                } else { (entry.func)(&session, request) }
             }
         }
     }
@@ -181,29 +232,857 @@ fn handle_connection(stream: &TcpStream) -> HttpResponse {
     }
 }
 
-fn handle_admin_quit(session: &Session) -> HttpResponse {
+// Sends the message given by the 'text' parameter to every user other than the admin
+fn handle_admin_broadcast(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let text = request.get_parameter("text").cloned().unwrap_or_default();
+    let to_user_ids: Vec<user::UserId> = user::get_user_ids().into_iter().filter(|user_id| *user_id != session.user_id).collect();
+    let result = database::with_database(|database| message::broadcast_message(database, session.user_id, &to_user_ids, &text));
+
+    match result {
+        Ok(count) => HttpResponse::new(HTTP_OK, format!("Message sent to {} users", count).as_str()),
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Validates and reports statistics for the galaxy given by the 'galaxy' parameter (an id or a name),
+// or for every galaxy if the parameter is absent.
+fn handle_admin_galaxy_report(_session: &Session, request: &HttpRequest) -> HttpResponse {
+    let galaxies: Vec<Galaxy> = match request.get_parameter("galaxy") {
+        Some(value) => match find_galaxy(value) {
+            Some(galaxy) => vec![galaxy],
+            None => { return HttpResponse::new(HTTP_NOT_FOUND, format!("No such galaxy {}", value).as_str()); },
+        },
+        None => galaxy::get_galaxy_ids().into_iter().filter_map(galaxy::get_galaxy).collect(),
+    };
+
+    let reports: Vec<GalaxyReport> = galaxies.iter().map(|galaxy| galaxy.get_report()).collect();
+    let lines: Vec<String> = reports.iter().flat_map(|report| report.get_description()).collect();
+    let values: Vec<Value> = reports.iter().map(|report| report.to_json()).collect();
+    respond(request, HTTP_OK, &lines, &json!(values))
+}
+
+// Renames the galaxy given by the 'galaxy' parameter (an id or a name) to the value of the 'name' parameter
+fn handle_admin_galaxy_rename(_session: &Session, request: &HttpRequest) -> HttpResponse {
+    let value = request.get_parameter("galaxy").cloned().unwrap_or_default();
+    let galaxy = match find_galaxy(&value) {
+        Some(galaxy) => galaxy,
+        None => { return HttpResponse::new(HTTP_NOT_FOUND, format!("No such galaxy {}", value).as_str()); },
+    };
+    let galaxy_name = request.get_parameter("name").cloned().unwrap_or_default();
+
+    match database::with_database(|database| galaxy::rename_galaxy(database, galaxy.get_galaxy_id(), &galaxy_name)) {
+        Ok(()) => HttpResponse::new(HTTP_OK, format!("Galaxy {} is now named {}", galaxy.get_galaxy_id(), galaxy_name.trim()).as_str()),
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Places a planet in the sector given by the 'sector' parameter. The planet is either an existing planet
+// which is not yet in any sector (given by the 'planet' parameter), or a new planet (named by the 'name' parameter).
+fn handle_admin_planet_attach(_session: &Session, request: &HttpRequest) -> HttpResponse {
+    let sector_id: SectorId = match get_id_parameter(request, "sector") {
+        Ok(sector_id) => sector_id,
+        Err(response) => { return response; },
+    };
+
+    let result = match (request.get_parameter("planet"), request.get_parameter("name")) {
+        (Some(_), None) => {
+            let planet_id: PlanetId = match get_id_parameter(request, "planet") {
+                Ok(planet_id) => planet_id,
+                Err(response) => { return response; },
+            };
+            if planet::get_planet(planet_id).is_none() {
+                return HttpResponse::new(HTTP_NOT_FOUND, format!("Planet {} does not exist", planet_id).as_str());
+            }
+            if let Some(other_sector_id) = sector::find_sector_with_planet(planet_id) {
+                return HttpResponse::new(HTTP_BAD_REQUEST,
+                                         format!("Planet {} is already in sector {}", planet_id, other_sector_id).as_str());
+            }
+            database::with_database(|database| sector::assign_planet(database, sector_id, planet_id).map(|_| planet_id))
+        },
+        (None, Some(name)) if !name.trim().is_empty() => {
+            if sector::get_sector(sector_id).is_some_and(|sector| sector.has_planet()) {
+                return HttpResponse::new(HTTP_BAD_REQUEST, format!("Sector {} already has a planet", sector_id).as_str());
+            }
+            database::with_database(|database| {
+                let result = || -> Result<PlanetId, String> {
+                    let transaction = database.transaction().map_err(|e| e.to_string())?;
+                    let planet_id = planet::create_planet(&transaction, Some(name.trim().to_string()), &mut rand::rng())?;
+                    sector::assign_planet(&transaction, sector_id, planet_id)?;
+                    transaction.commit().map_err(|e| e.to_string())?;
+                    Ok(planet_id)
+                }();
+                if result.is_err() {
+                    // the new planet was added to our in-memory copies before the transaction was rolled back
+                    galaxy::reload_galaxies(database)?;
+                }
+                result
+            })
+        },
+        _ => { return HttpResponse::new(HTTP_BAD_REQUEST, "Either a planet id or a new planet name is required"); },
+    };
+
+    match result {
+        Ok(planet_id) => HttpResponse::new(HTTP_OK, format!("Planet {} is now in sector {}", planet_id, sector_id).as_str()),
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Removes the port given by the 'port' parameter from its sector, and deletes it
+fn handle_admin_port_delete(_session: &Session, request: &HttpRequest) -> HttpResponse {
+    let port_id: PortId = match get_id_parameter(request, "port") {
+        Ok(port_id) => port_id,
+        Err(response) => { return response; },
+    };
+    let port = match port::get_port(port_id) {
+        Some(port) => port,
+        None => { return HttpResponse::new(HTTP_NOT_FOUND, format!("Port {} does not exist", port_id).as_str()); },
+    };
+
+    let result = database::with_database(|database| {
+        let result = || -> Result<(), String> {
+            let transaction = database.transaction().map_err(|e| e.to_string())?;
+            if let Some(sector_id) = sector::find_sector_with_port(port_id) {
+                sector::remove_port(&transaction, sector_id)?;
+            }
+            port::delete_port(&transaction, port_id)?;
+            transaction.commit().map_err(|e| e.to_string())
+        }();
+        if result.is_err() {
+            // the port may have been removed from our in-memory copies before the transaction was rolled back
+            galaxy::reload_galaxies(database)?;
+        }
+        result
+    });
+
+    match result {
+        Ok(()) => HttpResponse::new(HTTP_OK, format!("Port {} ({}) has been deleted", port_id, port.port_name).as_str()),
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Moves the port given by the 'port' parameter to the sector given by the 'sector' parameter
+fn handle_admin_port_move(_session: &Session, request: &HttpRequest) -> HttpResponse {
+    let port_id: PortId = match get_id_parameter(request, "port") {
+        Ok(port_id) => port_id,
+        Err(response) => { return response; },
+    };
+    let sector_id: SectorId = match get_id_parameter(request, "sector") {
+        Ok(sector_id) => sector_id,
+        Err(response) => { return response; },
+    };
+
+    match database::with_database(|database| galaxy::move_port(database, port_id, sector_id)) {
+        Ok(()) => HttpResponse::new(HTTP_OK, format!("Port {} is now in sector {}", port_id, sector_id).as_str()),
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+fn handle_admin_quit(session: &Session, _request: &HttpRequest) -> HttpResponse {
     // TODO send messages to all and sundry... maybe?
     TERMINATE_FLAG.store(true, std::sync::atomic::Ordering::SeqCst);
     HttpResponse::new(HTTP_OK, "Sent termination request to server")
 }
 
-fn handle_message_poll(session: &Session) -> HttpResponse {
-    // TODO go grab pending messages for this user
-    thread::sleep(Duration::from_secs(5));
-    let mut data: String = "".to_string();
-    data.push_str("Place-holder message\r\n");
-    HttpResponse::new(HTTP_OK, data.as_str())
+// Links the sector given by the 'from' parameter to the sector given by the 'to' parameter,
+// and back again unless the 'oneway' parameter is true
+fn handle_admin_sector_link(_session: &Session, request: &HttpRequest) -> HttpResponse {
+    let (from_sector_id, to_sector_id, is_one_way) = match get_link_parameters(request) {
+        Ok(result) => result,
+        Err(response) => { return response; },
+    };
+
+    match database::with_database(|database| galaxy::add_link(database, from_sector_id, to_sector_id, is_one_way)) {
+        Ok(()) => {
+            let arrow = if is_one_way { "->" } else { "<->" };
+            HttpResponse::new(HTTP_OK, format!("Linked {} {} {}", from_sector_id, arrow, to_sector_id).as_str())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Removes the link from the sector given by the 'from' parameter to the sector given by the 'to' parameter,
+// and the link back again unless the 'oneway' parameter is true
+fn handle_admin_sector_unlink(_session: &Session, request: &HttpRequest) -> HttpResponse {
+    let (from_sector_id, to_sector_id, is_one_way) = match get_link_parameters(request) {
+        Ok(result) => result,
+        Err(response) => { return response; },
+    };
+
+    match database::with_database(|database| galaxy::remove_link(database, from_sector_id, to_sector_id, is_one_way)) {
+        Ok(()) => {
+            let arrow = if is_one_way { "->" } else { "<->" };
+            HttpResponse::new(HTTP_OK, format!("Unlinked {} {} {}", from_sector_id, arrow, to_sector_id).as_str())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Lists one page of the messages sent to the user, most recent first
+fn handle_message_inbox(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let (page, page_size) = get_paging_parameters(request);
+    let (messages, total) = message::get_inbox(session.user_id, page, page_size);
+    let lines: Vec<String> = messages.iter().map(|msg| msg.get_description()).collect();
+    respond_with_page(request, page, page_size, total, lines, &messages)
+}
+
+// Lists one page of the messages sent by the user, most recent first
+fn handle_message_outbox(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let (page, page_size) = get_paging_parameters(request);
+    let (messages, total) = message::get_outbox(session.user_id, page, page_size);
+    let lines: Vec<String> = messages.iter().map(|msg| msg.get_sent_description()).collect();
+    respond_with_page(request, page, page_size, total, lines, &messages)
+}
+
+// Long-poll for messages. We wait until at least one message is pending for the user, or until
+// the poll times out, and then return all pending messages in order. We wait in short slices so that
+// we notice server termination, or the session being closed, in a timely fashion.
+fn handle_message_poll(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let deadline = Instant::now() + Duration::from_secs(POLL_TIMEOUT_SECONDS);
+    let mut messages = Vec::new();
+    while messages.is_empty()
+        && Instant::now() < deadline
+        && !TERMINATE_FLAG.load(std::sync::atomic::Ordering::SeqCst)
+        && session::get_session(&session.session_id).is_some() {
+        messages = message::wait_for_messages(session.user_id, Duration::from_millis(POLL_SLICE_MILLISECONDS));
+    }
+
+    let message_ids: Vec<MessageId> = messages.iter().map(|msg| msg.message_id).collect();
+    if let Err(e) = database::with_database(|database| message::mark_delivered(database, &message_ids)) {
+        log_error!("{}", e);
+    }
+
+    let lines: Vec<String> = messages.iter().map(|msg| msg.get_description()).collect();
+    let values: Vec<Value> = messages.iter().map(|msg| msg.to_json()).collect();
+    respond(request, HTTP_OK, &lines, &json!(values))
+}
+
+// Sends the message given by the 'text' parameter to the user named by the 'to' parameter,
+// which may be either a user name or a game name.
+fn handle_message_send(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let recipient = match request.get_parameter("to").map(|name| user::find_user_by_name(name)) {
+        Some(Ok(Some(user))) => user,
+        Some(Err(e)) => { return HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()); },
+        _ => { return HttpResponse::new(HTTP_NOT_FOUND, "No such user"); },
+    };
+
+    let text = request.get_parameter("text").cloned().unwrap_or_default();
+    match database::with_database(|database| message::send_message(database, session.user_id, recipient.user_id, &text)) {
+        Ok(_) => HttpResponse::new(HTTP_OK, format!("Message sent to {}", recipient.game_name).as_str()),
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
 }
 
-fn handle_no_operation(session: &Session) -> HttpResponse {
+fn handle_no_operation(session: &Session, _request: &HttpRequest) -> HttpResponse {
     HttpResponse::new(HTTP_OK, "")
 }
 
-fn handle_session_logout(session: &Session) -> HttpResponse {
+// Buys a quantity of a commodity from the port in the sector containing the user's ship
+fn handle_port_buy(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let (commodity, quantity) = match get_trade_parameters(request) {
+        Ok(result) => result,
+        Err(response) => { return response; },
+    };
+
+    match database::with_database(|database| trade::buy_from_port(database, session.user_id, commodity, quantity)) {
+        Ok(receipt) => respond(request, HTTP_OK, &receipt.get_description(), &receipt.to_json()),
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Describes the port in the sector containing the user's ship
+fn handle_port_report(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let sector_id = match ship::get_ship_for_user(session.user_id) {
+        Some(ship) => ship.sector_id,
+        None => { return HttpResponse::new(HTTP_NOT_FOUND, "You do not have a ship"); },
+    };
+
+    match sector::get_sector(sector_id).unwrap().port_id.and_then(port::get_port) {
+        Some(port) => respond(request, HTTP_OK, &port.get_description(), &port.to_json()),
+        None => HttpResponse::new(HTTP_NOT_FOUND, format!("There is no port in sector {}", sector_id).as_str()),
+    }
+}
+
+// Attacks the planet in the sector containing the user's ship, which must belong to another player
+fn handle_planet_attack(session: &Session, request: &HttpRequest) -> HttpResponse {
+    match database::with_database(|database| colony::attack_planet(database, session.user_id, &mut rand::rng())) {
+        Ok(report) => respond(request, HTTP_OK, &report.get_description(), &report.to_json()),
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Builds the next citadel level on the user's planet
+fn handle_planet_citadel(session: &Session, request: &HttpRequest) -> HttpResponse {
+    match database::with_database(|database| colony::upgrade_citadel(database, session.user_id)) {
+        Ok(planet) => {
+            let mut lines = vec![format!("The citadel on {} is now level {}", planet.planet_name, planet.citadel_level)];
+            lines.extend(planet.get_description());
+            respond(request, HTTP_OK, &lines, &planet.to_json())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Claims the unowned planet in the sector containing the user's ship
+fn handle_planet_claim(session: &Session, request: &HttpRequest) -> HttpResponse {
+    match database::with_database(|database| colony::claim_planet(database, session.user_id)) {
+        Ok(planet) => {
+            let mut lines = vec![format!("You have claimed {}", planet.planet_name)];
+            lines.extend(planet.get_description());
+            respond(request, HTTP_OK, &lines, &planet.to_json())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Lands colonists (the number given by the 'quantity' parameter) from the user's ship on the planet in the same sector
+fn handle_planet_land(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let quantity = match get_quantity_parameter(request) {
+        Ok(quantity) => quantity,
+        Err(response) => { return response; },
+    };
+
+    match database::with_database(|database| colony::land_colonists(database, session.user_id, quantity)) {
+        Ok(planet) => {
+            let mut lines = vec![format!("Landed {} colonists on {}", quantity, planet.planet_name)];
+            lines.extend(planet.get_description());
+            respond(request, HTTP_OK, &lines, &planet.to_json())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Moves fighters and shields (given by the 'fighters' and 'shields' parameters) from the user's ship to the planet
+// in the same sector
+fn handle_planet_fortify(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let (fighters, shields) = match get_defense_parameters(request) {
+        Ok(result) => result,
+        Err(response) => { return response; },
+    };
+
+    match database::with_database(|database| colony::fortify_planet(database, session.user_id, fighters, shields)) {
+        Ok(planet) => {
+            let mut lines = vec![format!("Transferred {} fighters and {} shields to {}", fighters, shields, planet.planet_name)];
+            lines.extend(planet.get_description());
+            respond(request, HTTP_OK, &lines, &planet.to_json())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Describes the planet in the sector containing the user's ship
+fn handle_planet_scan(session: &Session, request: &HttpRequest) -> HttpResponse {
+    match colony::scan_planet(session.user_id) {
+        Ok(planet) => respond(request, HTTP_OK, &planet.get_description(), &planet.to_json()),
+        Err(e) => HttpResponse::new(HTTP_NOT_FOUND, e.as_str()),
+    }
+}
+
+// Moves goods from the stockpile of the user's planet into the user's ship
+fn handle_planet_withdraw(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let (commodity, quantity) = match get_trade_parameters(request) {
+        Ok(result) => result,
+        Err(response) => { return response; },
+    };
+
+    match database::with_database(|database| colony::withdraw_goods(database, session.user_id, commodity, quantity)) {
+        Ok(ship) => {
+            let mut lines = vec![format!("Withdrew {} units of {}", quantity, commodity.name())];
+            lines.extend(ship.get_description());
+            respond(request, HTTP_OK, &lines, &ship.to_json())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Finds the nearest port to the user's ship. If a commodity is given by the 'selling' or 'buying' parameter,
+// only ports which sell (or buy) that commodity are considered.
+fn handle_port_nearest(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let wanted = match (request.get_parameter("selling"), request.get_parameter("buying")) {
+        (None, None) => None,
+        (Some(name), None) => match Commodity::from_name(name) {
+            Some(commodity) => Some((commodity, TradeRole::Selling)),
+            None => { return HttpResponse::new(HTTP_BAD_REQUEST, "A valid commodity is required"); },
+        },
+        (None, Some(name)) => match Commodity::from_name(name) {
+            Some(commodity) => Some((commodity, TradeRole::Buying)),
+            None => { return HttpResponse::new(HTTP_BAD_REQUEST, "A valid commodity is required"); },
+        },
+        (Some(_), Some(_)) => { return HttpResponse::new(HTTP_BAD_REQUEST, "Specify either selling or buying, not both"); },
+    };
+
+    let (ship, galaxy) = match get_ship_and_galaxy(session) {
+        Ok(result) => result,
+        Err(response) => { return response; },
+    };
+
+    let is_wanted = |port: &Port| match wanted {
+        Some((commodity, role)) => port.get_commodity(commodity).is_some_and(|pc| pc.role == role),
+        None => true,
+    };
+    match galaxy.nearest_port_from(ship.sector_id, is_wanted) {
+        Some((sector_id, port, distance)) => {
+            let line = format!("Port {} is in sector {} ({} hops)", port.port_name, sector_id, distance);
+            let value = json!({"sector_id": sector_id, "distance": distance, "port": port.to_json()});
+            respond(request, HTTP_OK, &[line], &value)
+        },
+        None => HttpResponse::new(HTTP_NOT_FOUND, "There is no such port within reach"),
+    }
+}
+
+// Lists the ports within a number of hops (given by the 'hops' parameter) of the user's ship, nearest first
+fn handle_port_nearby(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let hops = match get_hops_parameter(request) {
+        Ok(hops) => hops,
+        Err(response) => { return response; },
+    };
+
+    let (ship, galaxy) = match get_ship_and_galaxy(session) {
+        Ok(result) => result,
+        Err(response) => { return response; },
+    };
+
+    let ports = galaxy.ports_within(ship.sector_id, hops);
+    let mut lines = vec![format!("{} ports within {} hops", ports.len(), hops)];
+    let mut values: Vec<Value> = Vec::new();
+    for (sector_id, port, distance) in &ports {
+        lines.push(format!("  Sector {:>5} {:>3} hops  {}", sector_id, distance, port.port_name));
+        values.push(json!({"sector_id": sector_id, "distance": distance, "port": port.to_json()}));
+    }
+    respond(request, HTTP_OK, &lines, &json!({"hops": hops, "ports": values}))
+}
+
+// Sells a quantity of a commodity to the port in the sector containing the user's ship
+fn handle_port_sell(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let (commodity, quantity) = match get_trade_parameters(request) {
+        Ok(result) => result,
+        Err(response) => { return response; },
+    };
+
+    match database::with_database(|database| trade::sell_to_port(database, session.user_id, commodity, quantity)) {
+        Ok(receipt) => respond(request, HTTP_OK, &receipt.get_description(), &receipt.to_json()),
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Describes the sector containing the user's ship
+fn handle_sector_look(session: &Session, request: &HttpRequest) -> HttpResponse {
+    match ship::get_ship_for_user(session.user_id) {
+        Some(ship) => {
+            let sector = sector::get_sector(ship.sector_id).unwrap();
+            let mut lines = sector.get_description();
+            let mut json = sector.to_json();
+
+            // Other ships, and anything deployed, are listed here rather than by the sector itself, as they come and go
+            let mut ship_values: Vec<Value> = Vec::new();
+            for other_ship in ship::get_ships_in_sector(ship.sector_id).iter().filter(|other| other.ship_id != ship.ship_id) {
+                let owner_name = user::get_user(other_ship.owner_user_id).map(|user| user.game_name).unwrap_or_default();
+                lines.push(format!("  Ship {}: {} class, owned by {}", other_ship.ship_id, other_ship.hull_class.name(), owner_name));
+                ship_values.push(json!({"ship_id": other_ship.ship_id, "hull_class": other_ship.hull_class.name(),
+                    "owner_user_id": other_ship.owner_user_id, "owner_name": owner_name}));
+            }
+            json["ships"] = json!(ship_values);
+
+            if let Some(deployment) = deployment::get_deployment(ship.sector_id) {
+                lines.push(format!("  Guarded by {}", deployment.get_description()));
+                json["deployment"] = deployment.to_json();
+            }
+            respond(request, HTTP_OK, &lines, &json)
+        },
+        None => HttpResponse::new(HTTP_NOT_FOUND, "You do not have a ship"),
+    }
+}
+
+// Lays fighters and mines (given by the 'fighters' and 'mines' parameters) to guard the sector containing the user's ship
+fn handle_sector_deploy(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let mut quantities = [0, 0];
+    for (index, name) in ["fighters", "mines"].iter().enumerate() {
+        if let Some(value) = request.get_parameter(name) {
+            match value.trim().parse::<i32>() {
+                Ok(quantity) if quantity >= 0 => quantities[index] = quantity,
+                _ => { return HttpResponse::new(HTTP_BAD_REQUEST, format!("Invalid number of {}", name).as_str()); },
+            }
+        }
+    }
+
+    match database::with_database(|database| deployment::deploy(database, session.user_id, quantities[0], quantities[1])) {
+        Ok(deployment) => {
+            let line = format!("Sector {} is guarded by {}", deployment.sector_id, deployment.get_description());
+            respond(request, HTTP_OK, &[line], &deployment.to_json())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Finds the number of hops from the sector containing the user's ship to the sector given by the 'sector' parameter
+fn handle_sector_distance(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let to_sector_id: SectorId = match request.get_parameter("sector").and_then(|value| value.trim().parse().ok()) {
+        Some(sector_id) => sector_id,
+        None => { return HttpResponse::new(HTTP_BAD_REQUEST, "A valid destination sector is required"); },
+    };
+
+    let (ship, galaxy) = match get_ship_and_galaxy(session) {
+        Ok(result) => result,
+        Err(response) => { return response; },
+    };
+    if !galaxy.contains_sector(to_sector_id) {
+        return HttpResponse::new(HTTP_NOT_FOUND, format!("Sector {} is not in this galaxy", to_sector_id).as_str());
+    }
+
+    let distance = galaxy.get_distance(ship.sector_id, to_sector_id);
+    let line = match distance {
+        Some(distance) => format!("Sector {} is {} hops from sector {}", to_sector_id, distance, ship.sector_id),
+        None => format!("There is no path to sector {}", to_sector_id),
+    };
+    let value = json!({"from_sector_id": ship.sector_id, "to_sector_id": to_sector_id, "distance": distance});
+    respond(request, HTTP_OK, &[line], &value)
+}
+
+// Lists the sectors within a number of hops (given by the 'hops' parameter) of the user's ship, nearest first
+fn handle_sector_nearby(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let hops = match get_hops_parameter(request) {
+        Ok(hops) => hops,
+        Err(response) => { return response; },
+    };
+
+    let (ship, galaxy) = match get_ship_and_galaxy(session) {
+        Ok(result) => result,
+        Err(response) => { return response; },
+    };
+
+    let sectors = galaxy.sectors_within(ship.sector_id, hops);
+    let mut lines = vec![format!("{} sectors within {} hops", sectors.len(), hops)];
+    for distance in 0..=hops {
+        let at_distance: Vec<String> = sectors.iter()
+            .filter(|(_, d)| *d == distance)
+            .map(|(sector_id, _)| sector_id.to_string())
+            .collect();
+        if !at_distance.is_empty() {
+            lines.push(format!("  {:>3} hops: {}", distance, at_distance.join(", ")));
+        }
+    }
+    let values: Vec<Value> = sectors.iter()
+        .map(|(sector_id, distance)| json!({"sector_id": sector_id, "distance": distance}))
+        .collect();
+    respond(request, HTTP_OK, &lines, &json!({"hops": hops, "sectors": values}))
+}
+
+// Takes the user's deployed fighters (the number given by the 'quantity' parameter) back aboard their ship
+fn handle_sector_recall(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let quantity = match get_quantity_parameter(request) {
+        Ok(quantity) => quantity,
+        Err(response) => { return response; },
+    };
+
+    match database::with_database(|database| deployment::recall_fighters(database, session.user_id, quantity)) {
+        Ok(ship) => {
+            let mut lines = vec![format!("Recalled {} fighters", quantity)];
+            lines.extend(ship.get_description());
+            respond(request, HTTP_OK, &lines, &ship.to_json())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Finds the shortest path from the sector containing the user's ship to the sector given by the 'sector' parameter.
+// If the 'safe' parameter is true, the path avoids sectors guarded by other players (other than the destination).
+fn handle_sector_path(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let to_sector_id: SectorId = match request.get_parameter("sector").and_then(|value| value.trim().parse().ok()) {
+        Some(sector_id) => sector_id,
+        None => { return HttpResponse::new(HTTP_BAD_REQUEST, "A valid destination sector is required"); },
+    };
+
+    let ship = match ship::get_ship_for_user(session.user_id) {
+        Some(ship) => ship,
+        None => { return HttpResponse::new(HTTP_NOT_FOUND, "You do not have a ship"); },
+    };
+
+    let galaxy = galaxy::get_galaxy(ship.galaxy_id).unwrap();
+    if !galaxy.contains_sector(to_sector_id) {
+        return HttpResponse::new(HTTP_NOT_FOUND, format!("Sector {} is not in this galaxy", to_sector_id).as_str());
+    }
+
+    let path = if get_flag_parameter(request, "safe") {
+        galaxy.find_shortest_path_avoiding(ship.sector_id, to_sector_id, &deployment::get_hostile_sector_ids(session.user_id))
+    } else {
+        galaxy.find_shortest_path(ship.sector_id, to_sector_id)
+    };
+    let line = if ship.sector_id == to_sector_id {
+        format!("You are already in sector {}", to_sector_id)
+    } else if path.is_empty() {
+        format!("There is no path to sector {}", to_sector_id)
+    } else {
+        let hops: Vec<String> = path.iter().map(|sector_id| sector_id.to_string()).collect();
+        format!("Path to sector {} ({} hops): {} > {}", to_sector_id, path.len(), ship.sector_id, hops.join(" > "))
+    };
+    let value = json!({"from_sector_id": ship.sector_id, "to_sector_id": to_sector_id, "path": path});
+    respond(request, HTTP_OK, &[line], &value)
+}
+
+fn handle_session_logout(session: &Session, _request: &HttpRequest) -> HttpResponse {
     session::close_session(&session.session_id);
     HttpResponse::new(HTTP_OK, "")
 }
 
+// Attacks the ship given by the 'ship' parameter, which must be in the same sector as the user's ship.
+// The optional 'order' parameter may be 'attack' (the default) or 'retreat'.
+fn handle_ship_attack(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let target_ship_id: ShipId = match get_id_parameter(request, "ship") {
+        Ok(ship_id) => ship_id,
+        Err(response) => { return response; },
+    };
+    let order = match request.get_parameter("order") {
+        Some(name) => match CombatOrder::from_name(name) {
+            Some(order) => order,
+            None => { return HttpResponse::new(HTTP_BAD_REQUEST, "The order must be attack or retreat"); },
+        },
+        None => CombatOrder::Attack,
+    };
+
+    match database::with_database(|database| combat::attack_ship(database, session.user_id, target_ship_id, order, &mut rand::rng())) {
+        Ok(report) => respond(request, HTTP_OK, &report.get_description(), &report.to_json()),
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Sets the user's standing order (the 'order' parameter - attack, retreat, or flee) for when their ship is attacked
+fn handle_ship_orders(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let order = match request.get_parameter("order").and_then(|name| CombatOrder::from_name(name)) {
+        Some(order) => order,
+        None => { return HttpResponse::new(HTTP_BAD_REQUEST, "The order must be attack, retreat, or flee"); },
+    };
+
+    match database::with_database(|database| ship::set_combat_order(database, session.user_id, order)) {
+        Ok(ship) => {
+            let mut lines = vec![format!("When attacked, your ship will now {}", order.name().to_lowercase())];
+            lines.extend(ship.get_description());
+            respond(request, HTTP_OK, &lines, &ship.to_json())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Buys fighters and shields (given by the 'fighters' and 'shields' parameters) for the user's ship
+fn handle_ship_outfit(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let (fighters, shields) = match get_defense_parameters(request) {
+        Ok(result) => result,
+        Err(response) => { return response; },
+    };
+
+    match database::with_database(|database| ship::outfit_ship(database, session.user_id, fighters, shields)) {
+        Ok(ship) => {
+            let mut lines = vec![format!("Bought {} fighters and {} shields", fighters, shields)];
+            lines.extend(ship.get_description());
+            respond(request, HTTP_OK, &lines, &ship.to_json())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Takes colonists (the number given by the 'quantity' parameter) aboard the user's ship
+fn handle_ship_recruit(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let quantity = match get_quantity_parameter(request) {
+        Ok(quantity) => quantity,
+        Err(response) => { return response; },
+    };
+
+    match database::with_database(|database| colony::recruit_colonists(database, session.user_id, quantity)) {
+        Ok(ship) => {
+            let mut lines = vec![format!("Recruited {} colonists", quantity)];
+            lines.extend(ship.get_description());
+            respond(request, HTTP_OK, &lines, &ship.to_json())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Fills the fuel tank of the user's ship - see ship::refuel_ship
+fn handle_ship_refuel(session: &Session, request: &HttpRequest) -> HttpResponse {
+    match database::with_database(|database| ship::refuel_ship(database, session.user_id)) {
+        Ok(ship) => {
+            let mut lines = vec![format!("Your ship now has {} units of fuel", ship.fuel)];
+            lines.extend(ship.get_description());
+            respond(request, HTTP_OK, &lines, &ship.to_json())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Repairs the hull of the user's ship
+fn handle_ship_repair(session: &Session, request: &HttpRequest) -> HttpResponse {
+    match database::with_database(|database| ship::repair_ship(database, session.user_id)) {
+        Ok(ship) => {
+            let mut lines = vec!["Your hull has been repaired".to_string()];
+            lines.extend(ship.get_description());
+            respond(request, HTTP_OK, &lines, &ship.to_json())
+        },
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Moves the user's ship to an adjacent sector, given by the 'sector' parameter.
+// If that sector is guarded by another player, the 'encounter' parameter says what to do - toll, fight, or retreat.
+fn handle_ship_move(session: &Session, request: &HttpRequest) -> HttpResponse {
+    let sector_id: SectorId = match request.get_parameter("sector").and_then(|value| value.trim().parse().ok()) {
+        Some(sector_id) => sector_id,
+        None => { return HttpResponse::new(HTTP_BAD_REQUEST, "A valid destination sector is required"); },
+    };
+
+    if let Some(name) = request.get_parameter("encounter") {
+        let response = match EncounterResponse::from_name(name) {
+            Some(response) => response,
+            None => { return HttpResponse::new(HTTP_BAD_REQUEST, "The encounter response must be toll, fight, or retreat"); },
+        };
+        if deployment::get_hostile_deployment(sector_id, session.user_id).is_some() {
+            return match database::with_database(|database| deployment::enter_sector(database, session.user_id, sector_id,
+                                                                                      response, &mut rand::rng())) {
+                Ok(report) => respond(request, HTTP_OK, &report.get_description(), &report.to_json()),
+                Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+            };
+        }
+    }
+
+    match database::with_database(|database| ship::move_ship(database, session.user_id, sector_id)) {
+        Ok(sector) => respond(request, HTTP_OK, &sector.get_description(), &sector.to_json()),
+        Err(e) => HttpResponse::new(HTTP_BAD_REQUEST, e.as_str()),
+    }
+}
+
+// Describes the user's ship
+fn handle_ship_status(session: &Session, request: &HttpRequest) -> HttpResponse {
+    match ship::get_ship_for_user(session.user_id) {
+        Some(ship) => respond(request, HTTP_OK, &ship.get_description(), &ship.to_json()),
+        None => HttpResponse::new(HTTP_NOT_FOUND, "You do not have a ship"),
+    }
+}
+
+// Lists the users who are currently logged in
+fn handle_user_who(_session: &Session, request: &HttpRequest) -> HttpResponse {
+    let users: Vec<User> = session::get_active_user_ids().into_iter().filter_map(user::get_user).collect();
+    let mut lines = vec![format!("{} users online", users.len())];
+    lines.extend(users.iter().map(|user| format!("  {}", user.game_name)));
+    let values: Vec<Value> = users.iter().map(|user| json!({"user_id": user.user_id, "game_name": user.game_name})).collect();
+    respond(request, HTTP_OK, &lines, &json!(values))
+}
+
+// Extracts the 'page' and 'page_size' parameters for a paged listing, applying defaults and limits
+fn get_paging_parameters(request: &HttpRequest) -> (usize, usize) {
+    let page = request.get_parameter("page")
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .unwrap_or(1)
+        .max(1);
+    let page_size = request.get_parameter("page_size")
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    (page, page_size)
+}
+
+// Extracts the 'commodity' and 'quantity' parameters for a buy or sell request
+fn get_trade_parameters(request: &HttpRequest) -> Result<(Commodity, i32), HttpResponse> {
+    let commodity = match request.get_parameter("commodity").and_then(|value| Commodity::from_name(value)) {
+        Some(commodity) => commodity,
+        None => { return Err(HttpResponse::new(HTTP_BAD_REQUEST, "A valid commodity is required")); },
+    };
+
+    Ok((commodity, get_quantity_parameter(request)?))
+}
+
+fn get_quantity_parameter(request: &HttpRequest) -> Result<i32, HttpResponse> {
+    match request.get_parameter("quantity").and_then(|value| value.trim().parse::<i32>().ok()) {
+        Some(quantity) if quantity > 0 => Ok(quantity),
+        _ => Err(HttpResponse::new(HTTP_BAD_REQUEST, "A positive quantity is required")),
+    }
+}
+
+// Gets the optional 'fighters' and 'shields' parameters, which default to zero - but at least one must be given
+fn get_defense_parameters(request: &HttpRequest) -> Result<(i32, i32), HttpResponse> {
+    let mut result = [0, 0];
+    for (index, name) in ["fighters", "shields"].iter().enumerate() {
+        if let Some(value) = request.get_parameter(name) {
+            match value.trim().parse::<i32>() {
+                Ok(quantity) if quantity >= 0 => result[index] = quantity,
+                _ => { return Err(HttpResponse::new(HTTP_BAD_REQUEST, format!("Invalid number of {}", name).as_str())); },
+            }
+        }
+    }
+    if result[0] + result[1] == 0 {
+        return Err(HttpResponse::new(HTTP_BAD_REQUEST, "A number of fighters or shields is required"));
+    }
+    Ok((result[0], result[1]))
+}
+
+// Gets a required id parameter (of a sector, port, planet, etc.)
+fn get_id_parameter<T: FromStr>(request: &HttpRequest, name: &str) -> Result<T, HttpResponse> {
+    match request.get_parameter(name).and_then(|value| value.trim().parse().ok()) {
+        Some(id) => Ok(id),
+        None => Err(HttpResponse::new(HTTP_BAD_REQUEST, format!("A valid {} id is required", name).as_str())),
+    }
+}
+
+// Gets the 'from', 'to', and 'oneway' parameters for adding or removing a link
+fn get_link_parameters(request: &HttpRequest) -> Result<(SectorId, SectorId, bool), HttpResponse> {
+    let from_sector_id: SectorId = get_id_parameter(request, "from")?;
+    let to_sector_id: SectorId = get_id_parameter(request, "to")?;
+    let is_one_way = get_flag_parameter(request, "oneway");
+    Ok((from_sector_id, to_sector_id, is_one_way))
+}
+
+// Gets an optional true/false parameter, which is false if absent
+fn get_flag_parameter(request: &HttpRequest, name: &str) -> bool {
+    request.get_parameter(name).is_some_and(|value| matches!(value.trim().to_lowercase().as_str(), "true" | "yes" | "1"))
+}
+
+// Finds a galaxy given either its id or its name
+fn find_galaxy(value: &str) -> Option<Galaxy> {
+    value.trim().parse::<GalaxyId>().ok().and_then(galaxy::get_galaxy)
+        .or_else(|| galaxy::find_galaxy_by_name(value.trim()))
+}
+
+// Gets the optional 'hops' parameter for the nearby queries
+fn get_hops_parameter(request: &HttpRequest) -> Result<usize, HttpResponse> {
+    match request.get_parameter("hops") {
+        None => Ok(DEFAULT_NEARBY_HOPS),
+        Some(value) => match value.trim().parse::<usize>() {
+            Ok(hops) if hops <= MAX_NEARBY_HOPS => Ok(hops),
+            _ => Err(HttpResponse::new(HTTP_BAD_REQUEST,
+                                       format!("Hops must be a number from 0 to {}", MAX_NEARBY_HOPS).as_str())),
+        },
+    }
+}
+
+// Gets the user's ship, and the galaxy containing it
+fn get_ship_and_galaxy(session: &Session) -> Result<(Ship, Galaxy), HttpResponse> {
+    let ship = match ship::get_ship_for_user(session.user_id) {
+        Some(ship) => ship,
+        None => { return Err(HttpResponse::new(HTTP_NOT_FOUND, "You do not have a ship")); },
+    };
+    let galaxy = galaxy::get_galaxy(ship.galaxy_id).unwrap();
+    Ok((ship, galaxy))
+}
+
+// Creates a response in whichever form the client prefers - JSON, or lines of text
+fn respond(request: &HttpRequest, code: u16, lines: &[String], value: &Value) -> HttpResponse {
+    if request.wants_json() {
+        HttpResponse::new_json(code, value)
+    } else {
+        HttpResponse::new(code, lines.join("\r\n").as_str())
+    }
+}
+
+// Creates a response for one page of a message listing
+fn respond_with_page(request: &HttpRequest,
+                     page: usize,
+                     page_size: usize,
+                     total: usize,
+                     mut lines: Vec<String>,
+                     messages: &[Message]) -> HttpResponse {
+    let page_count = total.div_ceil(page_size).max(1);
+    lines.push(format!("Page {} of {} ({} messages)", page, page_count, total));
+    let values: Vec<Value> = messages.iter().map(|msg| msg.to_json()).collect();
+    let value = json!({"page": page, "page_size": page_size, "page_count": page_count, "total": total, "messages": values});
+    respond(request, HTTP_OK, &lines, &value)
+}
+
 // Checks the authorization value against our users.
 // If successful, we create a new session for this client and return the SessionId.
 fn validate_authorization(auth_value: &String) -> Result<SessionId, HttpResponse> {
@@ -233,15 +1112,14 @@ fn validate_authorization(auth_value: &String) -> Result<SessionId, HttpResponse
     };
 
     let session_id = session::create_session(user_id);
-    println!("Validated user {} session {}", &user_name, session_id);
+    log_info!("Validated user {} session {}", &user_name, session_id);
     Ok(session_id)
 }
 
 // thread which listens for connections
-fn worker() {
+fn worker(listener: TcpListener) {
     IS_ACTIVE.store(true, std::sync::atomic::Ordering::SeqCst);
 
-    let listener = TcpListener::bind("127.0.0.1:2000").unwrap();
     _ = listener.set_nonblocking(true);
     let mut prune_counter = HANDLER_PRUNE_RATIO;
     loop {
@@ -275,12 +1153,12 @@ fn worker() {
             }
             Some(Err(e)) => {
                 // Handle other errors
-                eprintln!("Error accepting connection: {}", e);
+                log_error!("Error accepting connection: {}", e);
                 break; // Or handle the error as appropriate
             }
             None => {
                 // The iterator is exhausted. Should not happen with a non-blocking listener.
-                eprintln!("Listener iterator exhausted. This should not happen with non-blocking I/O.");
+                log_error!("Listener iterator exhausted. This should not happen with non-blocking I/O.");
                 break;
             }
         }
@@ -295,6 +1173,6 @@ fn worker() {
         }
     }
 
-    println!("Server stopping...");
+    log_info!("Server stopping...");
     IS_ACTIVE.store(false, std::sync::atomic::Ordering::SeqCst);
 }
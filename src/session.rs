@@ -1,22 +1,44 @@
 use std::collections::HashMap;
 use std::sync::{LazyLock, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 use crate::user;
 use crate::user::UserId;
 
 pub type SessionId = String;
 
+pub const DEFAULT_SESSION_TIMEOUT_SECONDS: u64 = 300;
+
 static SESSIONS: LazyLock<Mutex<HashMap<SessionId, Session>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
-const SESSION_TIMEOUT_SECONDS: u64 = 60; // TODO should be 5 minutes, maybe?
+static SESSION_LIMITS: LazyLock<Mutex<SessionLimits>> = LazyLock::new(|| Mutex::new(SessionLimits {
+    idle_timeout: Duration::from_secs(DEFAULT_SESSION_TIMEOUT_SECONDS),
+    max_lifetime: None,
+}));
 
+#[derive(Clone)]
 pub struct Session {
     pub session_id: SessionId,
     pub user_id: UserId,
+    pub created_at: Instant,
     pub last_request_at: Instant,
     is_closed: bool,
 }
 
+/// The outcome of looking up a session for an incoming request
+pub enum SessionStatus {
+    Active(Session),
+    Expired,
+    NotFound,
+}
+
+// Limits on the life of a session. A session expires once it has been idle for idle_timeout,
+// or (if max_lifetime is set) once it is max_lifetime old, regardless of activity.
+#[derive(Clone, Copy)]
+struct SessionLimits {
+    idle_timeout: Duration,
+    max_lifetime: Option<Duration>,
+}
+
 pub fn close_session(session_id: &SessionId) {
     for session in SESSIONS.lock().unwrap().values_mut() {
         if session.session_id == *session_id {
@@ -36,29 +58,67 @@ pub fn create_session(user_id: UserId) -> SessionId {
 
     let session_id: SessionId = Uuid::new_v4().to_string();
     let result = session_id.clone();
-    let session = Session{session_id, user_id, last_request_at: Instant::now(), is_closed: false};
+    let now = Instant::now();
+    let session = Session{session_id, user_id, created_at: now, last_request_at: now, is_closed: false};
     SESSIONS.lock().unwrap().insert(session.session_id.clone(), session);
     result
 }
 
+/// Identifies the users who currently have open sessions
+pub fn get_active_user_ids() -> Vec<UserId> {
+    let limits = *SESSION_LIMITS.lock().unwrap();
+    let mut user_ids: Vec<UserId> = SESSIONS.lock().unwrap().values()
+        .filter(|session| !session.is_closed && !session.is_expired(&limits))
+        .map(|session| session.user_id)
+        .collect();
+    user_ids.sort();
+    user_ids.dedup();
+    user_ids
+}
+
+/// Gets a clone of the given session, provided it is neither closed nor expired
 pub fn get_session(session_id: &SessionId) -> Option<Session> {
-    for session in SESSIONS.lock().unwrap().values() {
-        if session.session_id == *session_id && !session.is_closed {
-            return Some(session.clone())
-        }
+    match check_session(session_id) {
+        SessionStatus::Active(session) => Some(session),
+        SessionStatus::Expired | SessionStatus::NotFound => None,
     }
-    None
 }
 
-// Iterates over sessions, removing those which have been closed
+/// Looks up the given session, distinguishing sessions which have expired from those which
+/// never existed (or have been closed or pruned).
+pub fn check_session(session_id: &SessionId) -> SessionStatus {
+    let limits = *SESSION_LIMITS.lock().unwrap();
+    match SESSIONS.lock().unwrap().get(session_id) {
+        Some(session) if session.is_closed => SessionStatus::NotFound,
+        Some(session) if session.is_expired(&limits) => SessionStatus::Expired,
+        Some(session) => SessionStatus::Active(session.clone()),
+        None => SessionStatus::NotFound,
+    }
+}
+
+// Iterates over sessions, removing those which have been closed.
+// Expired sessions are retained for a further idle timeout period, so that a returning client
+// can be told that its session has expired, rather than that it is not known.
 pub fn prune_sessions() {
-    SESSIONS.lock().unwrap().retain(|_, session| !session.is_closed);
+    let limits = *SESSION_LIMITS.lock().unwrap();
+    let retention = SessionLimits {
+        idle_timeout: limits.idle_timeout * 2,
+        max_lifetime: limits.max_lifetime.map(|lifetime| lifetime + limits.idle_timeout),
+    };
+    SESSIONS.lock().unwrap().retain(|_, session| !session.is_closed && !session.is_expired(&retention));
+}
+
+/// Sets the idle timeout, and optionally the maximum lifetime, for sessions.
+/// Applies to existing sessions as well as new ones.
+pub fn set_session_limits(idle_timeout: Duration, max_lifetime: Option<Duration>) {
+    *SESSION_LIMITS.lock().unwrap() = SessionLimits { idle_timeout, max_lifetime };
 }
 
-/// Updates the timestamp for a session provided it exists, and is not closed
+/// Updates the timestamp for a session provided it exists, and is neither closed nor expired
 pub fn touch_session(session_id: &SessionId) {
+    let limits = *SESSION_LIMITS.lock().unwrap();
     for session in SESSIONS.lock().unwrap().values_mut() {
-        if session.session_id == *session_id && !session.is_closed {
+        if session.session_id == *session_id && !session.is_closed && !session.is_expired(&limits) {
             session.last_request_at = Instant::now();
             break;
         }
@@ -66,11 +126,12 @@ pub fn touch_session(session_id: &SessionId) {
 }
 
 impl Session {
-    pub fn clone(&self) -> Session {
-        Session{session_id: self.session_id.clone(), user_id: self.user_id.clone(), last_request_at: Instant::now(), is_closed: false}
-    }
-
     pub fn is_admin(&self) -> bool {
         self.user_id == user::ADMIN_USER_ID
     }
+
+    fn is_expired(&self, limits: &SessionLimits) -> bool {
+        self.last_request_at.elapsed() >= limits.idle_timeout
+            || limits.max_lifetime.is_some_and(|lifetime| self.created_at.elapsed() >= lifetime)
+    }
 }
@@ -0,0 +1,659 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use rusqlite::{params, Connection};
+use serde_json::{json, Value};
+use crate::combat::CombatOrder;
+use crate::commodity::Commodity;
+use crate::galaxy::GalaxyId;
+use crate::sector::{Sector, SectorId};
+use crate::{deployment, galaxy, sector, user};
+use crate::user::UserId;
+use crate::log_info;
+
+pub type ShipId = usize;
+
+static NEXT_SHIP_ID: LazyLock<Mutex<ShipId>> = LazyLock::new(|| Mutex::new(1));
+static SHIPS: LazyLock<Mutex<HashMap<ShipId, Ship>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub const STARTING_CREDITS: i64 = 1000;
+pub const STARTING_HULL_CLASS: HullClass = HullClass::Merchant;
+pub const COLONISTS_PER_HOLD: i32 = 100;
+pub const MOVE_REQUEST_COST: i32 = 1;
+const OUTFIT_REQUEST_COST: i32 = 1;
+const FIGHTER_PRICE: i64 = 25;
+const SHIELD_PRICE: i64 = 10;
+const HULL_REPAIR_PRICE: i64 = 5; // credits per point of hull damage repaired
+const FUEL_PRICE: i64 = 5; // credits per unit of fuel bought in a root sector
+const FUEL_PER_ORE: i32 = 5; // units of fuel refined from each unit of Fuel Ore in the holds
+const WRECK_HULL_PERCENT: i32 = 10; // how much of its hull a wrecked ship has left when it is towed in
+
+/// The type of hull on which a ship is built - this determines the ship's capacities.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HullClass {
+    Scout,
+    Merchant,
+    Freighter,
+}
+
+/// A ship belonging to a player. Every ship is always located in exactly one sector.
+/// Cargo is tracked per commodity; commodities which are not carried have no entry in holds.
+/// Colonists are also carried in the holds, COLONISTS_PER_HOLD to a hold.
+/// Fighters and shields are carried separately, up to limits set by the hull class.
+/// The hull takes whatever damage the shields and fighters do not absorb in combat (see combat::Combatant).
+#[derive(Clone)]
+pub struct Ship {
+    pub ship_id: ShipId,
+    pub owner_user_id: UserId,
+    pub galaxy_id: GalaxyId,
+    pub sector_id: SectorId,
+    pub hull_class: HullClass,
+    pub fuel: i32,
+    pub credits: i64,
+    pub holds: HashMap<Commodity, i32>,
+    pub colonists: i32,
+    pub fighters: i32,
+    pub shields: i32,
+    pub hull: i32,
+    pub combat_order: CombatOrder, // how the ship responds when attacked
+}
+
+/// Creates a new ship with full fuel and empty holds, and persists it to the database.
+pub fn create_ship(database: &Connection,
+                   owner_user_id: UserId,
+                   galaxy_id: GalaxyId,
+                   sector_id: SectorId,
+                   hull_class: HullClass) -> Result<ShipId, String> {
+    let mut next_ship_id = NEXT_SHIP_ID.lock().unwrap();
+    let ship_id = *next_ship_id;
+    *next_ship_id += 1;
+
+    let ship = Ship {
+        ship_id,
+        owner_user_id,
+        galaxy_id,
+        sector_id,
+        hull_class,
+        fuel: hull_class.fuel_capacity(),
+        credits: STARTING_CREDITS,
+        holds: HashMap::new(),
+        colonists: 0,
+        fighters: 0,
+        shields: 0,
+        hull: hull_class.max_hull(),
+        combat_order: CombatOrder::Retreat };
+
+    match ship.persist(database) {
+        Ok(_) => (),
+        Err(e) => { return Err(e.to_string()); },
+    }
+
+    SHIPS.lock().unwrap().insert(ship_id, ship);
+    Ok(ship_id)
+}
+
+// Gets a clone of a ship - changes must be written back via update_ship(), then publish_ship()
+pub fn get_ship(ship_id: ShipId) -> Option<Ship> {
+    SHIPS.lock().unwrap().get(&ship_id).cloned()
+}
+
+// Gets clones of all the ships in the given sector, in ship id order
+pub fn get_ships_in_sector(sector_id: SectorId) -> Vec<Ship> {
+    let mut ships: Vec<Ship> = SHIPS.lock().unwrap().values().filter(|ship| ship.sector_id == sector_id).cloned().collect();
+    ships.sort_by_key(|ship| ship.ship_id);
+    ships
+}
+
+// Gets a clone of the ship owned by the given user, if there is one
+pub fn get_ship_for_user(user_id: UserId) -> Option<Ship> {
+    SHIPS.lock().unwrap().values().find(|ship| ship.owner_user_id == user_id).cloned()
+}
+
+/// Creates a ship map describing all the ships in the universe - Used when a game starts up.
+pub fn load_ships(database: &Connection) -> Result<(), String> {
+    SHIPS.lock().unwrap().clear();
+
+    match || -> rusqlite::Result<()> {
+        let mut stmt = database.prepare("SELECT shipId, ownerUserId, galaxyId, sectorId, hullClass, fuel, credits, colonists, \
+                                                    fighters, shields, hull, combatOrder FROM ships ORDER BY shipId")?;
+        let ship_iter = stmt.query_map([], |row| {
+            let hull_code: i32 = row.get(4)?;
+            let order_code: i32 = row.get(11)?;
+            Ok(Ship {
+                ship_id: row.get(0)?,
+                owner_user_id: row.get(1)?,
+                galaxy_id: row.get(2)?,
+                sector_id: row.get(3)?,
+                hull_class: HullClass::from_code(hull_code).unwrap_or(STARTING_HULL_CLASS),
+                fuel: row.get(5)?,
+                credits: row.get(6)?,
+                holds: HashMap::new(),
+                colonists: row.get(7)?,
+                fighters: row.get(8)?,
+                shields: row.get(9)?,
+                hull: row.get(10)?,
+                combat_order: CombatOrder::from_code(order_code).unwrap_or(CombatOrder::Retreat) })
+        })?;
+
+        let mut highest_ship_id: ShipId = 0;
+        for ship_result in ship_iter {
+            let mut ship = ship_result?;
+            highest_ship_id = ship.ship_id;
+
+            let mut stmt = database.prepare("SELECT commodity, quantity FROM ship_cargo WHERE shipId = :shipId")?;
+            let cargo_iter = stmt.query_map(&[(":shipId", &ship.ship_id)], |row| {
+                Ok((row.get::<_, i32>(0)?, row.get::<_, i32>(1)?))
+            })?;
+
+            for cargo_result in cargo_iter {
+                let (code, quantity) = cargo_result?;
+                if let Some(commodity) = Commodity::from_code(code) {
+                    ship.holds.insert(commodity, quantity);
+                }
+            }
+
+            SHIPS.lock().unwrap().insert(ship.ship_id, ship);
+        }
+
+        *NEXT_SHIP_ID.lock().unwrap() = highest_ship_id + 1;
+        log_info!("Loaded {} ships", SHIPS.lock().unwrap().len());
+        Ok(())
+    }() {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Cannot load ships:{}", e)),
+    }
+}
+
+/// Moves the given user's ship to an adjacent sector.
+/// The destination must be one of the sectors to which the current sector links.
+/// Moving costs fuel (depending on the hull class) and one of the user's daily requests.
+/// A sector guarded by another player's fighters or mines cannot be entered this way - see deployment::enter_sector.
+///
+/// # Returns
+/// A clone of the sector into which the ship has moved
+pub fn move_ship(database: &mut Connection, user_id: UserId, to_sector_id: SectorId) -> Result<Sector, String> {
+    let mut ship = match get_ship_for_user(user_id) {
+        Some(ship) => ship,
+        None => { return Err("You do not have a ship".to_string()); },
+    };
+
+    ship.check_move(to_sector_id)?;
+    if let Some(deployment) = deployment::get_hostile_deployment(to_sector_id, user_id) {
+        return Err(format!("Sector {} is guarded by {} - you must pay a toll of {} credits, fight, or retreat",
+                           to_sector_id, deployment.get_description(), deployment.get_toll()));
+    }
+
+    ship.fuel -= ship.hull_class.fuel_per_move();
+    ship.sector_id = to_sector_id;
+
+    let transaction = database.transaction().map_err(|e| format!("Cannot move ship:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, MOVE_REQUEST_COST)?;
+    update_ship(&transaction, &ship)?;
+    transaction.commit().map_err(|e| format!("Cannot move ship:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    publish_ship(ship);
+
+    Ok(sector::get_sector(to_sector_id).unwrap())
+}
+
+/// Buys fighters and shields for the given user's ship, at FIGHTER_PRICE and SHIELD_PRICE credits each.
+/// These are only sold in the root sector of each galaxy, and the ship cannot carry more than its hull class allows.
+///
+/// # Returns
+/// The ship, as outfitted
+pub fn outfit_ship(database: &mut Connection, user_id: UserId, fighters: i32, shields: i32) -> Result<Ship, String> {
+    if fighters < 0 || shields < 0 || fighters + shields == 0 {
+        return Err("Nothing to buy".to_string());
+    }
+
+    let mut ship = match get_ship_for_user(user_id) {
+        Some(ship) => ship,
+        None => { return Err("You do not have a ship".to_string()); },
+    };
+
+    let root_sector_id = ship.get_root_sector_id()?;
+    if ship.sector_id != root_sector_id {
+        return Err(format!("Fighters and shields are only sold in sector {}", root_sector_id));
+    }
+    if ship.fighters + fighters > ship.hull_class.max_fighters() {
+        return Err(format!("You only have room for {} more fighters", ship.hull_class.max_fighters() - ship.fighters));
+    }
+    if ship.shields + shields > ship.hull_class.max_shields() {
+        return Err(format!("You only have room for {} more shields", ship.hull_class.max_shields() - ship.shields));
+    }
+
+    let total_price = fighters as i64 * FIGHTER_PRICE + shields as i64 * SHIELD_PRICE;
+    if total_price > ship.credits {
+        return Err(format!("That costs {} credits, but you only have {}", total_price, ship.credits));
+    }
+
+    ship.credits -= total_price;
+    ship.fighters += fighters;
+    ship.shields += shields;
+    let transaction = database.transaction().map_err(|e| format!("Cannot outfit ship:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, OUTFIT_REQUEST_COST)?;
+    update_ship(&transaction, &ship)?;
+    transaction.commit().map_err(|e| format!("Cannot outfit ship:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    publish_ship(ship.clone());
+    Ok(ship)
+}
+
+/// Repairs all the damage to the hull of the given user's ship, at HULL_REPAIR_PRICE credits per point.
+/// Repairs can only be made in the root sector of each galaxy.
+///
+/// # Returns
+/// The ship, as repaired
+pub fn repair_ship(database: &mut Connection, user_id: UserId) -> Result<Ship, String> {
+    let mut ship = match get_ship_for_user(user_id) {
+        Some(ship) => ship,
+        None => { return Err("You do not have a ship".to_string()); },
+    };
+
+    let root_sector_id = ship.get_root_sector_id()?;
+    if ship.sector_id != root_sector_id {
+        return Err(format!("Repairs can only be made in sector {}", root_sector_id));
+    }
+    let damage = ship.hull_class.max_hull() - ship.hull;
+    if damage == 0 {
+        return Err("Your hull is undamaged".to_string());
+    }
+
+    let total_price = damage as i64 * HULL_REPAIR_PRICE;
+    if total_price > ship.credits {
+        return Err(format!("Repairs cost {} credits, but you only have {}", total_price, ship.credits));
+    }
+
+    ship.credits -= total_price;
+    ship.hull += damage;
+    let transaction = database.transaction().map_err(|e| format!("Cannot repair ship:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, OUTFIT_REQUEST_COST)?;
+    update_ship(&transaction, &ship)?;
+    transaction.commit().map_err(|e| format!("Cannot repair ship:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    publish_ship(ship.clone());
+    Ok(ship)
+}
+
+/// Fills the fuel tank of the given user's ship, as far as the ship can pay for it. In the root sector of each galaxy,
+/// fuel is bought at FUEL_PRICE credits per unit. Anywhere else, fuel is refined from the Fuel Ore in the ship's holds,
+/// FUEL_PER_ORE units from each unit of ore (which may be bought at ports).
+///
+/// # Returns
+/// The ship, as refuelled
+pub fn refuel_ship(database: &mut Connection, user_id: UserId) -> Result<Ship, String> {
+    let mut ship = match get_ship_for_user(user_id) {
+        Some(ship) => ship,
+        None => { return Err("You do not have a ship".to_string()); },
+    };
+
+    let shortfall = ship.hull_class.fuel_capacity() - ship.fuel;
+    if shortfall == 0 {
+        return Err("Your fuel tank is already full".to_string());
+    }
+
+    if ship.sector_id == ship.get_root_sector_id()? {
+        let fuel = shortfall.min((ship.credits / FUEL_PRICE).min(i32::MAX as i64) as i32);
+        if fuel == 0 {
+            return Err(format!("Fuel costs {} credits per unit, but you only have {}", FUEL_PRICE, ship.credits));
+        }
+        ship.credits -= fuel as i64 * FUEL_PRICE;
+        ship.fuel += fuel;
+    } else {
+        let ore = ship.get_cargo(Commodity::FuelOre);
+        if ore == 0 {
+            return Err(format!("You have no {} to refine - fuel can be bought in sector {}",
+                               Commodity::FuelOre.name(), ship.get_root_sector_id()?));
+        }
+        // refine just enough ore to fill the tank, even if that wastes part of the last unit
+        let ore_used = ore.min((shortfall + FUEL_PER_ORE - 1) / FUEL_PER_ORE);
+        ship.holds.insert(Commodity::FuelOre, ore - ore_used);
+        ship.fuel = (ship.fuel + ore_used * FUEL_PER_ORE).min(ship.hull_class.fuel_capacity());
+    }
+
+    let transaction = database.transaction().map_err(|e| format!("Cannot refuel ship:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, OUTFIT_REQUEST_COST)?;
+    update_ship(&transaction, &ship)?;
+    transaction.commit().map_err(|e| format!("Cannot refuel ship:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    publish_ship(ship.clone());
+    Ok(ship)
+}
+
+/// Sets the standing order which determines how the given user's ship responds when it is attacked.
+/// This costs none of the user's daily requests.
+pub fn set_combat_order(database: &Connection, user_id: UserId, combat_order: CombatOrder) -> Result<Ship, String> {
+    let mut ship = match get_ship_for_user(user_id) {
+        Some(ship) => ship,
+        None => { return Err("You do not have a ship".to_string()); },
+    };
+
+    ship.combat_order = combat_order;
+    update_ship(database, &ship)?;
+    publish_ship(ship.clone());
+    Ok(ship)
+}
+
+/// Writes the mutable state of the given ship to the database. Our in-memory copy is left alone -
+/// the caller passes the ship to publish_ship() once its transaction is committed, so that a rollback
+/// cannot leave memory and the database disagreeing.
+pub fn update_ship(database: &Connection, ship: &Ship) -> Result<(), String> {
+    ship.update(database)
+}
+
+/// Repairs the hulls of ships from a database built before ships could be damaged. See database::upgrade_schema.
+pub fn upgrade_hulls(database: &Connection) -> Result<(), String> {
+    for hull_class in [HullClass::Scout, HullClass::Merchant, HullClass::Freighter] {
+        let statement = "UPDATE ships SET hull = ?1 WHERE hullClass = ?2;";
+        database.execute(statement, params![hull_class.max_hull(), hull_class.code()])
+            .map_err(|e| format!("Cannot upgrade ship hulls:{}", e))?;
+    }
+    Ok(())
+}
+
+/// Replaces our in-memory copy of the ship - to be called only after the ship's changes are committed.
+pub fn publish_ship(ship: Ship) {
+    SHIPS.lock().unwrap().insert(ship.ship_id, ship);
+}
+
+impl HullClass {
+    /// Integer code used to represent the hull class in the database
+    pub fn code(&self) -> i32 {
+        match self {
+            HullClass::Scout => 1,
+            HullClass::Merchant => 2,
+            HullClass::Freighter => 3,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Option<HullClass> {
+        match code {
+            1 => Some(HullClass::Scout),
+            2 => Some(HullClass::Merchant),
+            3 => Some(HullClass::Freighter),
+            _ => None,
+        }
+    }
+
+    pub fn fuel_capacity(&self) -> i32 {
+        match self {
+            HullClass::Scout => 200,
+            HullClass::Merchant => 150,
+            HullClass::Freighter => 100,
+        }
+    }
+
+    pub fn fuel_per_move(&self) -> i32 {
+        match self {
+            HullClass::Scout => 1,
+            HullClass::Merchant => 2,
+            HullClass::Freighter => 3,
+        }
+    }
+
+    pub fn hold_capacity(&self) -> i32 {
+        match self {
+            HullClass::Scout => 20,
+            HullClass::Merchant => 50,
+            HullClass::Freighter => 120,
+        }
+    }
+
+    pub fn max_fighters(&self) -> i32 {
+        match self {
+            HullClass::Scout => 20,
+            HullClass::Merchant => 50,
+            HullClass::Freighter => 30,
+        }
+    }
+
+    pub fn max_shields(&self) -> i32 {
+        match self {
+            HullClass::Scout => 50,
+            HullClass::Merchant => 100,
+            HullClass::Freighter => 200,
+        }
+    }
+
+    pub fn max_hull(&self) -> i32 {
+        match self {
+            HullClass::Scout => 100,
+            HullClass::Merchant => 150,
+            HullClass::Freighter => 250,
+        }
+    }
+
+    /// The chance (as a percentage) in each round of combat that a ship under orders to flee gets away
+    pub fn escape_percent(&self) -> i32 {
+        match self {
+            HullClass::Scout => 60,
+            HullClass::Merchant => 40,
+            HullClass::Freighter => 20,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HullClass::Scout => "Scout",
+            HullClass::Merchant => "Merchant",
+            HullClass::Freighter => "Freighter",
+        }
+    }
+}
+
+impl Ship {
+    /// Number of units of the given commodity in our holds
+    pub fn get_cargo(&self, commodity: Commodity) -> i32 {
+        *self.holds.get(&commodity).unwrap_or(&0)
+    }
+
+    /// Number of holds which are not currently occupied by cargo or colonists
+    pub fn get_free_holds(&self) -> i32 {
+        self.hull_class.hold_capacity() - self.holds.values().sum::<i32>() - self.get_colonist_holds()
+    }
+
+    /// Number of additional colonists for which there is room, in free holds and the unused part of any colonist hold
+    pub fn get_colonist_room(&self) -> i32 {
+        (self.hull_class.hold_capacity() - self.holds.values().sum::<i32>()) * COLONISTS_PER_HOLD - self.colonists
+    }
+
+    /// Number of holds occupied by colonists - a partly-filled hold counts as occupied
+    pub fn get_colonist_holds(&self) -> i32 {
+        (self.colonists + COLONISTS_PER_HOLD - 1) / COLONISTS_PER_HOLD
+    }
+
+    /// Checks that the ship can move to the given sector - that it is adjacent, and that there is enough fuel
+    pub fn check_move(&self, to_sector_id: SectorId) -> Result<(), String> {
+        let from_sector = sector::get_sector(self.sector_id).unwrap();
+        if !from_sector.sector_links.contains(&to_sector_id) {
+            return Err(format!("Sector {} is not adjacent to sector {}", to_sector_id, self.sector_id));
+        }
+
+        let fuel_cost = self.hull_class.fuel_per_move();
+        if self.fuel < fuel_cost {
+            return Err(format!("Insufficient fuel - {} required, {} remaining", fuel_cost, self.fuel));
+        }
+        Ok(())
+    }
+
+    /// The root sector of the galaxy the ship is in
+    pub fn get_root_sector_id(&self) -> Result<SectorId, String> {
+        galaxy::get_galaxy(self.galaxy_id)
+            .and_then(|galaxy| galaxy.get_root_sector_id())
+            .ok_or(format!("Galaxy {} has no root sector", self.galaxy_id))
+    }
+
+    /// Deals with a ship whose hull has been destroyed: its cargo and colonists are lost,
+    /// and it is towed to the root sector of its galaxy with a patched-up hull.
+    pub fn wreck(&mut self) {
+        self.holds.clear();
+        self.colonists = 0;
+        if let Ok(root_sector_id) = self.get_root_sector_id() {
+            self.sector_id = root_sector_id;
+        }
+        self.hull = (self.hull_class.max_hull() * WRECK_HULL_PERCENT / 100).max(1);
+    }
+
+    /// Creates a vector of strings to be sent to a user, describing the ship
+    pub fn get_description(&self) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        result.push(format!("Ship {} ({} class) in sector {}", self.ship_id, self.hull_class.name(), self.sector_id));
+        result.push(format!("  Fuel: {}/{}", self.fuel, self.hull_class.fuel_capacity()));
+        result.push(format!("  Credits: {}", self.credits));
+        result.push(format!("  Fighters: {}/{}", self.fighters, self.hull_class.max_fighters()));
+        result.push(format!("  Shields: {}/{}", self.shields, self.hull_class.max_shields()));
+        result.push(format!("  Hull: {}/{}", self.hull, self.hull_class.max_hull()));
+        result.push(format!("  Orders when attacked: {}", self.combat_order.name()));
+        result.push(format!("  Holds: {} free of {}", self.get_free_holds(), self.hull_class.hold_capacity()));
+        for commodity in Commodity::ALL {
+            let quantity = self.get_cargo(commodity);
+            if quantity > 0 {
+                result.push(format!("    {:<10} {:>5}", commodity.name(), quantity));
+            }
+        }
+        if self.colonists > 0 {
+            result.push(format!("    {:<10} {:>5} ({} holds)", "Colonists", self.colonists, self.get_colonist_holds()));
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> Value {
+        let holds: Vec<Value> = Commodity::ALL.iter()
+            .map(|commodity| json!({"commodity": commodity.name(), "quantity": self.get_cargo(*commodity)}))
+            .collect();
+        json!({
+            "ship_id": self.ship_id,
+            "owner_user_id": self.owner_user_id,
+            "galaxy_id": self.galaxy_id,
+            "sector_id": self.sector_id,
+            "hull_class": self.hull_class.name(),
+            "fuel": self.fuel,
+            "fuel_capacity": self.hull_class.fuel_capacity(),
+            "credits": self.credits,
+            "hold_capacity": self.hull_class.hold_capacity(),
+            "holds": holds,
+            "colonists": self.colonists,
+            "fighters": self.fighters,
+            "max_fighters": self.hull_class.max_fighters(),
+            "shields": self.shields,
+            "max_shields": self.hull_class.max_shields(),
+            "hull": self.hull,
+            "max_hull": self.hull_class.max_hull(),
+            "combat_order": self.combat_order.name(),
+        })
+    }
+
+    /// Writes information about this ship to the database.
+    /// To be used when the ship is first created.
+    pub fn persist(&self, database: &Connection) -> Result<(), String> {
+        match || -> rusqlite::Result<()> {
+            let statement = "INSERT INTO ships (shipId, ownerUserId, galaxyId, sectorId, hullClass, fuel, credits, colonists, \
+                            fighters, shields, hull, combatOrder) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12);";
+            let params = params![self.ship_id, self.owner_user_id, self.galaxy_id, self.sector_id,
+                self.hull_class.code(), self.fuel, self.credits, self.colonists, self.fighters, self.shields,
+                self.hull, self.combat_order.code()];
+            database.execute(statement, params)?;
+            self.persist_cargo(database)
+        }() {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!("Cannot persist ship:{}", e)),
+        }
+    }
+
+    // Rewrites the cargo rows for this ship
+    fn persist_cargo(&self, database: &Connection) -> rusqlite::Result<()> {
+        database.execute("DELETE FROM ship_cargo WHERE shipId = ?1;", params![self.ship_id])?;
+        for (commodity, quantity) in self.holds.iter() {
+            if *quantity > 0 {
+                let statement = "INSERT INTO ship_cargo (shipId, commodity, quantity) VALUES (?1, ?2, ?3);";
+                database.execute(statement, params![self.ship_id, commodity.code(), quantity])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the mutable state of an existing ship to the database.
+    /// See update_ship() and publish_ship().
+    pub fn update(&self, database: &Connection) -> Result<(), String> {
+        match || -> rusqlite::Result<()> {
+            let statement = "UPDATE ships SET galaxyId = ?2, sectorId = ?3, hullClass = ?4, fuel = ?5, credits = ?6, \
+                            colonists = ?7, fighters = ?8, shields = ?9, hull = ?10, combatOrder = ?11 WHERE shipId = ?1;";
+            let params = params![self.ship_id, self.galaxy_id, self.sector_id, self.hull_class.code(),
+                self.fuel, self.credits, self.colonists, self.fighters, self.shields, self.hull, self.combat_order.code()];
+            database.execute(statement, params)?;
+            self.persist_cargo(database)
+        }() {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!("Cannot update ship:{}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+
+    // Creates a small galaxy, and a user whose ship is in the root sector with an empty fuel tank.
+    // Returns the user, and the sector to which the root sector's lowest link leads.
+    fn strand_ship(database: &Connection) -> (UserId, SectorId) {
+        let galaxy_id = galaxy::create_conventional_galaxy(database, "Testing".to_string(), 20, 15, 10, Some(7)).unwrap();
+        let user_id = user::create_normal_user(database, "stranded".to_string(), "x".to_string(), "Stranded".to_string()).unwrap();
+        let root_sector_id = galaxy::get_galaxy(galaxy_id).unwrap().get_root_sector_id().unwrap();
+        let next_sector_id = *sector::get_sector(root_sector_id).unwrap().sector_links.iter().min().unwrap();
+
+        let mut ship = get_ship_for_user(user_id).unwrap();
+        ship.fuel = 0;
+        update_ship(database, &ship).unwrap();
+        publish_ship(ship);
+        (user_id, next_sector_id)
+    }
+
+    #[test]
+    fn ship_without_fuel_can_buy_fuel_and_move_again() {
+        let (_game, mut database) = testing::create_game();
+        let (user_id, next_sector_id) = strand_ship(&database);
+        assert!(move_ship(&mut database, user_id, next_sector_id).is_err());
+
+        let ship = refuel_ship(&mut database, user_id).unwrap();
+        let capacity = ship.hull_class.fuel_capacity();
+        assert_eq!(ship.fuel, capacity);
+        assert_eq!(ship.credits, STARTING_CREDITS - capacity as i64 * FUEL_PRICE);
+
+        move_ship(&mut database, user_id, next_sector_id).unwrap();
+        let ship = get_ship_for_user(user_id).unwrap();
+        assert_eq!(ship.sector_id, next_sector_id);
+        assert_eq!(ship.fuel, capacity - ship.hull_class.fuel_per_move());
+    }
+
+    #[test]
+    fn ship_away_from_the_root_sector_refines_fuel_ore() {
+        let (_game, mut database) = testing::create_game();
+        let (user_id, next_sector_id) = strand_ship(&database);
+        let mut ship = get_ship_for_user(user_id).unwrap();
+        ship.sector_id = next_sector_id;
+        update_ship(&database, &ship).unwrap();
+        publish_ship(ship);
+        assert!(refuel_ship(&mut database, user_id).is_err());
+
+        let mut ship = get_ship_for_user(user_id).unwrap();
+        ship.holds.insert(Commodity::FuelOre, 3);
+        update_ship(&database, &ship).unwrap();
+        publish_ship(ship);
+
+        let ship = refuel_ship(&mut database, user_id).unwrap();
+        assert_eq!(ship.fuel, 3 * FUEL_PER_ORE);
+        assert_eq!(ship.get_cargo(Commodity::FuelOre), 0);
+        assert_eq!(ship.credits, STARTING_CREDITS);
+        let to_sector_id = *sector::get_sector(next_sector_id).unwrap().sector_links.iter().min().unwrap();
+        move_ship(&mut database, user_id, to_sector_id).unwrap();
+        assert_eq!(get_ship_for_user(user_id).unwrap().fuel, 3 * FUEL_PER_ORE - ship.hull_class.fuel_per_move());
+    }
+}
@@ -0,0 +1,145 @@
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use crate::commodity::Commodity;
+use crate::port::TradeRole;
+use crate::user::UserId;
+use crate::{port, sector, ship, user};
+
+const TRADE_REQUEST_COST: i32 = 1;
+
+/// Describes a completed trade, from the point of view of the user
+pub struct TradeReceipt {
+    pub commodity: Commodity,
+    pub quantity: i32,
+    pub unit_price: i32,
+    pub total_price: i64,
+    pub is_purchase: bool,
+    pub credits_remaining: i64,
+}
+
+/// Buys units of a commodity from the port in the sector where the user's ship is located.
+/// The port must be selling the commodity, and the ship must have the credits and the free holds
+/// to accommodate the purchase.
+///
+/// # Returns
+/// A receipt describing the completed trade
+pub fn buy_from_port(database: &mut Connection,
+                     user_id: UserId,
+                     commodity: Commodity,
+                     quantity: i32) -> Result<TradeReceipt, String> {
+    execute_trade(database, user_id, commodity, quantity, TradeRole::Selling)
+}
+
+/// Sells units of a commodity from the user's ship to the port in the sector where the ship is located.
+/// The port must be buying the commodity, and the ship must be carrying the units to be sold.
+///
+/// # Returns
+/// A receipt describing the completed trade
+pub fn sell_to_port(database: &mut Connection,
+                    user_id: UserId,
+                    commodity: Commodity,
+                    quantity: i32) -> Result<TradeReceipt, String> {
+    execute_trade(database, user_id, commodity, quantity, TradeRole::Buying)
+}
+
+// Common code for buying and selling.
+// port_role is the role the port must have for the commodity - Selling if the user is buying, and vice versa.
+// Everything is verified up front; the database changes are then made in a single transaction.
+fn execute_trade(database: &mut Connection,
+                 user_id: UserId,
+                 commodity: Commodity,
+                 quantity: i32,
+                 port_role: TradeRole) -> Result<TradeReceipt, String> {
+    if quantity <= 0 {
+        return Err("Quantity must be greater than zero".to_string());
+    }
+
+    let mut ship = match ship::get_ship_for_user(user_id) {
+        Some(ship) => ship,
+        None => { return Err("You do not have a ship".to_string()); },
+    };
+
+    let port = match sector::get_sector(ship.sector_id).unwrap().port_id.and_then(port::get_port) {
+        Some(port) => port,
+        None => { return Err(format!("There is no port in sector {}", ship.sector_id)); },
+    };
+
+    let pc = match port.get_commodity(commodity) {
+        Some(pc) => pc,
+        None => { return Err(format!("{} does not trade in {}", port.port_name, commodity.name())); },
+    };
+
+    if pc.role != port_role {
+        return match port_role {
+            TradeRole::Selling => Err(format!("{} is not selling {}", port.port_name, commodity.name())),
+            TradeRole::Buying => Err(format!("{} is not buying {}", port.port_name, commodity.name())),
+        };
+    }
+
+    if quantity > pc.quantity {
+        return Err(format!("{} will only trade {} units of {}", port.port_name, pc.quantity, commodity.name()));
+    }
+
+    let unit_price = pc.current_price();
+    let total_price = unit_price as i64 * quantity as i64;
+    match port_role {
+        TradeRole::Selling => {
+            if quantity > ship.get_free_holds() {
+                return Err(format!("You only have {} free holds", ship.get_free_holds()));
+            } else if total_price > ship.credits {
+                return Err(format!("{} units cost {} credits, but you only have {}", quantity, total_price, ship.credits));
+            }
+            ship.credits -= total_price;
+            ship.holds.insert(commodity, ship.get_cargo(commodity) + quantity);
+        },
+        TradeRole::Buying => {
+            if quantity > ship.get_cargo(commodity) {
+                return Err(format!("You are only carrying {} units of {}", ship.get_cargo(commodity), commodity.name()));
+            }
+            ship.credits += total_price;
+            ship.holds.insert(commodity, ship.get_cargo(commodity) - quantity);
+        },
+    }
+
+    let transaction = database.transaction().map_err(|e| format!("Cannot trade:{}", e))?;
+    let requests_remaining = user::consume_requests(&transaction, user_id, TRADE_REQUEST_COST)?;
+    let port_quantity = port::record_trade(&transaction, port.port_id, ship.ship_id, commodity, quantity, unit_price)?;
+    ship::update_ship(&transaction, &ship)?;
+    transaction.commit().map_err(|e| format!("Cannot trade:{}", e))?;
+
+    // Only now that the changes are committed do we publish them to our in-memory copies
+    user::publish_requests_remaining(user_id, requests_remaining);
+    port::publish_quantity(port.port_id, commodity, port_quantity);
+    ship::publish_ship(ship.clone());
+
+    Ok(TradeReceipt {
+        commodity,
+        quantity,
+        unit_price,
+        total_price,
+        is_purchase: port_role == TradeRole::Selling,
+        credits_remaining: ship.credits })
+}
+
+impl TradeReceipt {
+    /// Creates a vector of strings to be sent to a user, describing the trade
+    pub fn get_description(&self) -> Vec<String> {
+        let verb = if self.is_purchase { "Bought" } else { "Sold" };
+        vec![
+            format!("{} {} units of {} at {} credits each, for {} credits",
+                    verb, self.quantity, self.commodity.name(), self.unit_price, self.total_price),
+            format!("Credits remaining: {}", self.credits_remaining),
+        ]
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "commodity": self.commodity.name(),
+            "quantity": self.quantity,
+            "unit_price": self.unit_price,
+            "total_price": self.total_price,
+            "is_purchase": self.is_purchase,
+            "credits_remaining": self.credits_remaining,
+        })
+    }
+}
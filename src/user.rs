@@ -1,15 +1,24 @@
 use std::collections::{HashMap};
 use std::sync::{LazyLock, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use rand::Rng;
 use rusqlite::{params, Connection};
+use serde_json::{json, Value};
+use crate::{config, database, galaxy, ship};
+use crate::{log_error, log_info};
+use crate::action::Actor;
 
 pub type UserId = usize;
 
 pub const ADMIN_USER_ID: UserId = 1;
 const ADMIN_USER_NAME: &'static str  = "Admin";
-const ADMIN_PASSWORD: &'static str = "admin";
+pub const DEFAULT_ADMIN_PASSWORD: &str = "admin";
 const ADMIN_GAME_NAME: &'static str  = "Cosmic Overlord";
-const DEFAULT_REQUESTS_PER_DAY: i32 = 150;
+pub const DEFAULT_REQUESTS_PER_DAY: i32 = 150;
+const PASSWORD_HASH_PREFIX: &str = "$argon2";
+const SALT_LENGTH: usize = 16;
 
 static NEXT_USER_ID: LazyLock<Mutex<UserId>> = LazyLock::new(|| Mutex::new(1));
 static USERS: LazyLock<Mutex<HashMap<UserId, User>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
@@ -21,6 +30,7 @@ pub enum ValidationResult {
     UserIsDisabled,
 }
 
+/// A user of the game. user_password holds a salted argon2 hash (in PHC string format), never the password itself.
 pub struct User {
     pub user_id: UserId,
     pub user_name: String,
@@ -32,6 +42,63 @@ pub struct User {
     pub requests_remaining: Option<i32> // None if the above is None
 }
 
+/// Deducts from the number of requests the user has remaining for today, in the database only.
+/// Users with no daily limit are unaffected.
+/// Fails without deducting anything if the user does not have enough requests remaining.
+///
+/// # Returns
+/// The number of requests remaining, which the caller passes to publish_requests_remaining()
+/// once its transaction is committed. None if the user has no daily limit.
+pub fn consume_requests(database: &Connection, user_id: UserId, count: i32) -> Result<Option<i32>, String> {
+    let remaining = match USERS.lock().unwrap().get(&user_id) {
+        Some(user) => user.requests_remaining,
+        None => { return Err(format!("No such user {}", user_id)); },
+    };
+
+    let remaining = match remaining {
+        Some(remaining) => remaining,
+        None => { return Ok(None); },
+    };
+
+    if remaining < count {
+        return Err("You have no requests remaining for today".to_string());
+    }
+
+    let statement = "UPDATE users SET requestsRemaining = ?2 WHERE userId = ?1;";
+    match database.execute(statement, params![user_id, remaining - count]) {
+        Ok(_) => Ok(Some(remaining - count)),
+        Err(e) => Err(format!("Cannot update user:{}", e)),
+    }
+}
+
+/// Updates our in-memory copy of the number of requests the user has remaining, as returned by
+/// consume_requests() - to be called only after the transaction in which they were consumed is committed.
+pub fn publish_requests_remaining(user_id: UserId, requests_remaining: Option<i32>) {
+    if requests_remaining.is_none() {
+        return;
+    }
+    if let Some(user) = USERS.lock().unwrap().get_mut(&user_id) {
+        user.requests_remaining = requests_remaining;
+    }
+}
+
+/// Daily actor which restores every user's daily request allowance
+pub struct RequestResetActor;
+
+/// Resets requests_remaining to requests_per_day for every user who has a daily limit.
+pub fn reset_daily_requests(database: &Connection) -> Result<(), String> {
+    let statement = "UPDATE users SET requestsRemaining = requestsPerDay WHERE requestsPerDay IS NOT NULL;";
+    match database.execute(statement, params![]) {
+        Ok(_) => {
+            for user in USERS.lock().unwrap().values_mut() {
+                user.requests_remaining = user.requests_per_day;
+            }
+            Ok(())
+        },
+        Err(e) => Err(format!("Cannot reset daily requests:{}", e)),
+    }
+}
+
 pub fn create_admin_user(database: &Connection) -> Result<UserId, String> {
     let mut next_user_id = NEXT_USER_ID.lock().unwrap();
     if *next_user_id >= ADMIN_USER_ID {
@@ -41,7 +108,7 @@ pub fn create_admin_user(database: &Connection) -> Result<UserId, String> {
     create_user(database,
                 ADMIN_USER_ID,
                 ADMIN_USER_NAME.to_string(),
-                ADMIN_PASSWORD.to_string(),
+                config::get_config().users.admin_password,
                 ADMIN_GAME_NAME.to_string(),
                 None)
 }
@@ -50,10 +117,22 @@ pub fn create_normal_user(database: &Connection,
                           user_name: String,
                           password: String,
                           game_name: String) -> Result<UserId, String> {
+    // Every normal user gets a ship, which starts out in the root sector of the starting galaxy.
+    let galaxy = match galaxy::get_starting_galaxy_id().and_then(galaxy::get_galaxy) {
+        Some(galaxy) => galaxy,
+        None => { return Err("No galaxy exists in which to place the new user's ship".to_string()); },
+    };
+
     let mut next_user_id = NEXT_USER_ID.lock().unwrap();
     let user_id = *next_user_id;
     *next_user_id += 1;
-    create_user(database, user_id, user_name, password, game_name, Some(DEFAULT_REQUESTS_PER_DAY))
+    create_user(database, user_id, user_name, password, game_name, Some(config::get_config().users.requests_per_day))?;
+    ship::create_ship(database,
+                      user_id,
+                      galaxy.get_galaxy_id(),
+                      galaxy.get_root_sector_id().ok_or("The starting galaxy has no sectors".to_string())?,
+                      ship::STARTING_HULL_CLASS)?;
+    Ok(user_id)
 }
 
 fn create_user(database: &Connection,
@@ -69,7 +148,7 @@ fn create_user(database: &Connection,
     let user = User {
         user_id,
         user_name,
-        user_password,
+        user_password: hash_password(&user_password)?,
         last_login_timestamp: None,
         game_name: user_game_name,
         is_disabled: false,
@@ -85,6 +164,33 @@ fn create_user(database: &Connection,
     Ok(user_id)
 }
 
+/// Finds a user by user name or by game name - either is case-insensitive.
+/// User names are unique, so a matching user name takes precedence; game names need not be unique,
+/// so a game name shared by several users is rejected as ambiguous rather than picking one of them.
+///
+/// # Returns
+/// The user, or None if there is no such user
+pub fn find_user_by_name(name: &str) -> Result<Option<User>, String> {
+    let name = name.trim();
+    let lowercase_name = name.to_lowercase();
+    let lock = USERS.lock().unwrap();
+    if let Some(user) = lock.values().find(|user| user.user_name.to_lowercase() == lowercase_name) {
+        return Ok(Some(user.clone()));
+    }
+
+    let mut matches: Vec<&User> = lock.values().filter(|user| user.game_name.to_lowercase() == lowercase_name).collect();
+    if matches.len() > 1 {
+        return Err(format!("{} users are known as {} - use a user name instead", matches.len(), name));
+    }
+    Ok(matches.pop().map(|user| user.clone()))
+}
+
+pub fn get_user_ids() -> Vec<UserId> {
+    let mut user_ids: Vec<UserId> = USERS.lock().unwrap().keys().copied().collect();
+    user_ids.sort();
+    user_ids
+}
+
 pub fn get_user(user_id: UserId) -> Option<User> {
     for user in USERS.lock().unwrap().values() {
         if user.user_id == user_id {
@@ -116,14 +222,14 @@ pub fn load_users(database: &Connection) -> Result<(), String> {
         for user_result in user_iter {
             let user = user_result?;
             highest_user_id = user.user_id;
-            println!("Loaded user {} {}", user.user_id, user.user_name);
+            log_info!("Loaded user {} {}", user.user_id, user.user_name);
             USERS.lock().unwrap().insert(user.user_id, user);
         }
 
         *NEXT_USER_ID.lock().unwrap() = highest_user_id + 1;
         Ok(())
     }() {
-        Ok(()) => Ok(()),
+        Ok(()) => migrate_plaintext_passwords(database),
         Err(e) => Err(format!("Cannot load users:{}", e)),
     }
 }
@@ -137,22 +243,75 @@ fn user_exists(user_name: &String) -> bool {
     false
 }
 
-pub fn validate_credentials(user_name: &String, password: &String) -> ValidationResult {
-    for user in USERS.lock().unwrap().values() {
-        if user_name.to_lowercase() == user.user_name.to_lowercase() {
-            if *password == user.user_password {
-                if user.is_disabled {
-                    return ValidationResult::UserIsDisabled;
-                } else {
-                    return ValidationResult::Success(user.user_id);
-                }
-            } else {
-                return ValidationResult::IncorrectPassword;
-            }
+/// Checks the given credentials against our users.
+/// The user lock is not held while the (deliberately slow) hash verification takes place.
+pub fn validate_credentials(user_name: &str, password: &str) -> ValidationResult {
+    let (user_id, password_hash, is_disabled) = {
+        let lock = USERS.lock().unwrap();
+        match lock.values().find(|user| user_name.to_lowercase() == user.user_name.to_lowercase()) {
+            Some(user) => (user.user_id, user.user_password.clone(), user.is_disabled),
+            None => { return ValidationResult::NoSuchUser; },
+        }
+    };
+
+    if !verify_password(password, &password_hash) {
+        ValidationResult::IncorrectPassword
+    } else if is_disabled {
+        ValidationResult::UserIsDisabled
+    } else {
+        ValidationResult::Success(user_id)
+    }
+}
+
+// Produces a salted hash of the given password, in PHC string format
+fn hash_password(password: &str) -> Result<String, String> {
+    let mut salt_bytes = [0u8; SALT_LENGTH];
+    rand::rng().fill(&mut salt_bytes);
+    let salt = SaltString::encode_b64(&salt_bytes).map_err(|e| format!("Cannot hash password:{}", e))?;
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(e) => Err(format!("Cannot hash password:{}", e)),
+    }
+}
+
+// Verifies a password against a stored hash - the comparison is constant-time.
+// A malformed hash never matches anything.
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(parsed_hash) => Argon2::default().verify_password(password.as_bytes(), &parsed_hash).is_ok(),
+        Err(_) => false,
+    }
+}
+
+// Replaces any plaintext passwords (as stored by older versions of the game) with hashes,
+// both in the database and in our user map.
+fn migrate_plaintext_passwords(database: &Connection) -> Result<(), String> {
+    let mut lock = USERS.lock().unwrap();
+    for user in lock.values_mut().filter(|user| !user.user_password.starts_with(PASSWORD_HASH_PREFIX)) {
+        let password_hash = hash_password(&user.user_password)?;
+        let statement = "UPDATE users SET password = ?2 WHERE userId = ?1;";
+        match database.execute(statement, params![user.user_id, password_hash]) {
+            Ok(_) => {
+                log_info!("Migrated password for user {} {}", user.user_id, user.user_name);
+                user.user_password = password_hash;
+            },
+            Err(e) => { return Err(format!("Cannot migrate password for user {}:{}", user.user_name, e)); },
+        }
+    }
+    Ok(())
+}
+
+impl Actor for RequestResetActor {
+    fn act(&self) {
+        match database::with_database(|database| reset_daily_requests(database)) {
+            Ok(()) => log_info!("Daily requests reset"),
+            Err(e) => log_error!("{}", e),
         }
     }
 
-    ValidationResult::NoSuchUser
+    fn is_finished(&self) -> bool {
+        false
+    }
 }
 
 impl User {
@@ -186,6 +345,21 @@ impl User {
             is_disabled, requests_per_day, requests_remaining}
     }
 
+    /// Creates a JSON representation of the user - this never includes the password
+    pub fn to_json(&self) -> Value {
+        let last_login = self.last_login_timestamp
+            .map(|timestamp| timestamp.duration_since(UNIX_EPOCH).unwrap().as_secs());
+        json!({
+            "user_id": self.user_id,
+            "user_name": self.user_name,
+            "game_name": self.game_name,
+            "last_login": last_login,
+            "is_disabled": self.is_disabled,
+            "requests_per_day": self.requests_per_day,
+            "requests_remaining": self.requests_remaining,
+        })
+    }
+
     pub fn persist(&self, database: &Connection) -> Result<(), String> {
         match || -> rusqlite::Result<()> {
             let statement = "INSERT INTO users \